@@ -0,0 +1,1249 @@
+use actix_web::{web, HttpResponse, Responder, get, post, put, delete, HttpRequest};
+use chrono::{Duration, NaiveDate, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::auth::{api_keys, generate_secret_token, hash_password, require_admin, require_staff, verify_token};
+use crate::auth::models::Role;
+use crate::analytics::models::Granularity;
+use crate::config::Config;
+use crate::db::DbPool;
+use crate::shelves;
+use crate::validation::{ValidJson, ValidQuery};
+use super::models::{
+    DashboardQuery, ExportFormat, BookReadership, TrendingBook, ActiveUsers,
+    BookCompletion, CategoryPopularity, DropOffBucket,
+    RoleSecurityPolicy, SecurityPolicyRequest,
+    ServiceAccount, CreateServiceAccountRequest, ApiKey, CreateApiKeyRequest, CreatedApiKey, API_KEY_COLUMNS,
+};
+
+// Range used when the caller gives no dates
+const DEFAULT_RANGE_DAYS: i64 = 30;
+
+const DEFAULT_LIMIT: i64 = 20;
+
+// Drop-off is reported in tenths of a book
+const DROP_OFF_BUCKETS: i32 = 10;
+
+// Requests per minute allowed to a key when none is given
+const DEFAULT_API_KEY_RATE_LIMIT: i32 = 60;
+
+#[get("/analytics/books/most-read")]
+pub async fn most_read_books(
+    req: HttpRequest,
+    query: ValidQuery<DashboardQuery>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    if let Err(response) = require_staff(&client, user_id).await {
+        return response;
+    }
+
+    let (from, to) = date_range(&query);
+
+    let result = client
+        .query(
+            "SELECT b.id, b.title, b.author,
+                    COUNT(DISTINCT a.user_id) AS readers,
+                    SUM(a.pages_read)::bigint AS pages_read,
+                    SUM(a.reading_time_seconds)::bigint AS reading_time_seconds
+             FROM user_analytics a
+             JOIN books b ON b.id = a.book_id
+             WHERE a.session_date BETWEEN $1 AND $2
+             GROUP BY b.id, b.title, b.author
+             ORDER BY readers DESC, reading_time_seconds DESC
+             LIMIT $3",
+            &[&from, &to, &limit(&query)],
+        )
+        .await;
+
+    match result {
+        Ok(rows) => {
+            let books: Vec<BookReadership> = rows
+                .iter()
+                .map(|row| BookReadership {
+                    book_id: row.get("id"),
+                    title: row.get("title"),
+                    author: row.get("author"),
+                    readers: row.get("readers"),
+                    pages_read: row.get("pages_read"),
+                    reading_time_seconds: row.get("reading_time_seconds"),
+                })
+                .collect();
+
+            respond(&books, &query, "most-read-books")
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error fetching most read books")
+        }
+    }
+}
+
+#[get("/analytics/books/trending")]
+pub async fn trending_books(
+    req: HttpRequest,
+    query: ValidQuery<DashboardQuery>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    if let Err(response) = require_staff(&client, user_id).await {
+        return response;
+    }
+
+    let days = query.days.unwrap_or(7);
+
+    // Readers in the last `days` days against the `days` days before that
+    let result = client
+        .query(
+            "SELECT id, title, author, recent_readers, previous_readers,
+                    recent_readers - previous_readers AS growth
+             FROM (
+                 SELECT b.id, b.title, b.author,
+                        COUNT(DISTINCT a.user_id) FILTER (WHERE a.session_date > CURRENT_DATE - $1::int) AS recent_readers,
+                        COUNT(DISTINCT a.user_id) FILTER (WHERE a.session_date <= CURRENT_DATE - $1::int) AS previous_readers
+                 FROM user_analytics a
+                 JOIN books b ON b.id = a.book_id
+                 WHERE a.session_date > CURRENT_DATE - 2 * $1::int
+                 GROUP BY b.id, b.title, b.author
+             ) windows
+             WHERE recent_readers > 0
+             ORDER BY growth DESC, recent_readers DESC
+             LIMIT $2",
+            &[&days, &limit(&query)],
+        )
+        .await;
+
+    match result {
+        Ok(rows) => {
+            let books: Vec<TrendingBook> = rows
+                .iter()
+                .map(|row| TrendingBook {
+                    book_id: row.get("id"),
+                    title: row.get("title"),
+                    author: row.get("author"),
+                    recent_readers: row.get("recent_readers"),
+                    previous_readers: row.get("previous_readers"),
+                    growth: row.get("growth"),
+                })
+                .collect();
+
+            respond(&books, &query, "trending-books")
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error fetching trending books")
+        }
+    }
+}
+
+#[get("/analytics/active-users")]
+pub async fn active_users(
+    req: HttpRequest,
+    query: ValidQuery<DashboardQuery>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    if let Err(response) = require_staff(&client, user_id).await {
+        return response;
+    }
+
+    let (from, to) = date_range(&query);
+    let granularity = query.granularity.unwrap_or(Granularity::Day);
+
+    let result = client
+        .query(
+            "SELECT date_trunc($1, session_date::timestamp)::date AS period_start,
+                    COUNT(DISTINCT user_id) AS active_users,
+                    SUM(reading_time_seconds)::bigint AS reading_time_seconds
+             FROM user_analytics
+             WHERE session_date BETWEEN $2 AND $3
+             GROUP BY period_start
+             ORDER BY period_start",
+            &[&granularity.as_str(), &from, &to],
+        )
+        .await;
+
+    match result {
+        Ok(rows) => {
+            let periods: Vec<ActiveUsers> = rows
+                .iter()
+                .map(|row| ActiveUsers {
+                    period_start: row.get("period_start"),
+                    active_users: row.get("active_users"),
+                    reading_time_seconds: row.get("reading_time_seconds"),
+                })
+                .collect();
+
+            respond(&periods, &query, "active-users")
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error fetching active users")
+        }
+    }
+}
+
+#[get("/analytics/books/completion")]
+pub async fn completion_rates(
+    req: HttpRequest,
+    query: ValidQuery<DashboardQuery>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    if let Err(response) = require_staff(&client, user_id).await {
+        return response;
+    }
+
+    let min_readers = query.min_readers.unwrap_or(1);
+
+    let result = client
+        .query(
+            "SELECT b.id, b.title, b.author,
+                    COUNT(*) AS readers,
+                    COUNT(*) FILTER (WHERE p.completed) AS completed,
+                    (COUNT(*) FILTER (WHERE p.completed))::float8 / COUNT(*) AS completion_rate
+             FROM user_reading_progress p
+             JOIN books b ON b.id = p.book_id
+             GROUP BY b.id, b.title, b.author
+             HAVING COUNT(*) >= $1
+             ORDER BY completion_rate DESC, readers DESC
+             LIMIT $2",
+            &[&min_readers, &limit(&query)],
+        )
+        .await;
+
+    match result {
+        Ok(rows) => {
+            let books: Vec<BookCompletion> = rows
+                .iter()
+                .map(|row| BookCompletion {
+                    book_id: row.get("id"),
+                    title: row.get("title"),
+                    author: row.get("author"),
+                    readers: row.get("readers"),
+                    completed: row.get("completed"),
+                    completion_rate: row.get("completion_rate"),
+                })
+                .collect();
+
+            respond(&books, &query, "completion-rates")
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error fetching completion rates")
+        }
+    }
+}
+
+#[get("/analytics/categories")]
+pub async fn category_popularity(
+    req: HttpRequest,
+    query: ValidQuery<DashboardQuery>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    if let Err(response) = require_staff(&client, user_id).await {
+        return response;
+    }
+
+    let (from, to) = date_range(&query);
+
+    let result = client
+        .query(
+            "SELECT c.id, c.name,
+                    COUNT(DISTINCT a.user_id) AS readers,
+                    COUNT(DISTINCT a.book_id) AS books_read,
+                    SUM(a.pages_read)::bigint AS pages_read,
+                    SUM(a.reading_time_seconds)::bigint AS reading_time_seconds
+             FROM user_analytics a
+             JOIN books b ON b.id = a.book_id
+             JOIN categories c ON c.id = b.category_id
+             WHERE a.session_date BETWEEN $1 AND $2
+             GROUP BY c.id, c.name
+             ORDER BY readers DESC, reading_time_seconds DESC
+             LIMIT $3",
+            &[&from, &to, &limit(&query)],
+        )
+        .await;
+
+    match result {
+        Ok(rows) => {
+            let categories: Vec<CategoryPopularity> = rows
+                .iter()
+                .map(|row| CategoryPopularity {
+                    category_id: row.get("id"),
+                    category_name: row.get("name"),
+                    readers: row.get("readers"),
+                    books_read: row.get("books_read"),
+                    pages_read: row.get("pages_read"),
+                    reading_time_seconds: row.get("reading_time_seconds"),
+                })
+                .collect();
+
+            respond(&categories, &query, "category-popularity")
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error fetching category popularity")
+        }
+    }
+}
+
+#[get("/analytics/books/{id}/drop-off")]
+pub async fn drop_off(
+    req: HttpRequest,
+    path: web::Path<(String,)>,
+    query: ValidQuery<DashboardQuery>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let book_id = match Uuid::parse_str(&path.0) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().json("Invalid book ID"),
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    if let Err(response) = require_staff(&client, user_id).await {
+        return response;
+    }
+
+    let total_pages: i32 = match client
+        .query_opt("SELECT total_pages FROM books WHERE id = $1", &[&book_id])
+        .await
+    {
+        Ok(Some(row)) => row.get("total_pages"),
+        Ok(None) => return HttpResponse::NotFound().json("Book not found"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Database error");
+        }
+    };
+
+    // Where readers who haven't finished the book stopped, as a share of the pages
+    // their reader reported (page counts differ between devices for reflowable formats)
+    let result = client
+        .query(
+            "SELECT LEAST(GREATEST(width_bucket(p.current_page::float8 / p.total_pages, 0, 1, $2), 1), $2) AS bucket,
+                    COUNT(*) AS readers
+             FROM user_reading_progress p
+             WHERE p.book_id = $1 AND NOT p.completed AND p.total_pages > 0
+             GROUP BY bucket",
+            &[&book_id, &DROP_OFF_BUCKETS],
+        )
+        .await;
+
+    let rows = match result {
+        Ok(rows) => rows,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Error fetching drop-off distribution");
+        }
+    };
+
+    let mut buckets: Vec<DropOffBucket> = (1..=DROP_OFF_BUCKETS)
+        .map(|bucket| DropOffBucket {
+            from_percent: (bucket - 1) * 100 / DROP_OFF_BUCKETS,
+            to_percent: bucket * 100 / DROP_OFF_BUCKETS,
+            from_page: (bucket - 1) * total_pages / DROP_OFF_BUCKETS + 1,
+            to_page: bucket * total_pages / DROP_OFF_BUCKETS,
+            readers: 0,
+        })
+        .collect();
+
+    for row in &rows {
+        let bucket: i32 = row.get("bucket");
+        buckets[(bucket - 1) as usize].readers = row.get("readers");
+    }
+
+    respond(&buckets, &query, "drop-off")
+}
+
+#[get("/security/policies")]
+pub async fn get_security_policies(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    if let Err(response) = require_admin(&client, user_id).await {
+        return response;
+    }
+
+    let result = client
+        .query(
+            "SELECT role, require_mfa, updated_by, updated_at FROM role_security_policies ORDER BY role",
+            &[],
+        )
+        .await;
+
+    match result {
+        Ok(rows) => {
+            let policies: Vec<RoleSecurityPolicy> = rows
+                .iter()
+                .map(|row| RoleSecurityPolicy {
+                    role: row.get::<_, String>("role").parse().unwrap_or_default(),
+                    require_mfa: row.get("require_mfa"),
+                    updated_by: row.get("updated_by"),
+                    updated_at: row.get("updated_at"),
+                })
+                .collect();
+            HttpResponse::Ok().json(policies)
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error fetching security policies")
+        }
+    }
+}
+
+// Takes effect at each user's next login; users of the role without 2FA are then made to enroll
+#[put("/security/policies/{role}")]
+pub async fn update_security_policy(
+    req: HttpRequest,
+    path: web::Path<(String,)>,
+    body: ValidJson<SecurityPolicyRequest>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let role: Role = match path.0.parse() {
+        Ok(role) => role,
+        Err(message) => return HttpResponse::BadRequest().json(message),
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    if let Err(response) = require_admin(&client, user_id).await {
+        return response;
+    }
+
+    let result = client
+        .query_one(
+            "INSERT INTO role_security_policies (role, require_mfa, updated_by)
+             VALUES ($1, $2, $3)
+             ON CONFLICT (role)
+             DO UPDATE SET require_mfa = EXCLUDED.require_mfa, updated_by = EXCLUDED.updated_by,
+                 updated_at = CURRENT_TIMESTAMP
+             RETURNING require_mfa, updated_by, updated_at",
+            &[&role.to_string(), &body.require_mfa, &user_id],
+        )
+        .await;
+
+    match result {
+        Ok(row) => HttpResponse::Ok().json(RoleSecurityPolicy {
+            role,
+            require_mfa: row.get("require_mfa"),
+            updated_by: row.get("updated_by"),
+            updated_at: row.get("updated_at"),
+        }),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error updating security policy")
+        }
+    }
+}
+
+#[get("/service-accounts")]
+pub async fn list_service_accounts(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    if let Err(response) = require_admin(&client, user_id).await {
+        return response;
+    }
+
+    let result = client
+        .query(
+            "SELECT u.id, u.username, u.role, u.created_at,
+                    (SELECT COUNT(*) FROM api_keys k
+                     WHERE k.user_id = u.id AND k.revoked_at IS NULL
+                       AND (k.expires_at IS NULL OR k.expires_at > CURRENT_TIMESTAMP)) AS active_keys
+             FROM users u
+             WHERE u.is_service_account
+             ORDER BY u.username",
+            &[],
+        )
+        .await;
+
+    match result {
+        Ok(rows) => {
+            let accounts: Vec<ServiceAccount> = rows.iter().map(service_account_from_row).collect();
+            HttpResponse::Ok().json(accounts)
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error fetching service accounts")
+        }
+    }
+}
+
+#[post("/service-accounts")]
+pub async fn create_service_account(
+    req: HttpRequest,
+    body: ValidJson<CreateServiceAccountRequest>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let name = body.name.trim().to_string();
+    let role = body.role.unwrap_or_default();
+
+    let mut client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    if let Err(response) = require_admin(&client, user_id).await {
+        return response;
+    }
+
+    // Nobody knows this password; service accounts are refused at login anyway
+    let password_hash = match hash_password(&generate_secret_token(), &config) {
+        Ok(hash) => hash,
+        Err(_) => return HttpResponse::InternalServerError().json("Password hashing error"),
+    };
+
+    let tx = match client.transaction().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let account_id = Uuid::new_v4();
+    let result = async {
+        let taken: bool = tx
+            .query_one("SELECT EXISTS (SELECT 1 FROM users WHERE LOWER(username) = LOWER($1))", &[&name])
+            .await?
+            .get(0);
+        if taken {
+            return Ok(None);
+        }
+
+        // Users need a unique email; the reserved .invalid domain can never receive mail
+        let row = tx
+            .query_one(
+                "INSERT INTO users (id, username, email, password_hash, role, email_verified_at, is_service_account)
+                 VALUES ($1, $2, $3, $4, $5, CURRENT_TIMESTAMP, TRUE)
+                 RETURNING id, username, role, created_at, 0::bigint AS active_keys",
+                &[
+                    &account_id,
+                    &name,
+                    &format!("{}@service-accounts.invalid", account_id),
+                    &password_hash,
+                    &role.to_string(),
+                ],
+            )
+            .await?;
+        shelves::create_default_shelves(&tx, account_id).await?;
+        tx.commit().await?;
+        Ok::<_, tokio_postgres::Error>(Some(service_account_from_row(&row)))
+    }
+    .await;
+
+    match result {
+        Ok(Some(account)) => HttpResponse::Created().json(account),
+        Ok(None) => HttpResponse::Conflict().json("Username is already taken"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error creating service account")
+        }
+    }
+}
+
+// Its keys go with it
+#[delete("/service-accounts/{id}")]
+pub async fn delete_service_account(
+    req: HttpRequest,
+    path: web::Path<(String,)>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let account_id = match Uuid::parse_str(&path.0) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().json("Invalid service account ID"),
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    if let Err(response) = require_admin(&client, user_id).await {
+        return response;
+    }
+
+    match client
+        .execute("DELETE FROM users WHERE id = $1 AND is_service_account", &[&account_id])
+        .await
+    {
+        Ok(0) => HttpResponse::NotFound().json("Service account not found"),
+        Ok(_) => HttpResponse::NoContent().finish(),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error deleting service account")
+        }
+    }
+}
+
+#[get("/service-accounts/{id}/keys")]
+pub async fn list_api_keys(
+    req: HttpRequest,
+    path: web::Path<(String,)>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let account_id = match Uuid::parse_str(&path.0) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().json("Invalid service account ID"),
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    if let Err(response) = require_admin(&client, user_id).await {
+        return response;
+    }
+
+    let result = client
+        .query(
+            &format!(
+                "SELECT {} FROM api_keys WHERE user_id = $1 ORDER BY created_at DESC",
+                API_KEY_COLUMNS
+            ),
+            &[&account_id],
+        )
+        .await;
+
+    match result {
+        Ok(rows) => {
+            let keys: Vec<ApiKey> = rows.iter().map(ApiKey::from_row).collect();
+            HttpResponse::Ok().json(keys)
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error fetching API keys")
+        }
+    }
+}
+
+#[post("/service-accounts/{id}/keys")]
+pub async fn create_api_key(
+    req: HttpRequest,
+    path: web::Path<(String,)>,
+    body: ValidJson<CreateApiKeyRequest>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let account_id = match Uuid::parse_str(&path.0) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().json("Invalid service account ID"),
+    };
+
+    let name = body.name.trim().to_string();
+    let rate_limit = body.rate_limit_per_minute.unwrap_or(DEFAULT_API_KEY_RATE_LIMIT);
+    let expires_at = body.expires_in_days.map(|days| Utc::now() + Duration::days(days));
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    if let Err(response) = require_admin(&client, user_id).await {
+        return response;
+    }
+
+    let mut scopes = body.scopes.clone();
+    scopes.sort();
+    scopes.dedup();
+
+    let generated = api_keys::generate();
+    let result = client
+        .query_opt(
+            &format!(
+                "INSERT INTO api_keys (id, user_id, name, prefix, key_hash, scopes, rate_limit_per_minute, expires_at, created_by)
+                 SELECT $1, id, $3, $4, $5, $6, $7, $8, $9
+                 FROM users WHERE id = $2 AND is_service_account
+                 RETURNING {}",
+                API_KEY_COLUMNS
+            ),
+            &[
+                &Uuid::new_v4(),
+                &account_id,
+                &name,
+                &generated.prefix,
+                &generated.hash,
+                &scopes,
+                &rate_limit,
+                &expires_at,
+                &user_id,
+            ],
+        )
+        .await;
+
+    match result {
+        Ok(Some(row)) => HttpResponse::Created().json(CreatedApiKey {
+            key: generated.key,
+            api_key: ApiKey::from_row(&row),
+        }),
+        Ok(None) => HttpResponse::NotFound().json("Service account not found"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error creating API key")
+        }
+    }
+}
+
+// Revoked keys are kept so their last use stays visible
+#[delete("/api-keys/{id}")]
+pub async fn revoke_api_key(
+    req: HttpRequest,
+    path: web::Path<(String,)>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let key_id = match Uuid::parse_str(&path.0) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().json("Invalid API key ID"),
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    if let Err(response) = require_admin(&client, user_id).await {
+        return response;
+    }
+
+    let result = client
+        .query_opt(
+            &format!(
+                "UPDATE api_keys SET revoked_at = COALESCE(revoked_at, CURRENT_TIMESTAMP)
+                 WHERE id = $1
+                 RETURNING {}",
+                API_KEY_COLUMNS
+            ),
+            &[&key_id],
+        )
+        .await;
+
+    match result {
+        Ok(Some(row)) => HttpResponse::Ok().json(ApiKey::from_row(&row)),
+        Ok(None) => HttpResponse::NotFound().json("API key not found"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error revoking API key")
+        }
+    }
+}
+
+fn date_range(query: &DashboardQuery) -> (NaiveDate, NaiveDate) {
+    let to = query.to.unwrap_or_else(|| Utc::now().date_naive());
+    let from = query.from.unwrap_or(to - Duration::days(DEFAULT_RANGE_DAYS - 1));
+    (from, to)
+}
+
+fn limit(query: &DashboardQuery) -> i64 {
+    query.limit.unwrap_or(DEFAULT_LIMIT)
+}
+
+// Sends the rows as JSON, or as a CSV download when `format=csv`
+fn respond<T: Serialize>(rows: &[T], query: &DashboardQuery, name: &str) -> HttpResponse {
+    if query.format.unwrap_or_default() == ExportFormat::Json {
+        return HttpResponse::Ok().json(rows);
+    }
+
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for row in rows {
+        if let Err(e) = writer.serialize(row) {
+            eprintln!("CSV error: {}", e);
+            return HttpResponse::InternalServerError().json("Error exporting CSV");
+        }
+    }
+
+    match writer.into_inner() {
+        Ok(body) => HttpResponse::Ok()
+            .content_type("text/csv; charset=utf-8")
+            .insert_header((
+                "Content-Disposition",
+                format!("attachment; filename=\"{}.csv\"", name),
+            ))
+            .body(body),
+        Err(e) => {
+            eprintln!("CSV error: {}", e);
+            HttpResponse::InternalServerError().json("Error exporting CSV")
+        }
+    }
+}
+
+fn service_account_from_row(row: &tokio_postgres::Row) -> ServiceAccount {
+    ServiceAccount {
+        id: row.get("id"),
+        name: row.get("username"),
+        role: row.get::<_, String>("role").parse().unwrap_or_default(),
+        active_keys: row.get("active_keys"),
+        created_at: row.get("created_at"),
+    }
+}
\ No newline at end of file
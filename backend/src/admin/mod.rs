@@ -0,0 +1,24 @@
+pub mod handlers;
+pub mod models;
+
+use actix_web::web;
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/admin")
+            .service(handlers::most_read_books)
+            .service(handlers::trending_books)
+            .service(handlers::active_users)
+            .service(handlers::completion_rates)
+            .service(handlers::category_popularity)
+            .service(handlers::drop_off)
+            .service(handlers::get_security_policies)
+            .service(handlers::update_security_policy)
+            .service(handlers::list_service_accounts)
+            .service(handlers::create_service_account)
+            .service(handlers::delete_service_account)
+            .service(handlers::list_api_keys)
+            .service(handlers::create_api_key)
+            .service(handlers::revoke_api_key)
+    );
+}
\ No newline at end of file
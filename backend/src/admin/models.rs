@@ -0,0 +1,215 @@
+use std::borrow::Cow;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::{Validate, ValidationError};
+
+use crate::analytics::models::Granularity;
+use crate::auth::api_keys;
+use crate::auth::models::Role;
+use crate::validation::{field_error, not_blank};
+
+// Most rows a dashboard returns at once
+const MAX_LIMIT: i64 = 1000;
+
+// Service account names double as usernames
+const MAX_SERVICE_ACCOUNT_NAME_LENGTH: u64 = 50;
+
+const MAX_API_KEY_NAME_LENGTH: u64 = 100;
+
+const MAX_API_KEY_RATE_LIMIT: i32 = 10_000;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+#[validate(schema(function = "from_not_after_to"))]
+pub struct DashboardQuery {
+    pub from: Option<NaiveDate>,
+    // Today when missing
+    pub to: Option<NaiveDate>,
+    #[validate(range(min = 1, max = MAX_LIMIT))]
+    pub limit: Option<i64>,
+    pub granularity: Option<Granularity>,
+    // Length of the window compared against the one before it, for trending books
+    #[validate(range(min = 1, max = 365))]
+    pub days: Option<i32>,
+    // Books with fewer readers than this are left out of completion rates
+    #[validate(range(min = 1))]
+    pub min_readers: Option<i64>,
+    pub format: Option<ExportFormat>,
+}
+
+fn from_not_after_to(query: &DashboardQuery) -> Result<(), ValidationError> {
+    let to = query.to.unwrap_or_else(|| Utc::now().date_naive());
+    if query.from.is_some_and(|from| from > to) {
+        return Err(field_error("from", "Must not be after `to`"));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+pub struct BookReadership {
+    pub book_id: Uuid,
+    pub title: String,
+    pub author: String,
+    pub readers: i64,
+    pub pages_read: i64,
+    pub reading_time_seconds: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TrendingBook {
+    pub book_id: Uuid,
+    pub title: String,
+    pub author: String,
+    pub recent_readers: i64,
+    pub previous_readers: i64,
+    pub growth: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ActiveUsers {
+    pub period_start: NaiveDate,
+    pub active_users: i64,
+    pub reading_time_seconds: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BookCompletion {
+    pub book_id: Uuid,
+    pub title: String,
+    pub author: String,
+    pub readers: i64,
+    pub completed: i64,
+    pub completion_rate: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CategoryPopularity {
+    pub category_id: Uuid,
+    pub category_name: String,
+    pub readers: i64,
+    pub books_read: i64,
+    pub pages_read: i64,
+    pub reading_time_seconds: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DropOffBucket {
+    // Share of the book reached, as a half-open percentage range
+    pub from_percent: i32,
+    pub to_percent: i32,
+    pub from_page: i32,
+    pub to_page: i32,
+    pub readers: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RoleSecurityPolicy {
+    pub role: Role,
+    pub require_mfa: bool,
+    pub updated_by: Option<Uuid>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct SecurityPolicyRequest {
+    pub require_mfa: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ServiceAccount {
+    pub id: Uuid,
+    pub name: String,
+    pub role: Role,
+    pub active_keys: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateServiceAccountRequest {
+    #[validate(custom(function = "not_blank"), length(max = MAX_SERVICE_ACCOUNT_NAME_LENGTH))]
+    pub name: String,
+    // Readers by default; librarians can also see the staff dashboards
+    #[validate(custom(function = "not_admin"))]
+    pub role: Option<Role>,
+}
+
+// Admin rights stay with people
+fn not_admin(role: &Role) -> Result<(), ValidationError> {
+    if *role == Role::Admin {
+        return Err(ValidationError::new("role").with_message(Cow::Borrowed("Service accounts cannot be admins")));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Vec<String>,
+    pub rate_limit_per_minute: i32,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub last_used_ip: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub created_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+// Columns read by `ApiKey::from_row`
+pub const API_KEY_COLUMNS: &str = "id, name, prefix, scopes, rate_limit_per_minute, last_used_at, last_used_ip, \
+                                   expires_at, revoked_at, created_by, created_at";
+
+impl ApiKey {
+    pub fn from_row(row: &tokio_postgres::Row) -> Self {
+        ApiKey {
+            id: row.get("id"),
+            name: row.get("name"),
+            prefix: row.get("prefix"),
+            scopes: row.get("scopes"),
+            rate_limit_per_minute: row.get("rate_limit_per_minute"),
+            last_used_at: row.get("last_used_at"),
+            last_used_ip: row.get("last_used_ip"),
+            expires_at: row.get("expires_at"),
+            revoked_at: row.get("revoked_at"),
+            created_by: row.get("created_by"),
+            created_at: row.get("created_at"),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateApiKeyRequest {
+    #[validate(custom(function = "not_blank"), length(max = MAX_API_KEY_NAME_LENGTH))]
+    pub name: String,
+    // e.g. ["catalog:read", "reader:write"]
+    #[validate(custom(function = "known_scopes"))]
+    pub scopes: Vec<String>,
+    #[validate(range(min = 1, max = MAX_API_KEY_RATE_LIMIT))]
+    pub rate_limit_per_minute: Option<i32>,
+    // Keys without an expiry stay valid until revoked
+    #[validate(range(min = 1))]
+    pub expires_in_days: Option<i64>,
+}
+
+fn known_scopes(scopes: &[String]) -> Result<(), ValidationError> {
+    api_keys::validate_scopes(scopes)
+        .map_err(|message| ValidationError::new("scopes").with_message(Cow::Owned(message)))
+}
+
+// The only response that includes the key itself
+#[derive(Debug, Serialize)]
+pub struct CreatedApiKey {
+    pub key: String,
+    #[serde(flatten)]
+    pub api_key: ApiKey,
+}
\ No newline at end of file
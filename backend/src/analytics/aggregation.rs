@@ -0,0 +1,141 @@
+use deadpool_postgres::Transaction;
+use uuid::Uuid;
+
+// Name of this job's row in analytics_aggregation_state
+const JOB_NAME: &str = "reading_events";
+
+// Events received this recently are left for the next run, so rows from
+// transactions still in flight are not skipped by the watermark
+const SETTLE_SECONDS: f64 = 30.0;
+
+/// Folds events received since the last run into the daily `user_analytics` rows
+/// and the weekly/monthly summaries. Days are computed in each user's timezone.
+/// Returns the number of user-days rebuilt.
+pub async fn aggregate_new_events(tx: &Transaction<'_>) -> Result<u64, tokio_postgres::Error> {
+    // Locking the state row keeps concurrent runs (e.g. several instances) from overlapping
+    tx.execute(
+        "INSERT INTO analytics_aggregation_state (job, processed_until)
+         VALUES ($1, 'epoch')
+         ON CONFLICT (job) DO NOTHING",
+        &[&JOB_NAME],
+    )
+    .await?;
+
+    let row = tx
+        .query_one(
+            "SELECT processed_until,
+                    CURRENT_TIMESTAMP - make_interval(secs => $2) AS upper_bound
+             FROM analytics_aggregation_state
+             WHERE job = $1
+             FOR UPDATE",
+            &[&JOB_NAME, &SETTLE_SECONDS],
+        )
+        .await?;
+
+    let processed_until: chrono::DateTime<chrono::Utc> = row.get("processed_until");
+    let upper_bound: chrono::DateTime<chrono::Utc> = row.get("upper_bound");
+
+    if upper_bound <= processed_until {
+        return Ok(0);
+    }
+
+    tx.execute(
+        "CREATE TEMP TABLE touched_days ON COMMIT DROP AS
+         SELECT DISTINCT e.user_id, (e.occurred_at AT TIME ZONE u.timezone)::date AS day
+         FROM reading_events e
+         JOIN users u ON u.id = e.user_id
+         WHERE e.received_at > $1 AND e.received_at <= $2",
+        &[&processed_until, &upper_bound],
+    )
+    .await?;
+
+    let rebuilt = rebuild_touched_days(tx).await?;
+
+    tx.execute(
+        "UPDATE analytics_aggregation_state SET processed_until = $2 WHERE job = $1",
+        &[&JOB_NAME, &upper_bound],
+    )
+    .await?;
+
+    Ok(rebuilt)
+}
+
+/// Rebuilds all of a user's summaries, e.g. after they change timezone and
+/// every event may fall on a different local day.
+pub async fn rebuild_user(tx: &Transaction<'_>, user_id: Uuid) -> Result<u64, tokio_postgres::Error> {
+    tx.execute("DELETE FROM user_analytics WHERE user_id = $1", &[&user_id])
+        .await?;
+    tx.execute("DELETE FROM user_analytics_summaries WHERE user_id = $1", &[&user_id])
+        .await?;
+    // Cached recaps were computed with the old day boundaries
+    tx.execute("DELETE FROM yearly_recaps WHERE user_id = $1", &[&user_id])
+        .await?;
+
+    tx.execute(
+        "CREATE TEMP TABLE touched_days ON COMMIT DROP AS
+         SELECT DISTINCT e.user_id, (e.occurred_at AT TIME ZONE u.timezone)::date AS day
+         FROM reading_events e
+         JOIN users u ON u.id = e.user_id
+         WHERE e.user_id = $1",
+        &[&user_id],
+    )
+    .await?;
+
+    rebuild_touched_days(tx).await
+}
+
+// Recomputes every (user, day) in `touched_days` from the event log, then the
+// weeks and months containing those days.
+async fn rebuild_touched_days(tx: &Transaction<'_>) -> Result<u64, tokio_postgres::Error> {
+    let touched: i64 = tx
+        .query_one("SELECT COUNT(*) FROM touched_days", &[])
+        .await?
+        .get(0);
+
+    tx.execute(
+        "DELETE FROM user_analytics a
+         USING touched_days t
+         WHERE a.user_id = t.user_id AND a.session_date = t.day",
+        &[],
+    )
+    .await?;
+
+    tx.execute(
+        "INSERT INTO user_analytics (user_id, book_id, pages_read, reading_time_seconds, session_date)
+         SELECT e.user_id, e.book_id, SUM(e.pages_read)::int, SUM(e.reading_time_seconds)::int, t.day
+         FROM reading_events e
+         JOIN users u ON u.id = e.user_id
+         JOIN touched_days t
+           ON t.user_id = e.user_id AND t.day = (e.occurred_at AT TIME ZONE u.timezone)::date
+         GROUP BY e.user_id, e.book_id, t.day",
+        &[],
+    )
+    .await?;
+
+    tx.execute(
+        "INSERT INTO user_analytics_summaries
+            (user_id, period, period_start, pages_read, reading_time_seconds, books_count, active_days)
+         SELECT a.user_id, p.period, date_trunc(p.period, a.session_date)::date,
+                SUM(a.pages_read), SUM(a.reading_time_seconds),
+                COUNT(DISTINCT a.book_id), COUNT(DISTINCT a.session_date)
+         FROM user_analytics a
+         CROSS JOIN (VALUES ('week'), ('month')) AS p(period)
+         WHERE EXISTS (
+             SELECT 1 FROM touched_days t
+             WHERE t.user_id = a.user_id
+               AND date_trunc(p.period, t.day) = date_trunc(p.period, a.session_date)
+         )
+         GROUP BY a.user_id, p.period, date_trunc(p.period, a.session_date)
+         ON CONFLICT (user_id, period, period_start)
+         DO UPDATE SET
+            pages_read = EXCLUDED.pages_read,
+            reading_time_seconds = EXCLUDED.reading_time_seconds,
+            books_count = EXCLUDED.books_count,
+            active_days = EXCLUDED.active_days,
+            updated_at = CURRENT_TIMESTAMP",
+        &[],
+    )
+    .await?;
+
+    Ok(touched as u64)
+}
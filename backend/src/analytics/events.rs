@@ -0,0 +1,97 @@
+use chrono::{DateTime, Duration, Utc};
+use deadpool_postgres::Transaction;
+use uuid::Uuid;
+
+// Longest stretch of reading a single event may report
+pub const MAX_EVENT_SECONDS: i32 = 4 * 60 * 60;
+
+// Fastest plausible page turning
+pub const MIN_SECONDS_PER_PAGE: i32 = 3;
+
+// Offline clients may upload late, but not arbitrarily late
+pub const MAX_EVENT_AGE_DAYS: i64 = 30;
+
+// Tolerated difference between client and server clocks
+const MAX_CLOCK_SKEW_MINUTES: i64 = 5;
+
+#[derive(Debug, Clone, Copy)]
+pub enum EventSource {
+    Client,
+    Offline,
+    Session,
+}
+
+impl std::fmt::Display for EventSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EventSource::Client => write!(f, "client"),
+            EventSource::Offline => write!(f, "offline"),
+            EventSource::Session => write!(f, "session"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ReadingEvent {
+    pub event_id: Uuid,
+    pub book_id: Uuid,
+    pub source: EventSource,
+    pub pages_read: i32,
+    pub reading_time_seconds: i32,
+    pub occurred_at: DateTime<Utc>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum IngestOutcome {
+    Accepted,
+    Duplicate,
+}
+
+/// Whether a real reader could have turned that many pages in that time.
+pub fn is_plausible_pace(pages_read: i32, reading_time_seconds: i32) -> bool {
+    pages_read <= reading_time_seconds / MIN_SECONDS_PER_PAGE + 1
+}
+
+/// Rejects offline activity from the future or from too long ago. The amounts
+/// are checked by the `Validate` rules of the request.
+pub fn validate_timestamp(occurred_at: DateTime<Utc>, now: DateTime<Utc>) -> Result<(), String> {
+    if occurred_at > now + Duration::minutes(MAX_CLOCK_SKEW_MINUTES) {
+        return Err("timestamp is in the future".to_string());
+    }
+    if occurred_at < now - Duration::days(MAX_EVENT_AGE_DAYS) {
+        return Err(format!("timestamp is older than {} days", MAX_EVENT_AGE_DAYS));
+    }
+    Ok(())
+}
+
+/// Appends an event to the log; the aggregation job later folds it into the daily
+/// analytics. Retrying the same event id is a no-op reported as a duplicate.
+pub async fn ingest(
+    tx: &Transaction<'_>,
+    user_id: Uuid,
+    event: &ReadingEvent,
+) -> Result<IngestOutcome, tokio_postgres::Error> {
+    let inserted = tx
+        .execute(
+            "INSERT INTO reading_events
+                (user_id, event_id, book_id, source, pages_read, reading_time_seconds, occurred_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7)
+             ON CONFLICT (user_id, event_id) DO NOTHING",
+            &[
+                &user_id,
+                &event.event_id,
+                &event.book_id,
+                &event.source.to_string(),
+                &event.pages_read,
+                &event.reading_time_seconds,
+                &event.occurred_at,
+            ],
+        )
+        .await?;
+
+    if inserted == 0 {
+        Ok(IngestOutcome::Duplicate)
+    } else {
+        Ok(IngestOutcome::Accepted)
+    }
+}
@@ -1,16 +1,30 @@
-use actix_web::{web, HttpResponse, Responder, post, get, HttpRequest};
+use actix_web::{web, HttpResponse, Responder, post, get, put, HttpRequest};
+use std::collections::HashSet;
 use uuid::Uuid;
-use chrono::Utc;
+use chrono::{Datelike, NaiveDate, Utc};
+use validator::Validate;
 
 use crate::auth::verify_token;
 use crate::config::Config;
 use crate::db::DbPool;
-use super::models::{ReadingAnalytics, BulkAnalytics, UserStats};
+use crate::validation::{FieldErrors, ValidJson, ValidQuery};
+use super::events::{self, EventSource, IngestOutcome, ReadingEvent};
+use super::models::{
+    ReadingAnalytics, BulkAnalytics, BulkAnalyticsResponse, BulkItemStatus, UserStats,
+    StatsQuery, Granularity,
+    StartSessionRequest, SessionUpdate, ReadingSession,
+    YearQuery, GoalRequest, GoalProgress, HeatmapDay, Heatmap,
+    RecapQuery, RecapFormat, YearlyRecap,
+};
+use super::{recap, sessions, streaks};
+
+// Largest batch the offline client may upload in one request
+const MAX_BULK_EVENTS: usize = 500;
 
 #[post("/reading")]
 pub async fn record_reading_analytics(
     req: HttpRequest,
-    body: web::Json<ReadingAnalytics>,
+    body: ValidJson<ReadingAnalytics>,
     pool: web::Data<DbPool>,
     config: web::Data<Config>,
 ) -> impl Responder {
@@ -50,23 +64,41 @@ pub async fn record_reading_analytics(
         Err(_) => return HttpResponse::BadRequest().json("Invalid book ID"),
     };
 
-    // Insert or update analytics record
-    let client = match pool.get().await {
+    // Clients that retry should send an event ID; without one the event can't be deduplicated
+    let event_id = match &body.event_id {
+        Some(id) => match Uuid::parse_str(id) {
+            Ok(id) => id,
+            Err(_) => return HttpResponse::BadRequest().json("Invalid event ID"),
+        },
+        None => Uuid::new_v4(),
+    };
+
+    let now = Utc::now();
+
+    let event = ReadingEvent {
+        event_id,
+        book_id,
+        source: EventSource::Client,
+        pages_read: body.pages_read,
+        reading_time_seconds: body.reading_time_seconds,
+        occurred_at: now,
+    };
+
+    // Append to the event log
+    let mut client = match pool.get().await {
         Ok(client) => client,
         Err(_) => return HttpResponse::InternalServerError().json("Database error"),
     };
 
-    let result = client
-        .execute(
-            "INSERT INTO user_analytics (user_id, book_id, pages_read, reading_time_seconds, session_date)
-             VALUES ($1, $2, $3, $4, CURRENT_DATE)
-             ON CONFLICT (user_id, book_id, session_date)
-             DO UPDATE SET 
-                pages_read = user_analytics.pages_read + $3,
-                reading_time_seconds = user_analytics.reading_time_seconds + $4",
-            &[&user_id, &book_id, &body.pages_read, &body.reading_time_seconds],
-        )
-        .await;
+    let tx = match client.transaction().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let result = match events::ingest(&tx, user_id, &event).await {
+        Ok(_) => tx.commit().await,
+        Err(e) => Err(e),
+    };
 
     match result {
         Ok(_) => HttpResponse::Ok().json("Analytics recorded successfully"),
@@ -114,60 +146,80 @@ pub async fn record_bulk_analytics(
         Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
     };
 
+    if body.len() > MAX_BULK_EVENTS {
+        return HttpResponse::PayloadTooLarge()
+            .json(format!("At most {} events can be uploaded at once", MAX_BULK_EVENTS));
+    }
+
     // Get database client
-    let client = match pool.get().await {
+    let mut client = match pool.get().await {
         Ok(client) => client,
         Err(_) => return HttpResponse::InternalServerError().json("Database error"),
     };
 
     // Start a transaction
-    let tx = match client.transaction().await {
+    let mut tx = match client.transaction().await {
         Ok(tx) => tx,
         Err(_) => return HttpResponse::InternalServerError().json("Database error"),
     };
 
+    // Look up every referenced book once instead of per item
+    let requested_ids: Vec<Uuid> = body
+        .iter()
+        .filter_map(|item| Uuid::parse_str(&item.book_id).ok())
+        .collect();
+
+    let known_books: HashSet<Uuid> = match tx
+        .query("SELECT id FROM books WHERE id = ANY($1)", &[&requested_ids])
+        .await
+    {
+        Ok(rows) => rows.iter().map(|row| row.get("id")).collect(),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Error recording bulk analytics");
+        }
+    };
+
+    let now = Utc::now();
+    let mut response = BulkAnalyticsResponse::default();
+
     // Process each analytics record
-    for analytics in body.iter() {
-        // Parse book ID
-        let book_id = match Uuid::parse_str(&analytics.book_id) {
-            Ok(id) => id,
-            Err(_) => continue, // Skip invalid book IDs
+    for (index, item) in body.iter().enumerate() {
+        let event = match parse_bulk_item(item, &known_books, now) {
+            Ok(event) => event,
+            Err(message) => {
+                response.push(index, item, BulkItemStatus::Rejected, Some(message));
+                continue;
+            }
         };
 
-        // Parse the timestamp
-        let timestamp = match chrono::DateTime::parse_from_rfc3339(&analytics.timestamp) {
-            Ok(dt) => dt.with_timezone(&Utc),
-            Err(_) => Utc::now(), // Use current time if invalid
+        // Each item gets its own savepoint so one failure doesn't abort the batch
+        let savepoint = match tx.transaction().await {
+            Ok(savepoint) => savepoint,
+            Err(e) => {
+                eprintln!("Database error: {}", e);
+                return HttpResponse::InternalServerError().json("Error recording bulk analytics");
+            }
         };
 
-        // Insert or update analytics record
-        let result = tx
-            .execute(
-                "INSERT INTO user_analytics (user_id, book_id, pages_read, reading_time_seconds, session_date)
-                 VALUES ($1, $2, $3, $4, $5::date)
-                 ON CONFLICT (user_id, book_id, session_date)
-                 DO UPDATE SET 
-                    pages_read = user_analytics.pages_read + $3,
-                    reading_time_seconds = user_analytics.reading_time_seconds + $4",
-                &[
-                    &user_id,
-                    &book_id,
-                    &analytics.pages_read,
-                    &analytics.reading_time_seconds,
-                    &timestamp,
-                ],
-            )
-            .await;
-
-        if let Err(e) = result {
-            eprintln!("Database error: {}", e);
-            // Continue with next record, don't fail the whole batch
+        let result = match events::ingest(&savepoint, user_id, &event).await {
+            Ok(outcome) => savepoint.commit().await.map(|_| outcome),
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(IngestOutcome::Accepted) => response.push(index, item, BulkItemStatus::Accepted, None),
+            Ok(IngestOutcome::Duplicate) => response.push(index, item, BulkItemStatus::Duplicate, None),
+            Err(e) => {
+                eprintln!("Database error: {}", e);
+                response.push(index, item, BulkItemStatus::Rejected, Some("Could not store event".to_string()));
+            }
         }
     }
 
     // Commit the transaction
     match tx.commit().await {
-        Ok(_) => HttpResponse::Ok().json("Bulk analytics recorded successfully"),
+        Ok(_) => HttpResponse::Ok().json(response),
         Err(e) => {
             eprintln!("Database error: {}", e);
             HttpResponse::InternalServerError().json("Error recording bulk analytics")
@@ -175,9 +227,49 @@ pub async fn record_bulk_analytics(
     }
 }
 
+// Validates one uploaded item and turns it into a reading event
+fn parse_bulk_item(
+    item: &BulkAnalytics,
+    known_books: &HashSet<Uuid>,
+    now: chrono::DateTime<Utc>,
+) -> Result<ReadingEvent, String> {
+    if let Err(errors) = item.validate() {
+        return Err(FieldErrors::from(errors).to_string());
+    }
+
+    let event_id = match item.event_id.as_deref().map(Uuid::parse_str) {
+        Some(Ok(id)) => id,
+        Some(Err(_)) => return Err("Invalid event ID".to_string()),
+        None => return Err("Missing event ID".to_string()),
+    };
+
+    let book_id = match Uuid::parse_str(&item.book_id) {
+        Ok(id) if known_books.contains(&id) => id,
+        Ok(_) => return Err("Book not found".to_string()),
+        Err(_) => return Err("Invalid book ID".to_string()),
+    };
+
+    let occurred_at = match chrono::DateTime::parse_from_rfc3339(&item.timestamp) {
+        Ok(dt) => dt.with_timezone(&Utc),
+        Err(_) => return Err("Invalid timestamp".to_string()),
+    };
+
+    events::validate_timestamp(occurred_at, now)?;
+
+    Ok(ReadingEvent {
+        event_id,
+        book_id,
+        source: EventSource::Offline,
+        pages_read: item.pages_read,
+        reading_time_seconds: item.reading_time_seconds,
+        occurred_at,
+    })
+}
+
 #[get("/user/stats")]
 pub async fn get_user_stats(
     req: HttpRequest,
+    query: ValidQuery<StatsQuery>,
     pool: web::Data<DbPool>,
     config: web::Data<Config>,
 ) -> impl Responder {
@@ -211,57 +303,893 @@ pub async fn get_user_stats(
         Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
     };
 
+    let granularity = query.granularity.unwrap_or(Granularity::Month);
+    let top_books_limit = query.top.unwrap_or(5);
+
     // Get database client
     let client = match pool.get().await {
         Ok(client) => client,
         Err(_) => return HttpResponse::InternalServerError().json("Database error"),
     };
 
-    // Get books read count
-    let books_read_result = client
+    // Everything is computed in one statement so totals and breakdowns agree
+    let result = client
         .query_one(
-            "SELECT COUNT(DISTINCT book_id) FROM user_reading_progress WHERE user_id = $1",
-            &[&user_id],
+            "WITH range_activity AS (
+                 SELECT a.book_id, a.session_date, a.pages_read, a.reading_time_seconds
+                 FROM user_analytics a
+                 WHERE a.user_id = $1
+                   AND ($2::date IS NULL OR a.session_date >= $2)
+                   AND ($3::date IS NULL OR a.session_date <= $3)
+             ),
+             per_book AS (
+                 SELECT book_id, SUM(pages_read) AS pages_read, SUM(reading_time_seconds) AS reading_time_seconds
+                 FROM range_activity
+                 GROUP BY book_id
+             ),
+             first_read AS (
+                 SELECT book_id, MIN(session_date) AS first_day
+                 FROM user_analytics
+                 WHERE user_id = $1
+                 GROUP BY book_id
+             ),
+             completed AS (
+                 SELECT p.book_id
+                 FROM user_reading_progress p
+                 JOIN users u ON u.id = p.user_id
+                 WHERE p.user_id = $1 AND p.completed
+                   AND ($2::date IS NULL OR (p.completed_at AT TIME ZONE u.timezone)::date >= $2)
+                   AND ($3::date IS NULL OR (p.completed_at AT TIME ZONE u.timezone)::date <= $3)
+             )
+             SELECT
+                 (SELECT COALESCE(SUM(pages_read), 0) FROM per_book)::bigint AS total_pages,
+                 (SELECT COALESCE(SUM(reading_time_seconds), 0) FROM per_book)::bigint AS total_reading_time,
+                 (SELECT COUNT(*) FROM first_read
+                  WHERE ($2::date IS NULL OR first_day >= $2)
+                    AND ($3::date IS NULL OR first_day <= $3)) AS books_started,
+                 (SELECT COUNT(*) FROM completed) AS books_completed,
+                 (SELECT COALESCE(json_agg(c ORDER BY c.reading_time_seconds DESC), '[]'::json) FROM (
+                     SELECT cat.id AS category_id, cat.name AS category_name,
+                            SUM(pb.reading_time_seconds)::bigint AS reading_time_seconds,
+                            SUM(pb.pages_read)::bigint AS pages_read
+                     FROM per_book pb
+                     JOIN books b ON b.id = pb.book_id
+                     JOIN categories cat ON cat.id = b.category_id
+                     GROUP BY cat.id, cat.name
+                 ) c) AS by_category,
+                 (SELECT COALESCE(json_agg(f ORDER BY f.reading_time_seconds DESC), '[]'::json) FROM (
+                     SELECT b.format::text AS format,
+                            SUM(pb.reading_time_seconds)::bigint AS reading_time_seconds,
+                            SUM(pb.pages_read)::bigint AS pages_read
+                     FROM per_book pb
+                     JOIN books b ON b.id = pb.book_id
+                     GROUP BY b.format
+                 ) f) AS by_format,
+                 (SELECT COALESCE(json_agg(t ORDER BY t.reading_time_seconds DESC), '[]'::json) FROM (
+                     SELECT b.id AS book_id, b.title, b.author,
+                            pb.reading_time_seconds::bigint AS reading_time_seconds,
+                            pb.pages_read::bigint AS pages_read
+                     FROM per_book pb
+                     JOIN books b ON b.id = pb.book_id
+                     ORDER BY pb.reading_time_seconds DESC
+                     LIMIT $5
+                 ) t) AS top_books,
+                 (SELECT COALESCE(json_agg(s ORDER BY s.period_start), '[]'::json) FROM (
+                     SELECT date_trunc($4, session_date::timestamp)::date AS period_start,
+                            SUM(pages_read)::bigint AS pages_read,
+                            SUM(reading_time_seconds)::bigint AS reading_time_seconds,
+                            COUNT(DISTINCT book_id) AS books
+                     FROM range_activity
+                     GROUP BY 1
+                 ) s) AS series",
+            &[
+                &user_id,
+                &query.from,
+                &query.to,
+                &granularity.as_str(),
+                &top_books_limit,
+            ],
         )
         .await;
 
-    let books_read = match books_read_result {
-        Ok(row) => row.get::<_, i64>(0),
-        Err(_) => 0,
+    let row = match result {
+        Ok(row) => row,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Error fetching user stats");
+        }
     };
 
-    // Get total pages read
-    let pages_result = client
-        .query_one(
-            "SELECT COALESCE(SUM(pages_read), 0) FROM user_analytics WHERE user_id = $1",
-            &[&user_id],
-        )
-        .await;
+    // The breakdowns are built as JSON by the query; a shape that no longer fits is a bug, not "no data"
+    let breakdowns = (|| {
+        Ok::<_, serde_json::Error>((
+            serde_json::from_value(row.get("by_category"))?,
+            serde_json::from_value(row.get("by_format"))?,
+            serde_json::from_value(row.get("top_books"))?,
+            serde_json::from_value(row.get("series"))?,
+        ))
+    })();
 
-    let total_pages = match pages_result {
-        Ok(row) => row.get::<_, i64>(0),
-        Err(_) => 0,
+    let (by_category, by_format, top_books, series) = match breakdowns {
+        Ok(breakdowns) => breakdowns,
+        Err(e) => {
+            eprintln!("Error decoding user stats: {}", e);
+            return HttpResponse::InternalServerError().json("Error fetching user stats");
+        }
     };
 
-    // Get total reading time
-    let time_result = client
-        .query_one(
-            "SELECT COALESCE(SUM(reading_time_seconds), 0) FROM user_analytics WHERE user_id = $1",
-            &[&user_id],
-        )
-        .await;
+    let total_pages: i64 = row.get("total_pages");
+    let total_reading_time: i64 = row.get("total_reading_time");
+    let books_completed: i64 = row.get("books_completed");
 
-    let total_reading_time = match time_result {
-        Ok(row) => row.get::<_, i64>(0),
-        Err(_) => 0,
+    let pages_per_hour = if total_reading_time > 0 {
+        Some(total_pages as f64 * 3600.0 / total_reading_time as f64)
+    } else {
+        None
     };
 
     // Prepare stats response
     let stats = UserStats {
-        books_read,
+        books_read: books_completed,
         total_pages,
         total_reading_time,
+        from: query.from,
+        to: query.to,
+        granularity,
+        books_started: row.get("books_started"),
+        books_completed,
+        pages_per_hour,
+        by_category,
+        by_format,
+        top_books,
+        series,
     };
 
     HttpResponse::Ok().json(stats)
+}
+
+#[post("/sessions")]
+pub async fn start_reading_session(
+    req: HttpRequest,
+    body: ValidJson<StartSessionRequest>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    // Parse book ID
+    let book_id = match Uuid::parse_str(&body.book_id) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().json("Invalid book ID"),
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let book_exists = client
+        .query_one("SELECT 1 FROM books WHERE id = $1", &[&book_id])
+        .await;
+
+    if book_exists.is_err() {
+        return HttpResponse::NotFound().json("Book not found");
+    }
+
+    let result = client
+        .query_one(
+            &format!(
+                "INSERT INTO reading_sessions (id, user_id, book_id, start_page, current_page)
+                 VALUES ($1, $2, $3, $4, $4)
+                 RETURNING {}",
+                sessions::SESSION_COLUMNS
+            ),
+            &[&Uuid::new_v4(), &user_id, &book_id, &body.current_page],
+        )
+        .await;
+
+    match result {
+        Ok(row) => HttpResponse::Created().json(ReadingSession::from_row(&row)),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error starting reading session")
+        }
+    }
+}
+
+#[post("/sessions/{id}/heartbeat")]
+pub async fn heartbeat_reading_session(
+    req: HttpRequest,
+    path: web::Path<(String,)>,
+    body: ValidJson<SessionUpdate>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    update_reading_session(&pool, user_id, &path.0, body.current_page, false).await
+}
+
+#[post("/sessions/{id}/end")]
+pub async fn end_reading_session(
+    req: HttpRequest,
+    path: web::Path<(String,)>,
+    body: ValidJson<SessionUpdate>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    update_reading_session(&pool, user_id, &path.0, body.current_page, true).await
+}
+
+// Shared by heartbeat and end: credits the time since the last heartbeat and,
+// when ending, closes the session and records it in the daily analytics.
+async fn update_reading_session(
+    pool: &DbPool,
+    user_id: Uuid,
+    session_id: &str,
+    current_page: i32,
+    end: bool,
+) -> HttpResponse {
+    let session_id = match Uuid::parse_str(session_id) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().json("Invalid session ID"),
+    };
+
+    let mut client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let tx = match client.transaction().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let existing = tx
+        .query_opt(
+            &format!(
+                "SELECT {} FROM reading_sessions WHERE id = $1 AND user_id = $2 FOR UPDATE",
+                sessions::SESSION_COLUMNS
+            ),
+            &[&session_id, &user_id],
+        )
+        .await;
+
+    let session = match existing {
+        Ok(Some(row)) => ReadingSession::from_row(&row),
+        Ok(None) => return HttpResponse::NotFound().json("Reading session not found"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Error updating reading session");
+        }
+    };
+
+    // Closed sessions (including ones closed as idle) can't be resumed; start a new one
+    if session.ended_at.is_some() {
+        return HttpResponse::Conflict().json("Reading session has ended");
+    }
+
+    let now = Utc::now();
+    let activity = sessions::credit_activity(
+        session.last_heartbeat_at,
+        now,
+        session.current_page,
+        current_page,
+    );
+
+    let updated = tx
+        .query_one(
+            &format!(
+                "UPDATE reading_sessions
+                 SET last_heartbeat_at = $2,
+                     current_page = $3,
+                     pages_turned = pages_turned + $4,
+                     active_seconds = active_seconds + $5,
+                     ended_at = CASE WHEN $6 THEN $2 ELSE NULL END,
+                     end_reason = CASE WHEN $6 THEN 'ended' ELSE NULL END
+                 WHERE id = $1
+                 RETURNING {}",
+                sessions::SESSION_COLUMNS
+            ),
+            &[&session_id, &now, &current_page, &activity.pages, &activity.seconds, &end],
+        )
+        .await;
+
+    let session = match updated {
+        Ok(row) => ReadingSession::from_row(&row),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Error updating reading session");
+        }
+    };
+
+    if end {
+        if let Err(e) = sessions::record_session(&tx, user_id, &session).await {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Error recording reading session");
+        }
+    }
+
+    match tx.commit().await {
+        Ok(_) => HttpResponse::Ok().json(session),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error updating reading session")
+        }
+    }
+}
+
+// The user's current date in their own timezone
+async fn local_today(
+    client: &deadpool_postgres::Client,
+    user_id: Uuid,
+) -> Result<NaiveDate, tokio_postgres::Error> {
+    let row = client
+        .query_one(
+            "SELECT (CURRENT_TIMESTAMP AT TIME ZONE timezone)::date AS today FROM users WHERE id = $1",
+            &[&user_id],
+        )
+        .await?;
+
+    Ok(row.get("today"))
+}
+
+async fn load_goal_progress(
+    client: &deadpool_postgres::Client,
+    user_id: Uuid,
+    year: i32,
+) -> Result<GoalProgress, tokio_postgres::Error> {
+    let row = client
+        .query_one(
+            "SELECT g.books_target, g.daily_minutes_target,
+                    (SELECT COUNT(*)
+                     FROM user_reading_progress p
+                     WHERE p.user_id = u.id AND p.completed
+                       AND EXTRACT(YEAR FROM p.completed_at AT TIME ZONE u.timezone)::int = $2
+                    ) AS books_completed,
+                    (SELECT COALESCE(SUM(a.reading_time_seconds), 0)
+                     FROM user_analytics a
+                     WHERE a.user_id = u.id
+                       AND a.session_date = (CURRENT_TIMESTAMP AT TIME ZONE u.timezone)::date
+                    ) AS today_seconds,
+                    (SELECT COUNT(*) FROM (
+                        SELECT a.session_date
+                        FROM user_analytics a
+                        WHERE a.user_id = u.id AND EXTRACT(YEAR FROM a.session_date)::int = $2
+                        GROUP BY a.session_date
+                        HAVING SUM(a.reading_time_seconds) >= g.daily_minutes_target * 60
+                    ) met) AS days_goal_met
+             FROM users u
+             LEFT JOIN reading_goals g ON g.user_id = u.id AND g.year = $2
+             WHERE u.id = $1",
+            &[&user_id, &year],
+        )
+        .await?;
+
+    let today_seconds: i64 = row.get("today_seconds");
+
+    Ok(GoalProgress {
+        year,
+        books_target: row.get("books_target"),
+        books_completed: row.get("books_completed"),
+        daily_minutes_target: row.get("daily_minutes_target"),
+        today_minutes: today_seconds / 60,
+        days_goal_met: row.get("days_goal_met"),
+    })
+}
+
+#[get("/goals")]
+pub async fn get_goals(
+    req: HttpRequest,
+    query: ValidQuery<YearQuery>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let year = match query.year {
+        Some(year) => year,
+        None => match local_today(&client, user_id).await {
+            Ok(today) => today.year(),
+            Err(_) => return HttpResponse::NotFound().json("User not found"),
+        },
+    };
+
+    match load_goal_progress(&client, user_id, year).await {
+        Ok(progress) => HttpResponse::Ok().json(progress),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error fetching reading goals")
+        }
+    }
+}
+
+#[put("/goals")]
+pub async fn set_goals(
+    req: HttpRequest,
+    body: ValidJson<GoalRequest>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let year = match body.year {
+        Some(year) => year,
+        None => match local_today(&client, user_id).await {
+            Ok(today) => today.year(),
+            Err(_) => return HttpResponse::NotFound().json("User not found"),
+        },
+    };
+
+    // A missing target clears that goal
+    let result = client
+        .execute(
+            "INSERT INTO reading_goals (user_id, year, books_target, daily_minutes_target)
+             VALUES ($1, $2, $3, $4)
+             ON CONFLICT (user_id, year)
+             DO UPDATE SET books_target = $3, daily_minutes_target = $4, updated_at = CURRENT_TIMESTAMP",
+            &[&user_id, &year, &body.books_target, &body.daily_minutes_target],
+        )
+        .await;
+
+    if let Err(e) = result {
+        eprintln!("Database error: {}", e);
+        return HttpResponse::InternalServerError().json("Error saving reading goals");
+    }
+
+    match load_goal_progress(&client, user_id, year).await {
+        Ok(progress) => HttpResponse::Ok().json(progress),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error fetching reading goals")
+        }
+    }
+}
+
+#[get("/streaks")]
+pub async fn get_streaks(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let today = match local_today(&client, user_id).await {
+        Ok(today) => today,
+        Err(_) => return HttpResponse::NotFound().json("User not found"),
+    };
+
+    let result = client
+        .query(
+            "SELECT session_date
+             FROM user_analytics
+             WHERE user_id = $1
+             GROUP BY session_date
+             HAVING SUM(reading_time_seconds) > 0 OR SUM(pages_read) > 0
+             ORDER BY session_date",
+            &[&user_id],
+        )
+        .await;
+
+    match result {
+        Ok(rows) => {
+            let days: Vec<NaiveDate> = rows.iter().map(|row| row.get("session_date")).collect();
+            HttpResponse::Ok().json(streaks::compute_streaks(&days, today))
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error fetching reading streaks")
+        }
+    }
+}
+
+#[get("/heatmap")]
+pub async fn get_heatmap(
+    req: HttpRequest,
+    query: ValidQuery<YearQuery>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let year = match query.year {
+        Some(year) => year,
+        None => match local_today(&client, user_id).await {
+            Ok(today) => today.year(),
+            Err(_) => return HttpResponse::NotFound().json("User not found"),
+        },
+    };
+
+    let daily_minutes_target: Option<i32> = match client
+        .query_opt(
+            "SELECT daily_minutes_target FROM reading_goals WHERE user_id = $1 AND year = $2",
+            &[&user_id, &year],
+        )
+        .await
+    {
+        Ok(row) => row.and_then(|row| row.get("daily_minutes_target")),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Error fetching reading heatmap");
+        }
+    };
+
+    // Only days with activity are returned; the client fills the gaps
+    let result = client
+        .query(
+            "SELECT session_date,
+                    SUM(reading_time_seconds)::bigint AS reading_time_seconds,
+                    SUM(pages_read)::bigint AS pages_read
+             FROM user_analytics
+             WHERE user_id = $1 AND EXTRACT(YEAR FROM session_date)::int = $2
+             GROUP BY session_date
+             ORDER BY session_date",
+            &[&user_id, &year],
+        )
+        .await;
+
+    match result {
+        Ok(rows) => {
+            let days = rows
+                .iter()
+                .map(|row| {
+                    let reading_time_seconds: i64 = row.get("reading_time_seconds");
+                    HeatmapDay {
+                        date: row.get("session_date"),
+                        reading_time_seconds,
+                        pages_read: row.get("pages_read"),
+                        level: streaks::heatmap_level(reading_time_seconds, daily_minutes_target),
+                    }
+                })
+                .collect();
+
+            HttpResponse::Ok().json(Heatmap {
+                year,
+                daily_minutes_target,
+                days,
+            })
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error fetching reading heatmap")
+        }
+    }
+}
+
+#[get("/recap/{year}")]
+pub async fn get_yearly_recap(
+    req: HttpRequest,
+    path: web::Path<(i32,)>,
+    query: ValidQuery<RecapQuery>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let year = path.0;
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let (username, today): (String, NaiveDate) = match client
+        .query_opt(
+            "SELECT username, (CURRENT_TIMESTAMP AT TIME ZONE timezone)::date AS today
+             FROM users WHERE id = $1",
+            &[&user_id],
+        )
+        .await
+    {
+        Ok(Some(row)) => (row.get("username"), row.get("today")),
+        Ok(None) => return HttpResponse::NotFound().json("User not found"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Database error");
+        }
+    };
+
+    if year > today.year() {
+        return HttpResponse::BadRequest().json("Recap year is in the future");
+    }
+
+    let cached: Option<YearlyRecap> = match client
+        .query_opt(
+            "SELECT recap FROM yearly_recaps WHERE user_id = $1 AND year = $2",
+            &[&user_id, &year],
+        )
+        .await
+    {
+        // A recap stored in an older shape is simply recomputed
+        Ok(row) => row.and_then(|row| serde_json::from_value(row.get("recap")).ok()),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Error fetching yearly recap");
+        }
+    };
+
+    let recap = match cached {
+        Some(recap) => recap,
+        None => {
+            let recap = match recap::build(&client, user_id, year, today).await {
+                Ok(recap) => recap,
+                Err(e) => {
+                    eprintln!("Database error: {}", e);
+                    return HttpResponse::InternalServerError().json("Error building yearly recap");
+                }
+            };
+
+            if recap.is_final {
+                let stored = client
+                    .execute(
+                        "INSERT INTO yearly_recaps (user_id, year, recap)
+                         VALUES ($1, $2, $3)
+                         ON CONFLICT (user_id, year)
+                         DO UPDATE SET recap = EXCLUDED.recap, generated_at = CURRENT_TIMESTAMP",
+                        &[&user_id, &year, &serde_json::json!(recap)],
+                    )
+                    .await;
+
+                // The recap is still served; it will be cached on a later request
+                if let Err(e) = stored {
+                    eprintln!("Database error: {}", e);
+                }
+            }
+
+            recap
+        }
+    };
+
+    match query.format.unwrap_or_default() {
+        RecapFormat::Json => HttpResponse::Ok().json(recap),
+        RecapFormat::Html => HttpResponse::Ok()
+            .content_type("text/html; charset=utf-8")
+            .body(recap::render_html(&recap, &username)),
+    }
 }
\ No newline at end of file
@@ -0,0 +1,83 @@
+use std::time::Duration;
+
+use log::{error, info};
+
+use crate::db::DbPool;
+use super::{aggregation, sessions};
+
+/// Periodically closes reading sessions whose client stopped sending heartbeats.
+pub fn spawn_session_reaper(pool: DbPool) {
+    actix_web::rt::spawn(async move {
+        let mut interval = tokio::time::interval(sessions::REAP_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            let mut client = match pool.get().await {
+                Ok(client) => client,
+                Err(e) => {
+                    error!("Session reaper could not get a connection: {}", e);
+                    continue;
+                }
+            };
+
+            let tx = match client.transaction().await {
+                Ok(tx) => tx,
+                Err(e) => {
+                    error!("Session reaper could not start a transaction: {}", e);
+                    continue;
+                }
+            };
+
+            let result = match sessions::close_idle_sessions(&tx).await {
+                Ok(closed) => tx.commit().await.map(|_| closed),
+                Err(e) => Err(e),
+            };
+
+            match result {
+                Ok(0) => {}
+                Ok(closed) => info!("Closed {} idle reading sessions", closed),
+                Err(e) => error!("Session reaper failed: {}", e),
+            }
+        }
+    });
+}
+
+/// Periodically rolls newly received reading events into the daily, weekly and
+/// monthly summaries.
+pub fn spawn_analytics_aggregator(pool: DbPool, every: Duration) {
+    actix_web::rt::spawn(async move {
+        let mut interval = tokio::time::interval(every);
+
+        loop {
+            interval.tick().await;
+
+            let mut client = match pool.get().await {
+                Ok(client) => client,
+                Err(e) => {
+                    error!("Analytics aggregation could not get a connection: {}", e);
+                    continue;
+                }
+            };
+
+            let tx = match client.transaction().await {
+                Ok(tx) => tx,
+                Err(e) => {
+                    error!("Analytics aggregation could not start a transaction: {}", e);
+                    continue;
+                }
+            };
+
+            let result = match aggregation::aggregate_new_events(&tx).await {
+                Ok(rebuilt) => tx.commit().await.map(|_| rebuilt),
+                Err(e) => Err(e),
+            };
+
+            match result {
+                Ok(0) => {}
+                Ok(rebuilt) => info!("Rebuilt analytics for {} user-days", rebuilt),
+                Err(e) => error!("Analytics aggregation failed: {}", e),
+            }
+        }
+    });
+}
@@ -1,5 +1,11 @@
+pub mod aggregation;
+pub mod events;
 pub mod handlers;
+pub mod jobs;
 pub mod models;
+pub mod recap;
+pub mod sessions;
+pub mod streaks;
 
 use actix_web::web;
 
@@ -9,5 +15,13 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
             .service(handlers::record_reading_analytics)
             .service(handlers::record_bulk_analytics)
             .service(handlers::get_user_stats)
+            .service(handlers::start_reading_session)
+            .service(handlers::heartbeat_reading_session)
+            .service(handlers::end_reading_session)
+            .service(handlers::get_goals)
+            .service(handlers::set_goals)
+            .service(handlers::get_streaks)
+            .service(handlers::get_heatmap)
+            .service(handlers::get_yearly_recap)
     );
 }
\ No newline at end of file
@@ -1,23 +1,334 @@
+use std::borrow::Cow;
+
+use chrono::{DateTime, NaiveDate, Utc};
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::{Validate, ValidationError};
+
+use crate::validation::field_error;
+use super::events::{self, MAX_EVENT_SECONDS};
+
+// Years that goals and yearly views can be asked for
+const MIN_YEAR: i32 = 1970;
+const MAX_YEAR: i32 = 9999;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Validate)]
+#[validate(schema(function = "plausible_reading"))]
 pub struct ReadingAnalytics {
+    pub event_id: Option<String>,
     pub book_id: String,
+    #[validate(range(min = 0))]
     pub pages_read: i32,
+    #[validate(range(min = 0, max = MAX_EVENT_SECONDS))]
     pub reading_time_seconds: i32,
 }
 
-#[derive(Debug, Deserialize)]
+// Checked item by item by the handler, which accepts the rest of the batch
+#[derive(Debug, Deserialize, Validate)]
+#[validate(schema(function = "plausible_bulk_reading"))]
 pub struct BulkAnalytics {
+    pub event_id: Option<String>,
     pub book_id: String,
+    #[validate(range(min = 0))]
     pub pages_read: i32,
+    #[validate(range(min = 0, max = MAX_EVENT_SECONDS))]
     pub reading_time_seconds: i32,
+    #[validate(custom(function = "rfc3339"))]
     pub timestamp: String,
 }
 
+fn plausible_reading(event: &ReadingAnalytics) -> Result<(), ValidationError> {
+    plausible_pace(event.pages_read, event.reading_time_seconds)
+}
+
+fn plausible_bulk_reading(event: &BulkAnalytics) -> Result<(), ValidationError> {
+    plausible_pace(event.pages_read, event.reading_time_seconds)
+}
+
+fn plausible_pace(pages_read: i32, reading_time_seconds: i32) -> Result<(), ValidationError> {
+    if !events::is_plausible_pace(pages_read, reading_time_seconds) {
+        return Err(field_error("pages_read", "Is implausible for the reported reading time"));
+    }
+    Ok(())
+}
+
+fn rfc3339(timestamp: &str) -> Result<(), ValidationError> {
+    if DateTime::parse_from_rfc3339(timestamp).is_err() {
+        return Err(ValidationError::new("timestamp").with_message(Cow::Borrowed("Must be an RFC 3339 timestamp")));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum BulkItemStatus {
+    Accepted,
+    Duplicate,
+    Rejected,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BulkItemResult {
+    pub index: usize,
+    pub event_id: Option<String>,
+    pub status: BulkItemStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct BulkAnalyticsResponse {
+    pub accepted: usize,
+    pub duplicates: usize,
+    pub rejected: usize,
+    pub results: Vec<BulkItemResult>,
+}
+
+impl BulkAnalyticsResponse {
+    pub fn push(&mut self, index: usize, item: &BulkAnalytics, status: BulkItemStatus, error: Option<String>) {
+        match status {
+            BulkItemStatus::Accepted => self.accepted += 1,
+            BulkItemStatus::Duplicate => self.duplicates += 1,
+            BulkItemStatus::Rejected => self.rejected += 1,
+        }
+        self.results.push(BulkItemResult {
+            index,
+            event_id: item.event_id.clone(),
+            status,
+            error,
+        });
+    }
+}
+
+#[derive(Debug, Deserialize, Validate)]
+#[validate(schema(function = "from_not_after_to"))]
+pub struct StatsQuery {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    pub granularity: Option<Granularity>,
+    #[validate(range(min = 1, max = 50))]
+    pub top: Option<i64>,
+}
+
+fn from_not_after_to(query: &StatsQuery) -> Result<(), ValidationError> {
+    if let (Some(from), Some(to)) = (query.from, query.to) {
+        if from > to {
+            return Err(field_error("from", "Must not be after `to`"));
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Granularity {
+    Day,
+    Week,
+    Month,
+}
+
+impl Granularity {
+    // Field name understood by PostgreSQL's date_trunc
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Granularity::Day => "day",
+            Granularity::Week => "week",
+            Granularity::Month => "month",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CategoryStats {
+    pub category_id: Uuid,
+    pub category_name: String,
+    pub reading_time_seconds: i64,
+    pub pages_read: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FormatStats {
+    pub format: String,
+    pub reading_time_seconds: i64,
+    pub pages_read: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TopBook {
+    pub book_id: Uuid,
+    pub title: String,
+    pub author: String,
+    pub reading_time_seconds: i64,
+    pub pages_read: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StatsBucket {
+    pub period_start: NaiveDate,
+    pub pages_read: i64,
+    pub reading_time_seconds: i64,
+    pub books: i64,
+}
+
 #[derive(Debug, Serialize)]
 pub struct UserStats {
+    // Books finished in the range (not merely opened)
     pub books_read: i64,
     pub total_pages: i64,
     pub total_reading_time: i64,
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    pub granularity: Granularity,
+    pub books_started: i64,
+    pub books_completed: i64,
+    pub pages_per_hour: Option<f64>,
+    pub by_category: Vec<CategoryStats>,
+    pub by_format: Vec<FormatStats>,
+    pub top_books: Vec<TopBook>,
+    pub series: Vec<StatsBucket>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct StartSessionRequest {
+    pub book_id: String,
+    #[validate(range(min = 1))]
+    pub current_page: i32,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct SessionUpdate {
+    #[validate(range(min = 1))]
+    pub current_page: i32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReadingSession {
+    pub id: Uuid,
+    pub book_id: Uuid,
+    pub started_at: DateTime<Utc>,
+    pub last_heartbeat_at: DateTime<Utc>,
+    pub ended_at: Option<DateTime<Utc>>,
+    pub end_reason: Option<String>,
+    pub start_page: i32,
+    pub current_page: i32,
+    pub pages_turned: i32,
+    pub active_seconds: i32,
+}
+
+impl ReadingSession {
+    pub fn from_row(row: &tokio_postgres::Row) -> Self {
+        ReadingSession {
+            id: row.get("id"),
+            book_id: row.get("book_id"),
+            started_at: row.get("started_at"),
+            last_heartbeat_at: row.get("last_heartbeat_at"),
+            ended_at: row.get("ended_at"),
+            end_reason: row.get("end_reason"),
+            start_page: row.get("start_page"),
+            current_page: row.get("current_page"),
+            pages_turned: row.get("pages_turned"),
+            active_seconds: row.get("active_seconds"),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct YearQuery {
+    #[validate(range(min = MIN_YEAR, max = MAX_YEAR))]
+    pub year: Option<i32>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct GoalRequest {
+    #[validate(range(min = MIN_YEAR, max = MAX_YEAR))]
+    pub year: Option<i32>,
+    #[validate(range(min = 1))]
+    pub books_target: Option<i32>,
+    #[validate(range(min = 1, max = 1440))]
+    pub daily_minutes_target: Option<i32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GoalProgress {
+    pub year: i32,
+    pub books_target: Option<i32>,
+    pub books_completed: i64,
+    pub daily_minutes_target: Option<i32>,
+    pub today_minutes: i64,
+    pub days_goal_met: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HeatmapDay {
+    pub date: NaiveDate,
+    pub reading_time_seconds: i64,
+    pub pages_read: i64,
+    pub level: u8,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Heatmap {
+    pub year: i32,
+    pub daily_minutes_target: Option<i32>,
+    pub days: Vec<HeatmapDay>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RecapFormat {
+    #[default]
+    Json,
+    Html,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct RecapQuery {
+    pub format: Option<RecapFormat>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecapBook {
+    pub book_id: Uuid,
+    pub title: String,
+    pub author: String,
+    pub finished_on: NaiveDate,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecapSession {
+    pub book_id: Uuid,
+    pub title: String,
+    pub date: NaiveDate,
+    pub active_seconds: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecapRanking {
+    pub name: String,
+    pub reading_time_seconds: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecapMonth {
+    pub month: u32,
+    pub reading_time_seconds: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct YearlyRecap {
+    pub year: i32,
+    pub books_finished: i64,
+    pub books_opened: i64,
+    pub total_hours: f64,
+    pub pages_read: i64,
+    pub active_days: i64,
+    pub longest_session: Option<RecapSession>,
+    pub favourite_categories: Vec<RecapRanking>,
+    pub favourite_authors: Vec<RecapRanking>,
+    pub busiest_month: Option<RecapMonth>,
+    pub first_book: Option<RecapBook>,
+    pub last_book: Option<RecapBook>,
+    // Final recaps are cached; the current year is recomputed on each request
+    pub is_final: bool,
+    pub generated_at: DateTime<Utc>,
 }
\ No newline at end of file
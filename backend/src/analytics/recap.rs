@@ -0,0 +1,271 @@
+use chrono::{Duration, NaiveDate, Utc};
+use uuid::Uuid;
+
+use super::events::MAX_EVENT_AGE_DAYS;
+use super::models::{RecapBook, RecapMonth, RecapRanking, RecapSession, YearlyRecap};
+
+// Categories and authors listed as favourites
+const TOP_ENTRIES: i64 = 3;
+
+const MONTH_NAMES: [&str; 12] = [
+    "January", "February", "March", "April", "May", "June",
+    "July", "August", "September", "October", "November", "December",
+];
+
+/// A year's recap can be cached once late offline uploads can no longer land in it.
+pub fn is_final(year: i32, today: NaiveDate) -> bool {
+    match NaiveDate::from_ymd_opt(year, 12, 31) {
+        Some(last_day) => today > last_day + Duration::days(MAX_EVENT_AGE_DAYS),
+        None => false,
+    }
+}
+
+/// Computes the recap from the daily summaries, finished books and reading sessions.
+/// Dates are taken in the user's timezone.
+pub async fn build(
+    client: &deadpool_postgres::Client,
+    user_id: Uuid,
+    year: i32,
+    today: NaiveDate,
+) -> Result<YearlyRecap, tokio_postgres::Error> {
+    let totals = client
+        .query_one(
+            "SELECT COALESCE(SUM(reading_time_seconds), 0)::bigint AS reading_time_seconds,
+                    COALESCE(SUM(pages_read), 0)::bigint AS pages_read,
+                    COUNT(DISTINCT session_date) AS active_days,
+                    COUNT(DISTINCT book_id) AS books_opened
+             FROM user_analytics
+             WHERE user_id = $1 AND EXTRACT(YEAR FROM session_date)::int = $2",
+            &[&user_id, &year],
+        )
+        .await?;
+
+    let finished: Vec<RecapBook> = client
+        .query(
+            "SELECT b.id, b.title, b.author, (p.completed_at AT TIME ZONE u.timezone)::date AS finished_on
+             FROM user_reading_progress p
+             JOIN users u ON u.id = p.user_id
+             JOIN books b ON b.id = p.book_id
+             WHERE p.user_id = $1 AND p.completed
+               AND EXTRACT(YEAR FROM p.completed_at AT TIME ZONE u.timezone)::int = $2
+             ORDER BY p.completed_at",
+            &[&user_id, &year],
+        )
+        .await?
+        .iter()
+        .map(|row| RecapBook {
+            book_id: row.get("id"),
+            title: row.get("title"),
+            author: row.get("author"),
+            finished_on: row.get("finished_on"),
+        })
+        .collect();
+
+    let longest_session = client
+        .query_opt(
+            "SELECT s.book_id, b.title, (s.started_at AT TIME ZONE u.timezone)::date AS date, s.active_seconds
+             FROM reading_sessions s
+             JOIN users u ON u.id = s.user_id
+             JOIN books b ON b.id = s.book_id
+             WHERE s.user_id = $1 AND s.active_seconds > 0
+               AND EXTRACT(YEAR FROM s.started_at AT TIME ZONE u.timezone)::int = $2
+             ORDER BY s.active_seconds DESC, s.started_at
+             LIMIT 1",
+            &[&user_id, &year],
+        )
+        .await?
+        .map(|row| RecapSession {
+            book_id: row.get("book_id"),
+            title: row.get("title"),
+            date: row.get("date"),
+            active_seconds: row.get("active_seconds"),
+        });
+
+    let favourite_categories = client
+        .query(
+            "SELECT c.name, SUM(a.reading_time_seconds)::bigint AS reading_time_seconds
+             FROM user_analytics a
+             JOIN books b ON b.id = a.book_id
+             JOIN categories c ON c.id = b.category_id
+             WHERE a.user_id = $1 AND EXTRACT(YEAR FROM a.session_date)::int = $2
+             GROUP BY c.id, c.name
+             ORDER BY reading_time_seconds DESC
+             LIMIT $3",
+            &[&user_id, &year, &TOP_ENTRIES],
+        )
+        .await?
+        .iter()
+        .map(ranking)
+        .collect();
+
+    let favourite_authors = client
+        .query(
+            "SELECT b.author AS name, SUM(a.reading_time_seconds)::bigint AS reading_time_seconds
+             FROM user_analytics a
+             JOIN books b ON b.id = a.book_id
+             WHERE a.user_id = $1 AND EXTRACT(YEAR FROM a.session_date)::int = $2
+             GROUP BY b.author
+             ORDER BY reading_time_seconds DESC
+             LIMIT $3",
+            &[&user_id, &year, &TOP_ENTRIES],
+        )
+        .await?
+        .iter()
+        .map(ranking)
+        .collect();
+
+    let busiest_month = client
+        .query_opt(
+            "SELECT EXTRACT(MONTH FROM session_date)::int AS month,
+                    SUM(reading_time_seconds)::bigint AS reading_time_seconds
+             FROM user_analytics
+             WHERE user_id = $1 AND EXTRACT(YEAR FROM session_date)::int = $2
+             GROUP BY month
+             HAVING SUM(reading_time_seconds) > 0
+             ORDER BY reading_time_seconds DESC, month
+             LIMIT 1",
+            &[&user_id, &year],
+        )
+        .await?
+        .map(|row| RecapMonth {
+            month: row.get::<_, i32>("month") as u32,
+            reading_time_seconds: row.get("reading_time_seconds"),
+        });
+
+    let reading_time_seconds: i64 = totals.get("reading_time_seconds");
+
+    Ok(YearlyRecap {
+        year,
+        books_finished: finished.len() as i64,
+        books_opened: totals.get("books_opened"),
+        total_hours: (reading_time_seconds as f64 / 360.0).round() / 10.0,
+        pages_read: totals.get("pages_read"),
+        active_days: totals.get("active_days"),
+        longest_session,
+        favourite_categories,
+        favourite_authors,
+        busiest_month,
+        first_book: finished.first().cloned(),
+        last_book: finished.last().cloned(),
+        is_final: is_final(year, today),
+        generated_at: Utc::now(),
+    })
+}
+
+fn ranking(row: &tokio_postgres::Row) -> RecapRanking {
+    RecapRanking {
+        name: row.get("name"),
+        reading_time_seconds: row.get("reading_time_seconds"),
+    }
+}
+
+/// Renders the recap as a self-contained HTML card that can be saved or shared.
+pub fn render_html(recap: &YearlyRecap, username: &str) -> String {
+    let mut highlights = Vec::new();
+
+    if let Some(month) = &recap.busiest_month {
+        highlights.push(format!(
+            "<li>Busiest month: <strong>{}</strong> ({})</li>",
+            MONTH_NAMES[(month.month - 1) as usize],
+            format_duration(month.reading_time_seconds)
+        ));
+    }
+    if let Some(session) = &recap.longest_session {
+        highlights.push(format!(
+            "<li>Longest session: <strong>{}</strong> with <em>{}</em> on {}</li>",
+            format_duration(session.active_seconds as i64),
+            escape_html(&session.title),
+            session.date.format("%B %-d")
+        ));
+    }
+    if !recap.favourite_categories.is_empty() {
+        highlights.push(format!(
+            "<li>Favourite categories: {}</li>",
+            join_names(&recap.favourite_categories)
+        ));
+    }
+    if !recap.favourite_authors.is_empty() {
+        highlights.push(format!(
+            "<li>Favourite authors: {}</li>",
+            join_names(&recap.favourite_authors)
+        ));
+    }
+    if let Some(book) = &recap.first_book {
+        highlights.push(format!(
+            "<li>First book finished: <em>{}</em> by {}</li>",
+            escape_html(&book.title),
+            escape_html(&book.author)
+        ));
+    }
+    if let Some(book) = &recap.last_book {
+        highlights.push(format!(
+            "<li>Last book finished: <em>{}</em> by {}</li>",
+            escape_html(&book.title),
+            escape_html(&book.author)
+        ));
+    }
+
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{name}'s {year} in books</title>
+<style>
+body {{ margin: 0; padding: 24px; background: #f4f1ea; font-family: Georgia, serif; color: #2b2b2b; }}
+.card {{ max-width: 480px; margin: 0 auto; padding: 32px; background: #fff; border-radius: 16px; box-shadow: 0 4px 16px rgba(0,0,0,.08); }}
+h1 {{ margin: 0 0 24px; font-size: 26px; }}
+.stats {{ display: flex; justify-content: space-between; margin-bottom: 24px; text-align: center; }}
+.stats strong {{ display: block; font-size: 28px; color: #8a4b2a; }}
+ul {{ padding-left: 20px; line-height: 1.6; }}
+</style>
+</head>
+<body>
+<div class="card">
+<h1>{name}'s {year} in books</h1>
+<div class="stats">
+<div><strong>{books}</strong>books finished</div>
+<div><strong>{hours}</strong>hours read</div>
+<div><strong>{pages}</strong>pages</div>
+</div>
+<ul>
+{highlights}
+</ul>
+</div>
+</body>
+</html>"#,
+        name = escape_html(username),
+        year = recap.year,
+        books = recap.books_finished,
+        hours = recap.total_hours,
+        pages = recap.pages_read,
+        highlights = highlights.join("\n"),
+    )
+}
+
+fn join_names(rankings: &[RecapRanking]) -> String {
+    rankings
+        .iter()
+        .map(|entry| escape_html(&entry.name))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn format_duration(seconds: i64) -> String {
+    let hours = seconds / 3600;
+    let minutes = (seconds % 3600) / 60;
+    if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else {
+        format!("{}m", minutes)
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
\ No newline at end of file
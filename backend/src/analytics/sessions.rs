@@ -0,0 +1,89 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use deadpool_postgres::Transaction;
+use uuid::Uuid;
+
+use super::events::{self, EventSource, IngestOutcome, ReadingEvent, MIN_SECONDS_PER_PAGE};
+use super::models::ReadingSession;
+
+/// Heartbeats further apart than this mean the reader walked away: the gap is not
+/// counted as reading time, and open sessions this quiet are closed as idle.
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+
+// How often the background job looks for idle sessions
+pub const REAP_INTERVAL: Duration = Duration::from_secs(60);
+
+pub const SESSION_COLUMNS: &str = "id, book_id, started_at, last_heartbeat_at, ended_at, end_reason, \
+     start_page, current_page, pages_turned, active_seconds";
+
+#[derive(Debug)]
+pub struct Activity {
+    pub seconds: i32,
+    pub pages: i32,
+}
+
+/// Reading time and pages to credit for the interval since the last heartbeat.
+pub fn credit_activity(
+    last_heartbeat_at: DateTime<Utc>,
+    now: DateTime<Utc>,
+    last_page: i32,
+    current_page: i32,
+) -> Activity {
+    let gap = (now - last_heartbeat_at).num_seconds().max(0);
+    let seconds = if gap > IDLE_TIMEOUT.as_secs() as i64 { 0 } else { gap };
+
+    let moved = (current_page as i64 - last_page as i64).abs();
+    // Jumps through the table of contents are not pages turned
+    let max_pages = seconds / MIN_SECONDS_PER_PAGE as i64 + 1;
+
+    Activity {
+        seconds: seconds as i32,
+        pages: moved.min(max_pages) as i32,
+    }
+}
+
+/// Appends a finished session to the reading event log, keyed by the session id.
+pub async fn record_session(
+    tx: &Transaction<'_>,
+    user_id: Uuid,
+    session: &ReadingSession,
+) -> Result<IngestOutcome, tokio_postgres::Error> {
+    let event = ReadingEvent {
+        event_id: session.id,
+        book_id: session.book_id,
+        source: EventSource::Session,
+        pages_read: session.pages_turned,
+        reading_time_seconds: session.active_seconds,
+        occurred_at: session.started_at,
+    };
+
+    events::ingest(tx, user_id, &event).await
+}
+
+/// Closes every session that has not sent a heartbeat within `IDLE_TIMEOUT` and
+/// records it. The session ends at its last heartbeat, so idle time is never counted.
+pub async fn close_idle_sessions(tx: &Transaction<'_>) -> Result<usize, tokio_postgres::Error> {
+    let idle_seconds = IDLE_TIMEOUT.as_secs() as f64;
+
+    let rows = tx
+        .query(
+            &format!(
+                "UPDATE reading_sessions
+                 SET ended_at = last_heartbeat_at, end_reason = 'idle'
+                 WHERE ended_at IS NULL
+                   AND last_heartbeat_at < CURRENT_TIMESTAMP - make_interval(secs => $1)
+                 RETURNING user_id, {}",
+                SESSION_COLUMNS
+            ),
+            &[&idle_seconds],
+        )
+        .await?;
+
+    for row in &rows {
+        let session = ReadingSession::from_row(row);
+        record_session(tx, row.get("user_id"), &session).await?;
+    }
+
+    Ok(rows.len())
+}
@@ -0,0 +1,57 @@
+use chrono::NaiveDate;
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+pub struct Streaks {
+    pub current_streak: u32,
+    pub longest_streak: u32,
+    pub last_read_date: Option<NaiveDate>,
+}
+
+/// Streaks of consecutive reading days. `days` must be sorted ascending and unique.
+/// The current streak is still alive if the last reading day was yesterday, since
+/// the user may simply not have read yet today.
+pub fn compute_streaks(days: &[NaiveDate], today: NaiveDate) -> Streaks {
+    let mut longest = 0;
+    let mut run = 0;
+    let mut previous: Option<NaiveDate> = None;
+
+    for &day in days {
+        run = match previous {
+            Some(prev) if prev.succ_opt() == Some(day) => run + 1,
+            _ => 1,
+        };
+        longest = longest.max(run);
+        previous = Some(day);
+    }
+
+    let current = match previous {
+        Some(last) if last == today || last.succ_opt() == Some(today) => run,
+        _ => 0,
+    };
+
+    Streaks {
+        current_streak: current,
+        longest_streak: longest,
+        last_read_date: previous,
+    }
+}
+
+/// Heatmap intensity from 0 (no reading) to 4. With a daily goal the level is the
+/// share of the goal reached; without one, fixed 15/30/60 minute thresholds are used.
+pub fn heatmap_level(reading_seconds: i64, daily_minutes_target: Option<i32>) -> u8 {
+    if reading_seconds <= 0 {
+        return 0;
+    }
+
+    let minutes = reading_seconds as f64 / 60.0;
+    let thresholds = match daily_minutes_target {
+        Some(target) if target > 0 => {
+            let target = target as f64;
+            [target * 0.25, target * 0.5, target]
+        }
+        _ => [15.0, 30.0, 60.0],
+    };
+
+    1 + thresholds.iter().filter(|&&t| minutes >= t).count() as u8
+}
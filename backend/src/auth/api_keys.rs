@@ -0,0 +1,252 @@
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::{header::{HeaderValue, AUTHORIZATION}, Method},
+    middleware::Next,
+    web, Error, HttpResponse,
+};
+use chrono::{Duration, Utc};
+use rand::RngCore;
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::db::DbPool;
+use super::{generate_secret_token, hash_secret_token, throttle, Claims};
+
+// Every API key starts with this, which is how they are told apart from JWTs
+pub const KEY_PREFIX: &str = "elib_";
+
+// Parts of the API a key can be granted, by the first segment of the path.
+// Account management (auth, oidc, privacy) is left to people.
+pub const SCOPE_AREAS: [&str; 7] = ["catalog", "reader", "analytics", "shelves", "recommendations", "opds", "admin"];
+
+// Lifetime of the access token a key is exchanged for on each request
+const ACCESS_TOKEN_EXPIRATION_SECONDS: i64 = 60;
+
+pub struct GeneratedKey {
+    // Shown to the admin once and never stored
+    pub key: String,
+    pub prefix: String,
+    pub hash: String,
+}
+
+/// New key of the form `elib_<prefix>_<secret>`. The prefix identifies the key in
+/// listings and lookups; the whole key is hashed.
+pub fn generate() -> GeneratedKey {
+    let mut bytes = [0u8; 6];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let prefix: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+
+    let key = format!("{}{}_{}", KEY_PREFIX, prefix, generate_secret_token());
+    let hash = hash_secret_token(&key);
+    GeneratedKey { key, prefix, hash }
+}
+
+/// Checks that every scope is `<area>:read` or `<area>:write` for a known area.
+pub fn validate_scopes(scopes: &[String]) -> Result<(), String> {
+    if scopes.is_empty() {
+        return Err("At least one scope is required".to_string());
+    }
+
+    for scope in scopes {
+        let valid = match scope.split_once(':') {
+            Some((area, access)) => SCOPE_AREAS.contains(&area) && (access == "read" || access == "write"),
+            None => false,
+        };
+        if !valid {
+            return Err(format!("Unknown scope: {}", scope));
+        }
+    }
+
+    Ok(())
+}
+
+// `read` covers GET and HEAD; `write` covers every method. Paths outside the
+// scope areas are refused even if a key somehow holds a scope naming them.
+fn allows(scopes: &[String], method: &Method, path: &str) -> bool {
+    let area = path.trim_start_matches('/').split('/').next().unwrap_or("");
+    if !SCOPE_AREAS.contains(&area) {
+        return false;
+    }
+    let read_only = *method == Method::GET || *method == Method::HEAD;
+
+    scopes.iter().any(|scope| match scope.split_once(':') {
+        Some((scope_area, access)) => scope_area == area && (access == "write" || (access == "read" && read_only)),
+        None => false,
+    })
+}
+
+/// Accepts API keys wherever a Bearer JWT is accepted. A valid key within its scopes
+/// and rate limit is swapped for a short-lived access token of its service account,
+/// so handlers authenticate the request as they would any other.
+pub async fn authenticate_api_keys<B: MessageBody>(
+    mut req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, Error> {
+    match exchange(&mut req).await {
+        Ok(()) => next.call(req).await.map(ServiceResponse::map_into_left_body),
+        Err(response) => Ok(req.into_response(response).map_into_right_body()),
+    }
+}
+
+// Leaves requests without an API key untouched; the error is the response to send instead
+async fn exchange(req: &mut ServiceRequest) -> Result<(), HttpResponse> {
+    let key = match req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|header| header.to_str().ok())
+        .and_then(|header| header.strip_prefix("Bearer "))
+    {
+        Some(token) if token.starts_with(KEY_PREFIX) => token.to_string(),
+        _ => return Ok(()),
+    };
+
+    let prefix = match key[KEY_PREFIX.len()..].split_once('_') {
+        Some((prefix, _)) => prefix.to_string(),
+        None => return Err(HttpResponse::Unauthorized().json("Invalid API key")),
+    };
+
+    let (config, pool) = match (req.app_data::<web::Data<Config>>(), req.app_data::<web::Data<DbPool>>()) {
+        (Some(config), Some(pool)) => (config.clone(), pool.clone()),
+        _ => return Err(HttpResponse::InternalServerError().json("Server misconfigured")),
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return Err(HttpResponse::InternalServerError().json("Database error")),
+    };
+
+    // Counting the request and recording its use in the statement that checks the
+    // key keeps concurrent requests from slipping past the limit
+    let row = client
+        .query_opt(
+            "UPDATE api_keys
+             SET rate_window_count = CASE WHEN rate_window_start = date_trunc('minute', CURRENT_TIMESTAMP)
+                                          THEN rate_window_count + 1 ELSE 1 END,
+                 rate_window_start = date_trunc('minute', CURRENT_TIMESTAMP),
+                 last_used_at = CURRENT_TIMESTAMP,
+                 last_used_ip = $3
+             WHERE prefix = $1 AND key_hash = $2
+               AND revoked_at IS NULL AND (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP)
+             RETURNING user_id, scopes, rate_limit_per_minute, rate_window_count,
+                       CEIL(EXTRACT(EPOCH FROM rate_window_start + INTERVAL '1 minute' - CURRENT_TIMESTAMP))::bigint
+                           AS window_remaining",
+            &[&prefix, &hash_secret_token(&key), &throttle::client_ip(req.request(), &config)],
+        )
+        .await;
+
+    let row = match row {
+        Ok(Some(row)) => row,
+        Ok(None) => return Err(HttpResponse::Unauthorized().json("Invalid API key")),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Err(HttpResponse::InternalServerError().json("Database error"));
+        }
+    };
+
+    if row.get::<_, i32>("rate_window_count") > row.get::<_, i32>("rate_limit_per_minute") {
+        let retry_after = row.get::<_, i64>("window_remaining").max(1);
+        return Err(HttpResponse::TooManyRequests()
+            .insert_header(("Retry-After", retry_after.to_string()))
+            .json("API key rate limit exceeded"));
+    }
+
+    let scopes: Vec<String> = row.get("scopes");
+    if !allows(&scopes, req.method(), req.path()) {
+        return Err(HttpResponse::Forbidden().json("API key does not grant access to this endpoint"));
+    }
+
+    let token = match access_token(row.get("user_id"), &config) {
+        Ok(token) => token,
+        Err(_) => return Err(HttpResponse::InternalServerError().json("Token generation error")),
+    };
+
+    match HeaderValue::from_str(&format!("Bearer {}", token)) {
+        Ok(value) => {
+            req.headers_mut().insert(AUTHORIZATION, value);
+            Ok(())
+        }
+        Err(_) => Err(HttpResponse::InternalServerError().json("Token generation error")),
+    }
+}
+
+fn access_token(user_id: Uuid, config: &Config) -> Result<String, jsonwebtoken::errors::Error> {
+    let now = Utc::now();
+
+    let claims = Claims {
+        sub: user_id.to_string(),
+        exp: (now + Duration::seconds(ACCESS_TOKEN_EXPIRATION_SECONDS)).timestamp() as usize,
+        iat: now.timestamp() as usize,
+        iss: config.keys.issuer().to_string(),
+        aud: config.keys.audience().to_string(),
+        scope: None,
+        sid: None,
+    };
+
+    config.keys.sign(&claims)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scopes(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn validate_scopes_accepts_known_areas_and_access() {
+        assert!(validate_scopes(&scopes(&["catalog:read", "admin:write"])).is_ok());
+    }
+
+    #[test]
+    fn validate_scopes_rejects_empty_unknown_and_malformed() {
+        assert!(validate_scopes(&[]).is_err());
+        assert!(validate_scopes(&scopes(&["auth:read"])).is_err());
+        assert!(validate_scopes(&scopes(&["catalog:admin"])).is_err());
+        assert!(validate_scopes(&scopes(&["catalog"])).is_err());
+        assert!(validate_scopes(&scopes(&["catalog:read", "privacy:write"])).is_err());
+    }
+
+    #[test]
+    fn read_scope_allows_only_safe_methods() {
+        let granted = scopes(&["catalog:read"]);
+        assert!(allows(&granted, &Method::GET, "/catalog/books"));
+        assert!(allows(&granted, &Method::HEAD, "/catalog/books"));
+        assert!(!allows(&granted, &Method::POST, "/catalog/books"));
+        assert!(!allows(&granted, &Method::DELETE, "/catalog/books/1"));
+    }
+
+    #[test]
+    fn write_scope_allows_every_method() {
+        let granted = scopes(&["shelves:write"]);
+        assert!(allows(&granted, &Method::GET, "/shelves"));
+        assert!(allows(&granted, &Method::PUT, "/shelves/1/books/order"));
+        assert!(allows(&granted, &Method::DELETE, "/shelves/1"));
+    }
+
+    #[test]
+    fn scope_matches_whole_first_segment_only() {
+        let granted = scopes(&["reader:write"]);
+        assert!(!allows(&granted, &Method::GET, "/readers"));
+        assert!(!allows(&granted, &Method::GET, "/catalog/reader"));
+        assert!(!allows(&granted, &Method::GET, "/"));
+    }
+
+    #[test]
+    fn paths_outside_scope_areas_are_refused() {
+        // Even a scope that slipped past validation doesn't open account management
+        let granted = scopes(&["auth:write", "privacy:write", "oidc:read", "catalog:write"]);
+        assert!(!allows(&granted, &Method::GET, "/auth/profile"));
+        assert!(!allows(&granted, &Method::DELETE, "/auth/account"));
+        assert!(!allows(&granted, &Method::POST, "/privacy/exports"));
+        assert!(!allows(&granted, &Method::GET, "/oidc/login"));
+        assert!(!allows(&granted, &Method::GET, "/koreader/syncs/progress/abc"));
+    }
+
+    #[test]
+    fn malformed_scopes_grant_nothing() {
+        let granted = scopes(&["catalog", ":write", "catalog:"]);
+        assert!(!allows(&granted, &Method::GET, "/catalog/books"));
+    }
+}
\ No newline at end of file
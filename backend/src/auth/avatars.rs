@@ -0,0 +1,53 @@
+use std::io;
+use std::path::PathBuf;
+
+use uuid::Uuid;
+
+use crate::config::Config;
+
+// Largest avatar upload accepted
+pub const MAX_AVATAR_BYTES: usize = 2 * 1024 * 1024;
+
+/// File extension for the image type given by the leading bytes. Only PNG, JPEG and
+/// WebP are accepted; the client's content type and file name are not trusted.
+pub fn detect_extension(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("png")
+    } else if bytes.starts_with(b"\xff\xd8\xff") {
+        Some("jpg")
+    } else if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        Some("webp")
+    } else {
+        None
+    }
+}
+
+/// Whether a requested avatar name could have been produced by `store`, so it
+/// cannot point outside the avatar directory.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+}
+
+pub fn path_of(config: &Config, name: &str) -> PathBuf {
+    PathBuf::from(&config.avatar_storage_path).join(name)
+}
+
+/// Writes the image under a fresh name so cached copies of the old avatar are never served.
+pub async fn store(config: &Config, user_id: Uuid, extension: &str, bytes: &[u8]) -> io::Result<String> {
+    tokio::fs::create_dir_all(&config.avatar_storage_path).await?;
+
+    let name = format!("{}-{}.{}", user_id, Uuid::new_v4(), extension);
+    tokio::fs::write(path_of(config, &name), bytes).await?;
+    Ok(name)
+}
+
+// A missing file is fine; the avatar is gone either way
+pub async fn remove(config: &Config, name: &str) {
+    if let Err(e) = tokio::fs::remove_file(path_of(config, name)).await {
+        if e.kind() != io::ErrorKind::NotFound {
+            eprintln!("Avatar removal error: {}", e);
+        }
+    }
+}
\ No newline at end of file
@@ -1,24 +1,145 @@
-use actix_web::{web, HttpResponse, Responder, post, get, HttpRequest};
+use actix_files::NamedFile;
+use actix_multipart::Multipart;
+use actix_web::{web, HttpResponse, Responder, post, get, put, delete, HttpRequest};
+use futures::StreamExt;
+use lettre::Address;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
+use validator::{Validate, ValidationError};
 use crate::{
-    auth::{hash_password, verify_password, generate_token, verify_token},
+    auth::{hash_password, verify_password, needs_rehash, generate_token, verify_token, generate_secret_token, hash_secret_token},
+    auth::{generate_mfa_token, verify_scoped_token, mfa_required_for, load_role},
+    auth::{MFA_CHALLENGE_SCOPE, MFA_ENROLLMENT_SCOPE, MFA_TOKEN_EXPIRATION_SECONDS},
+    auth::mfa::{self, SecondFactor},
+    auth::totp,
+    auth::throttle::{self, Method, Outcome},
+    auth::sessions::{self, MAX_DEVICE_NAME_LENGTH},
+    analytics::aggregation,
     config::Config,
     db::DbPool,
+    auth::verification,
+    auth::avatars,
+    mailer::{Email, Mailer},
+    privacy,
+    shelves,
+    validation::{known_timezone, not_blank, FieldErrors, ValidJson},
 };
-use super::models::{User, CreateUser};
+use super::models::{User, CreateUser, Role, ReaderTheme, Session, USER_COLUMNS, SESSION_COLUMNS};
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Validate)]
 pub struct RegisterRequest {
+    #[validate(custom(function = "not_blank"), length(max = MAX_USERNAME_LENGTH))]
     pub username: String,
+    #[validate(email, length(max = MAX_EMAIL_LENGTH))]
     pub email: String,
+    // The password policy is checked by the handler, which knows the configuration
     pub password: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Validate)]
 pub struct LoginRequest {
+    #[validate(length(min = 1, max = MAX_EMAIL_LENGTH))]
+    pub email: String,
+    #[validate(length(min = 1))]
+    pub password: String,
+    // Shown in the list of sessions; made up from the user agent when missing
+    #[validate(length(max = MAX_DEVICE_NAME_LENGTH))]
+    pub device_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ForgotPasswordRequest {
+    #[validate(email, length(max = MAX_EMAIL_LENGTH))]
     pub email: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ResetPasswordRequest {
+    #[validate(length(min = 1))]
+    pub token: String,
+    pub new_password: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct VerifyEmailRequest {
+    #[validate(length(min = 1))]
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct VerifyEmailCodeRequest {
+    #[validate(custom(function = "not_blank"))]
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct TimezoneRequest {
+    #[validate(custom(function = "known_timezone"))]
+    pub timezone: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct UpdateProfileRequest {
+    #[validate(custom(function = "not_blank"), length(max = MAX_USERNAME_LENGTH))]
+    pub username: Option<String>,
+    #[validate(email, length(max = MAX_EMAIL_LENGTH))]
+    pub email: Option<String>,
+    // Required to change the email address
+    pub current_password: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ChangePasswordRequest {
+    #[validate(length(min = 1))]
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct PreferencesRequest {
+    #[validate(custom(function = "supported_language"))]
+    pub language: Option<String>,
+    #[validate(custom(function = "known_timezone"))]
+    pub timezone: Option<String>,
+    pub reader_theme: Option<ReaderTheme>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct DeleteAccountRequest {
+    // May be left out by accounts linked to single sign-on right after signing in there
+    #[validate(length(min = 1))]
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SecondFactorRequest {
+    pub code: Option<String>,
+    pub recovery_code: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct MfaEnableRequest {
+    #[validate(custom(function = "not_blank"))]
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct MfaVerifyRequest {
+    #[validate(length(min = 1))]
+    pub mfa_token: String,
+    // As for `login`, which asked for the second factor
+    #[validate(length(max = MAX_DEVICE_NAME_LENGTH))]
+    pub device_name: Option<String>,
+    #[serde(flatten)]
+    pub factor: SecondFactorRequest,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct MfaDisableRequest {
+    #[validate(length(min = 1))]
     pub password: String,
+    #[serde(flatten)]
+    pub factor: SecondFactorRequest,
 }
 
 #[derive(Debug, Serialize)]
@@ -27,13 +148,86 @@ pub struct AuthResponse {
     pub user: User,
 }
 
+// Returned by `login` instead of a token while a second step is outstanding
+#[derive(Debug, Serialize)]
+pub struct MfaChallengeResponse {
+    pub mfa_token: String,
+    // The user's role requires 2FA but none is set up; the token only allows enrolling
+    pub enrollment_required: bool,
+    pub expires_in: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MfaSetupResponse {
+    pub secret: String,
+    pub provisioning_uri: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MfaEnabledResponse {
+    pub recovery_codes: Vec<String>,
+    // Present when 2FA was set up with an enrollment token, which completes the login
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<User>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecoveryCodesResponse {
+    pub recovery_codes: Vec<String>,
+}
+
+// Longest username accepted when registering or editing the profile
+const MAX_USERNAME_LENGTH: u64 = 50;
+
+// As in `users.email`
+const MAX_EMAIL_LENGTH: u64 = 255;
+
+// Interface languages the frontend ships translations for
+const SUPPORTED_LANGUAGES: [&str; 2] = ["en", "id"];
+
+fn supported_language(language: &str) -> Result<(), ValidationError> {
+    if !SUPPORTED_LANGUAGES.contains(&language) {
+        return Err(ValidationError::new("language").with_message("Unsupported language".into()));
+    }
+    Ok(())
+}
+
+// A new reset email is not sent while the previous one is younger than this
+const RESET_REQUEST_COOLDOWN_SECONDS: f64 = 60.0;
+
+// How recent a single sign-on round trip stands in for the password of an account
+// that signs in through the provider and never chose one
+const RECENT_SIGN_IN_SECONDS: f64 = 300.0;
+
 #[post("/register")]
 pub async fn register(
+    http_req: HttpRequest,
     pool: web::Data<DbPool>,
     config: web::Data<Config>,
-    req: web::Json<RegisterRequest>,
+    mailer: web::Data<dyn Mailer>,
+    req: ValidJson<RegisterRequest>,
 ) -> impl Responder {
-    let client = match pool.get().await {
+    let mut errors = FieldErrors::new();
+
+    let username = req.username.trim().to_string();
+
+    // Parsing as a mailbox address rejects anything a mail server would refuse
+    let email = match req.email.trim().parse::<Address>() {
+        Ok(address) => address.to_string(),
+        Err(_) => {
+            errors.add("email", "Invalid email address");
+            String::new()
+        }
+    };
+
+    errors.extend("password", config.password_policy.check(&req.password, &[username.as_str(), email.as_str()]));
+    if let Some(response) = errors.response() {
+        return response;
+    }
+
+    let mut client = match pool.get().await {
         Ok(client) => client,
         Err(_) => return HttpResponse::InternalServerError().json("Database error"),
     };
@@ -42,7 +236,7 @@ pub async fn register(
     let existing_user = client
         .query_one(
             "SELECT COUNT(*) FROM users WHERE email = $1",
-            &[&req.email],
+            &[&email],
         )
         .await;
 
@@ -57,7 +251,7 @@ pub async fn register(
     }
 
     // Hash password
-    let hashed_password = match hash_password(&req.password) {
+    let hashed_password = match hash_password(&req.password, &config) {
         Ok(hash) => hash,
         Err(_) => return HttpResponse::InternalServerError().json("Password hashing error"),
     };
@@ -66,31 +260,62 @@ pub async fn register(
     let user_id = Uuid::new_v4();
     let new_user = CreateUser {
         id: user_id,
-        username: req.username.clone(),
-        email: req.email.clone(),
+        username,
+        email,
         password_hash: hashed_password,
     };
 
-    // Insert user into database
-    let result = client
-        .execute(
+    // Insert user into database along with their default shelves
+    let tx = match client.transaction().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let result = async {
+        tx.execute(
             "INSERT INTO users (id, username, email, password_hash) VALUES ($1, $2, $3, $4)",
             &[&new_user.id, &new_user.username, &new_user.email, &new_user.password_hash],
         )
-        .await;
+        .await?;
+        shelves::create_default_shelves(&tx, new_user.id).await?;
+        let issued = verification::issue(&tx, &config, new_user.id, &new_user.email).await?;
+        tx.commit().await?;
+        Ok::<_, tokio_postgres::Error>(issued)
+    }
+    .await;
 
     match result {
-        Ok(_) => {
+        Ok(issued) => {
+            // The account stays usable while the email is pending; the user can ask for a resend
+            if let Err(e) = verification::send(
+                mailer.get_ref(),
+                &config,
+                &new_user.username,
+                &new_user.email,
+                &issued,
+            )
+            .await
+            {
+                eprintln!("Mail error: {}", e);
+            }
+
             // Create user object for token generation
             let user = User {
                 id: new_user.id,
                 username: new_user.username,
                 email: new_user.email,
+                pending_email: None,
+                timezone: "UTC".to_string(),
+                role: Role::Reader,
+                email_verified_at: None,
+                avatar_url: None,
+                language: "en".to_string(),
+                reader_theme: ReaderTheme::Light,
                 created_at: chrono::Utc::now(),
             };
 
             // Generate JWT token
-            match generate_token(&user, &config) {
+            match start_session(&client, &user, &http_req, None, &config).await {
                 Ok(token) => {
                     let response = AuthResponse {
                         token,
@@ -98,7 +323,7 @@ pub async fn register(
                     };
                     HttpResponse::Created().json(response)
                 }
-                Err(_) => HttpResponse::InternalServerError().json("Token generation error"),
+                Err(response) => response,
             }
         }
         Err(_) => HttpResponse::InternalServerError().json("Failed to create user"),
@@ -107,49 +332,105 @@ pub async fn register(
 
 #[post("/login")]
 pub async fn login(
+    http_req: HttpRequest,
     pool: web::Data<DbPool>,
     config: web::Data<Config>,
-    req: web::Json<LoginRequest>,
+    req: ValidJson<LoginRequest>,
 ) -> impl Responder {
     let client = match pool.get().await {
         Ok(client) => client,
         Err(_) => return HttpResponse::InternalServerError().json("Database error"),
     };
 
-    // Find user by email
+    // Refuse early while the account or address is backing off, without checking the password
+    let attempt = throttle::Attempt::new(&http_req, &config, &req.email);
+    match throttle::retry_after(&client, &attempt).await {
+        Ok(0) => {}
+        Ok(seconds) => {
+            audit(&client, &attempt, None, Method::Password, Outcome::Throttled).await;
+            return too_many_attempts(seconds);
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Database error");
+        }
+    }
+
+    // Find user by email; service accounts only authenticate with API keys
     let user_result = client
-        .query_one(
-            "SELECT id, username, email, password_hash, created_at FROM users WHERE email = $1",
+        .query_opt(
+            "SELECT u.id, u.username, u.email, u.pending_email, u.password_hash, u.timezone, u.role,
+                    u.email_verified_at, u.avatar_path, u.language, u.reader_theme, u.created_at,
+                    m.enabled_at IS NOT NULL AS mfa_enabled,
+                    COALESCE(p.require_mfa, FALSE) AS mfa_required
+             FROM users u
+             LEFT JOIN user_mfa m ON m.user_id = u.id
+             LEFT JOIN role_security_policies p ON p.role = u.role
+             WHERE u.email = $1 AND NOT u.is_service_account",
             &[&req.email],
         )
         .await;
 
     let row = match user_result {
-        Ok(row) => row,
-        Err(_) => return HttpResponse::Unauthorized().json("Invalid email or password"),
+        Ok(Some(row)) => row,
+        Ok(None) => {
+            // Unknown emails cost the same time as wrong passwords
+            throttle::verify_dummy_password(&req.password, &config);
+            audit(&client, &attempt, None, Method::Password, Outcome::InvalidCredentials).await;
+            return HttpResponse::Unauthorized().json("Invalid email or password");
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Database error");
+        }
     };
+    let user_id: Uuid = row.get("id");
 
     // Verify password
     let password_hash: String = row.get("password_hash");
     match verify_password(&req.password, &password_hash) {
         Ok(valid) => {
             if !valid {
+                audit(&client, &attempt, Some(user_id), Method::Password, Outcome::InvalidCredentials).await;
                 return HttpResponse::Unauthorized().json("Invalid email or password");
             }
         }
         Err(_) => return HttpResponse::InternalServerError().json("Password verification error"),
     }
 
-    // Create user object for token generation
-    let user = User {
-        id: row.get("id"),
-        username: row.get("username"),
-        email: row.get("email"),
-        created_at: row.get("created_at"),
+    // Bring bcrypt hashes and outdated Argon2 parameters up to date while the password is at hand
+    if needs_rehash(&password_hash, &config) {
+        rehash_password(&client, user_id, &req.password, &password_hash, &config).await;
+    }
+
+    // With 2FA the password only earns a challenge token, exchanged at /auth/mfa/verify
+    let mfa_scope = if row.get::<_, bool>("mfa_enabled") {
+        Some(MFA_CHALLENGE_SCOPE)
+    } else if row.get::<_, bool>("mfa_required") {
+        Some(MFA_ENROLLMENT_SCOPE)
+    } else {
+        None
     };
 
+    if let Some(scope) = mfa_scope {
+        audit(&client, &attempt, Some(user_id), Method::Password, Outcome::MfaPending).await;
+        return match generate_mfa_token(user_id, scope, &config) {
+            Ok(mfa_token) => HttpResponse::Ok().json(MfaChallengeResponse {
+                mfa_token,
+                enrollment_required: scope == MFA_ENROLLMENT_SCOPE,
+                expires_in: MFA_TOKEN_EXPIRATION_SECONDS,
+            }),
+            Err(_) => HttpResponse::InternalServerError().json("Token generation error"),
+        };
+    }
+
+    // Create user object for token generation
+    let user = User::from_row(&row);
+
+    audit(&client, &attempt, Some(user_id), Method::Password, Outcome::Success).await;
+
     // Generate JWT token
-    match generate_token(&user, &config) {
+    match start_session(&client, &user, &http_req, req.device_name.as_deref(), &config).await {
         Ok(token) => {
             let response = AuthResponse {
                 token,
@@ -157,19 +438,76 @@ pub async fn login(
             };
             HttpResponse::Ok().json(response)
         }
-        Err(_) => HttpResponse::InternalServerError().json("Token generation error"),
+        Err(response) => response,
     }
 }
 
 #[post("/logout")]
-pub async fn logout() -> impl Responder {
-    // Since we're using JWT, we don't need to do anything server-side
-    // The client should discard the token
-    HttpResponse::Ok().json("Logged out successfully")
+pub async fn logout(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    // Tokens without a session can only be discarded by the client
+    let session_id = match claims.sid.as_deref().map(Uuid::parse_str) {
+        Some(Ok(id)) => id,
+        Some(Err(_)) => return HttpResponse::Unauthorized().json("Invalid token"),
+        None => return HttpResponse::Ok().json("Logged out successfully"),
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    match client
+        .execute(
+            "UPDATE user_sessions SET revoked_at = CURRENT_TIMESTAMP
+             WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL",
+            &[&session_id, &user_id],
+        )
+        .await
+    {
+        Ok(_) => HttpResponse::Ok().json("Logged out successfully"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Database error")
+        }
+    }
 }
 
-#[get("/profile")]
-pub async fn profile(
+#[get("/sessions")]
+pub async fn list_sessions(
     req: HttpRequest,
     pool: web::Data<DbPool>,
     config: web::Data<Config>,
@@ -204,29 +542,1969 @@ pub async fn profile(
         Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
     };
 
-    // Get user from database
+    let current_id = claims.sid.as_deref().and_then(|sid| Uuid::parse_str(sid).ok());
+
     let client = match pool.get().await {
         Ok(client) => client,
         Err(_) => return HttpResponse::InternalServerError().json("Database error"),
     };
 
-    let user_result = client
-        .query_one(
-            "SELECT id, username, email, created_at FROM users WHERE id = $1",
+    let rows = client
+        .query(
+            &format!(
+                "SELECT {} FROM user_sessions
+                 WHERE user_id = $1 AND revoked_at IS NULL AND expires_at > CURRENT_TIMESTAMP
+                 ORDER BY last_active_at DESC",
+                SESSION_COLUMNS
+            ),
             &[&user_id],
         )
         .await;
 
-    match user_result {
-        Ok(row) => {
-            let user = User {
-                id: row.get("id"),
-                username: row.get("username"),
-                email: row.get("email"),
-                created_at: row.get("created_at"),
-            };
-            HttpResponse::Ok().json(user)
+    match rows {
+        Ok(rows) => {
+            let sessions: Vec<Session> = rows.iter().map(|row| Session::from_row(row, current_id)).collect();
+            HttpResponse::Ok().json(sessions)
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Database error")
+        }
+    }
+}
+
+#[delete("/sessions/{id}")]
+pub async fn revoke_session(
+    req: HttpRequest,
+    path: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    // Tokens of the session are turned away from the next request on
+    let revoked = client
+        .execute(
+            "UPDATE user_sessions SET revoked_at = CURRENT_TIMESTAMP
+             WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL AND expires_at > CURRENT_TIMESTAMP",
+            &[&path.into_inner(), &user_id],
+        )
+        .await;
+
+    match revoked {
+        Ok(0) => HttpResponse::NotFound().json("Session not found"),
+        Ok(_) => HttpResponse::NoContent().finish(),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Database error")
+        }
+    }
+}
+
+#[post("/password/forgot")]
+pub async fn forgot_password(
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    mailer: web::Data<dyn Mailer>,
+    req: ValidJson<ForgotPasswordRequest>,
+) -> impl Responder {
+    // The same answer whether or not the address is registered, so it can't be used to probe accounts
+    let accepted = HttpResponse::Ok().json("If that email is registered, a reset link has been sent");
+
+    let mut client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let user = match client
+        .query_opt("SELECT id, username, email FROM users WHERE email = $1 AND NOT is_service_account", &[&req.email])
+        .await
+    {
+        Ok(Some(row)) => row,
+        Ok(None) => return accepted,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Database error");
+        }
+    };
+    let user_id: Uuid = user.get("id");
+
+    let token = generate_secret_token();
+    let expires_at = chrono::Utc::now()
+        + chrono::Duration::seconds(config.password_reset_expires_in.as_secs() as i64);
+
+    let tx = match client.transaction().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    // Only the newest link works; older unused ones are dropped when a new one is issued
+    let result = async {
+        let recent = tx
+            .query_opt(
+                "SELECT 1 FROM password_reset_tokens
+                 WHERE user_id = $1 AND created_at > CURRENT_TIMESTAMP - make_interval(secs => $2)
+                 FOR UPDATE",
+                &[&user_id, &RESET_REQUEST_COOLDOWN_SECONDS],
+            )
+            .await?;
+        if recent.is_some() {
+            return Ok(false);
+        }
+
+        tx.execute(
+            "DELETE FROM password_reset_tokens WHERE user_id = $1 AND used_at IS NULL",
+            &[&user_id],
+        )
+        .await?;
+        tx.execute(
+            "INSERT INTO password_reset_tokens (user_id, token_hash, expires_at) VALUES ($1, $2, $3)",
+            &[&user_id, &hash_secret_token(&token), &expires_at],
+        )
+        .await?;
+        tx.commit().await?;
+        Ok::<_, tokio_postgres::Error>(true)
+    }
+    .await;
+
+    match result {
+        Ok(true) => {}
+        Ok(false) => return accepted,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Error creating reset token");
+        }
+    }
+
+    let username: String = user.get("username");
+    let email = Email {
+        to: user.get("email"),
+        subject: "Reset your E-Library password".to_string(),
+        body: format!(
+            "Hi {},\n\n\
+             Someone asked to reset the password of your E-Library account. \
+             Open this link to choose a new one:\n\n\
+             {}?token={}\n\n\
+             The link can be used once and expires in {} minutes. \
+             If you did not ask for this, you can ignore this email.\n",
+            username,
+            config.password_reset_url,
+            token,
+            config.password_reset_expires_in.as_secs() / 60,
+        ),
+    };
+
+    // Sent in the background and only logged on failure, for the same reason as above:
+    // waiting for the mail server would make registered addresses answer more slowly
+    let mailer = mailer.into_inner();
+    actix_web::rt::spawn(async move {
+        if let Err(e) = mailer.send(email).await {
+            eprintln!("Mail error: {}", e);
+        }
+    });
+
+    accepted
+}
+
+#[post("/password/reset")]
+pub async fn reset_password(
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    req: ValidJson<ResetPasswordRequest>,
+) -> impl Responder {
+    let mut errors = FieldErrors::new();
+    errors.extend("new_password", config.password_policy.check(&req.new_password, &[]));
+    if let Some(response) = errors.response() {
+        return response;
+    }
+
+    let hashed_password = match hash_password(&req.new_password, &config) {
+        Ok(hash) => hash,
+        Err(_) => return HttpResponse::InternalServerError().json("Password hashing error"),
+    };
+
+    let mut client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let tx = match client.transaction().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let result = async {
+        // Marking the token used in the same statement that checks it keeps it single-use
+        let claimed = tx
+            .query_opt(
+                "UPDATE password_reset_tokens SET used_at = CURRENT_TIMESTAMP
+                 WHERE token_hash = $1 AND used_at IS NULL AND expires_at > CURRENT_TIMESTAMP
+                 RETURNING user_id",
+                &[&hash_secret_token(&req.token)],
+            )
+            .await?;
+        let user_id: Uuid = match claimed {
+            Some(row) => row.get("user_id"),
+            None => return Ok(false),
+        };
+
+        // Whoever knew the old password may still hold a token; sign every device out
+        tx.execute(
+            "UPDATE users SET password_hash = $2, tokens_invalid_before = CURRENT_TIMESTAMP WHERE id = $1",
+            &[&user_id, &hashed_password],
+        )
+        .await?;
+        sessions::revoke_all(&tx, user_id).await?;
+        tx.execute(
+            "DELETE FROM password_reset_tokens WHERE user_id = $1 AND used_at IS NULL",
+            &[&user_id],
+        )
+        .await?;
+        tx.commit().await?;
+        Ok::<_, tokio_postgres::Error>(true)
+    }
+    .await;
+
+    match result {
+        Ok(true) => HttpResponse::Ok().json("Password has been reset"),
+        Ok(false) => HttpResponse::BadRequest().json("Invalid or expired reset token"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error resetting password")
         }
-        Err(_) => HttpResponse::NotFound().json("User not found"),
     }
+}
+
+#[post("/email/verify")]
+pub async fn verify_email(
+    pool: web::Data<DbPool>,
+    req: ValidJson<VerifyEmailRequest>,
+) -> impl Responder {
+    let mut client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let tx = match client.transaction().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let result = async {
+        let claimed = tx
+            .query_opt(
+                "UPDATE email_verification_tokens SET used_at = CURRENT_TIMESTAMP
+                 WHERE token_hash = $1 AND used_at IS NULL AND expires_at > CURRENT_TIMESTAMP
+                 RETURNING user_id, email",
+                &[&hash_secret_token(&req.token)],
+            )
+            .await?;
+        let row = match claimed {
+            Some(row) => row,
+            None => return Ok(false),
+        };
+
+        // A link sent to a previous address must not verify the current one
+        let verified = confirm_email(&tx, row.get("user_id"), &row.get::<_, String>("email")).await?;
+        if verified == 0 {
+            return Ok(false);
+        }
+
+        tx.commit().await?;
+        Ok::<_, tokio_postgres::Error>(true)
+    }
+    .await;
+
+    match result {
+        Ok(true) => HttpResponse::Ok().json("Email verified"),
+        Ok(false) => HttpResponse::BadRequest().json("Invalid or expired verification link"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error verifying email")
+        }
+    }
+}
+
+#[post("/email/verify/code")]
+pub async fn verify_email_code(
+    req: HttpRequest,
+    body: ValidJson<VerifyEmailCodeRequest>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let mut client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let tx = match client.transaction().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let result = async {
+        let pending = tx
+            .query_opt(
+                "SELECT v.id, v.email, v.code_hash, v.failed_attempts
+                 FROM email_verification_tokens v
+                 JOIN users u ON u.id = v.user_id AND (u.email = v.email OR u.pending_email = v.email)
+                 WHERE v.user_id = $1 AND v.used_at IS NULL AND v.expires_at > CURRENT_TIMESTAMP
+                 ORDER BY v.created_at DESC
+                 LIMIT 1
+                 FOR UPDATE OF v",
+                &[&user_id],
+            )
+            .await?;
+        let row = match pending {
+            Some(row) => row,
+            None => return Ok(Err("No pending verification; request a new code")),
+        };
+
+        let id: Uuid = row.get("id");
+        if row.get::<_, i32>("failed_attempts") >= verification::MAX_CODE_ATTEMPTS {
+            return Ok(Err("Too many wrong codes; request a new code"));
+        }
+
+        if hash_secret_token(body.code.trim()) != row.get::<_, String>("code_hash").trim_end() {
+            tx.execute(
+                "UPDATE email_verification_tokens SET failed_attempts = failed_attempts + 1 WHERE id = $1",
+                &[&id],
+            )
+            .await?;
+            tx.commit().await?;
+            return Ok(Err("Invalid verification code"));
+        }
+
+        tx.execute(
+            "UPDATE email_verification_tokens SET used_at = CURRENT_TIMESTAMP WHERE id = $1",
+            &[&id],
+        )
+        .await?;
+        if confirm_email(&tx, user_id, &row.get::<_, String>("email")).await? == 0 {
+            return Ok(Err("This email address is already in use"));
+        }
+        tx.commit().await?;
+        Ok::<_, tokio_postgres::Error>(Ok(()))
+    }
+    .await;
+
+    match result {
+        Ok(Ok(())) => HttpResponse::Ok().json("Email verified"),
+        Ok(Err(message)) => HttpResponse::BadRequest().json(message),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error verifying email")
+        }
+    }
+}
+
+#[post("/email/resend")]
+pub async fn resend_verification(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    mailer: web::Data<dyn Mailer>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let mut client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let tx = match client.transaction().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let result = async {
+        // Locking the user row serializes concurrent resends, so the limits below hold
+        let user = match tx
+            .query_opt(
+                "SELECT username, COALESCE(pending_email, email) AS email,
+                        email_verified_at IS NOT NULL AND pending_email IS NULL AS verified
+                 FROM users WHERE id = $1
+                 FOR UPDATE",
+                &[&user_id],
+            )
+            .await?
+        {
+            Some(user) => user,
+            None => return Ok(Err(HttpResponse::NotFound().json("User not found"))),
+        };
+
+        if user.get::<_, bool>("verified") {
+            return Ok(Err(HttpResponse::BadRequest().json("Email is already verified")));
+        }
+
+        let wait = verification::seconds_until_next_send(&tx, user_id).await?;
+        if wait > 0 {
+            return Ok(Err(HttpResponse::TooManyRequests()
+                .insert_header(("Retry-After", wait.to_string()))
+                .json("Please wait before requesting another verification email")));
+        }
+
+        let username: String = user.get("username");
+        let email: String = user.get("email");
+        let issued = verification::issue(&tx, &config, user_id, &email).await?;
+        tx.commit().await?;
+        Ok::<_, tokio_postgres::Error>(Ok((username, email, issued)))
+    }
+    .await;
+
+    let (username, email, issued) = match result {
+        Ok(Ok(sent)) => sent,
+        Ok(Err(response)) => return response,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Error creating verification email");
+        }
+    };
+
+    match verification::send(mailer.get_ref(), &config, &username, &email, &issued).await {
+        Ok(_) => HttpResponse::Ok().json("Verification email sent"),
+        Err(e) => {
+            eprintln!("Mail error: {}", e);
+            HttpResponse::InternalServerError().json("Error sending verification email")
+        }
+    }
+}
+
+#[post("/mfa/setup")]
+pub async fn mfa_setup(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Users whose role requires 2FA enroll with the token `login` gave them instead
+    let claims = match verify_token(token, &config)
+        .or_else(|_| verify_scoped_token(token, MFA_ENROLLMENT_SCOPE, &config))
+    {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let email: String = match client
+        .query_opt("SELECT email FROM users WHERE id = $1", &[&user_id])
+        .await
+    {
+        Ok(Some(row)) => row.get("email"),
+        Ok(None) => return HttpResponse::NotFound().json("User not found"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Database error");
+        }
+    };
+
+    // Starting over replaces a secret that was never confirmed, but never an active one
+    let secret = totp::generate_secret();
+    let result = client
+        .query_opt(
+            "INSERT INTO user_mfa (user_id, secret) VALUES ($1, $2)
+             ON CONFLICT (user_id) DO UPDATE
+             SET secret = EXCLUDED.secret, last_used_step = 0, failed_attempts = 0, created_at = CURRENT_TIMESTAMP
+             WHERE user_mfa.enabled_at IS NULL
+             RETURNING user_id",
+            &[&user_id, &secret],
+        )
+        .await;
+
+    match result {
+        Ok(Some(_)) => HttpResponse::Ok().json(MfaSetupResponse {
+            provisioning_uri: totp::provisioning_uri(&secret, &email),
+            secret,
+        }),
+        Ok(None) => HttpResponse::Conflict().json("Two-factor authentication is already enabled"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error setting up two-factor authentication")
+        }
+    }
+}
+
+#[post("/mfa/enable")]
+pub async fn mfa_enable(
+    req: HttpRequest,
+    body: ValidJson<MfaEnableRequest>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Users whose role requires 2FA enroll with the token `login` gave them instead
+    let claims = match verify_token(token, &config)
+        .or_else(|_| verify_scoped_token(token, MFA_ENROLLMENT_SCOPE, &config))
+    {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let mut client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let tx = match client.transaction().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let result = async {
+        let pending = tx
+            .query_opt(
+                "SELECT secret, enabled_at IS NOT NULL AS enabled FROM user_mfa WHERE user_id = $1 FOR UPDATE",
+                &[&user_id],
+            )
+            .await?;
+        let row = match pending {
+            Some(row) => row,
+            None => return Ok(Err(HttpResponse::BadRequest().json("Start two-factor setup first"))),
+        };
+        if row.get::<_, bool>("enabled") {
+            return Ok(Err(HttpResponse::Conflict().json("Two-factor authentication is already enabled")));
+        }
+
+        // Confirming a code proves the authenticator app holds the secret
+        let secret: String = row.get("secret");
+        let step = match totp::verify(&secret, &body.code, chrono::Utc::now().timestamp(), 0) {
+            Some(step) => step,
+            None => return Ok(Err(HttpResponse::BadRequest().json("Invalid code"))),
+        };
+
+        tx.execute(
+            "UPDATE user_mfa SET enabled_at = CURRENT_TIMESTAMP, last_used_step = $2, failed_attempts = 0
+             WHERE user_id = $1",
+            &[&user_id, &step],
+        )
+        .await?;
+        let recovery_codes = mfa::replace_recovery_codes(&tx, user_id).await?;
+        tx.commit().await?;
+        Ok::<_, tokio_postgres::Error>(Ok(recovery_codes))
+    }
+    .await;
+
+    let recovery_codes = match result {
+        Ok(Ok(codes)) => codes,
+        Ok(Err(response)) => return response,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Error enabling two-factor authentication");
+        }
+    };
+
+    // Enrolling with an enrollment token finishes the login that asked for it
+    if claims.scope.is_none() {
+        return HttpResponse::Ok().json(MfaEnabledResponse {
+            recovery_codes,
+            token: None,
+            user: None,
+        });
+    }
+
+    let user = match load_user(&client, user_id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return HttpResponse::NotFound().json("User not found"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Database error");
+        }
+    };
+
+    match start_session(&client, &user, &req, None, &config).await {
+        Ok(token) => HttpResponse::Ok().json(MfaEnabledResponse {
+            recovery_codes,
+            token: Some(token),
+            user: Some(user),
+        }),
+        Err(response) => response,
+    }
+}
+
+#[post("/mfa/verify")]
+pub async fn mfa_verify(
+    req: HttpRequest,
+    body: ValidJson<MfaVerifyRequest>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    let claims = match verify_scoped_token(&body.mfa_token, MFA_CHALLENGE_SCOPE, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid or expired MFA token"),
+    };
+
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let mut client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    // The challenge token carries no email; the attempt is keyed by the account's
+    let email: String = match client
+        .query_opt("SELECT email FROM users WHERE id = $1", &[&user_id])
+        .await
+    {
+        Ok(Some(row)) => row.get("email"),
+        Ok(None) => return HttpResponse::Unauthorized().json("User not found"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Database error");
+        }
+    };
+
+    // Wrong codes back off the account like wrong passwords do
+    let attempt = throttle::Attempt::new(&req, &config, &email);
+    match throttle::retry_after(&client, &attempt).await {
+        Ok(0) => {}
+        Ok(seconds) => {
+            audit(&client, &attempt, Some(user_id), Method::Mfa, Outcome::Throttled).await;
+            return too_many_attempts(seconds);
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Database error");
+        }
+    }
+
+    // The body moves into the transaction below
+    let device_name = body.device_name.clone();
+
+    let result = match client.transaction().await {
+        Ok(tx) => {
+            async move {
+                let outcome = mfa::check_second_factor(
+                    &tx,
+                    user_id,
+                    body.factor.code.as_deref(),
+                    body.factor.recovery_code.as_deref(),
+                )
+                .await?;
+                tx.commit().await?;
+                Ok::<_, tokio_postgres::Error>(outcome)
+            }
+            .await
+        }
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(SecondFactor::Accepted) => {
+            audit(&client, &attempt, Some(user_id), Method::Mfa, Outcome::Success).await;
+        }
+        Ok(SecondFactor::Rejected) => {
+            audit(&client, &attempt, Some(user_id), Method::Mfa, Outcome::InvalidCode).await;
+            return HttpResponse::Unauthorized().json("Invalid code");
+        }
+        Ok(SecondFactor::LockedOut) => {
+            return HttpResponse::TooManyRequests().json("Too many wrong codes; try again later or use a recovery code")
+        }
+        Ok(SecondFactor::NotEnabled) => {
+            return HttpResponse::Unauthorized().json("Invalid or expired MFA token")
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Database error");
+        }
+    }
+
+    let user = match load_user(&client, user_id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return HttpResponse::Unauthorized().json("User not found"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Database error");
+        }
+    };
+
+    match start_session(&client, &user, &req, device_name.as_deref(), &config).await {
+        Ok(token) => HttpResponse::Ok().json(AuthResponse { token, user }),
+        Err(response) => response,
+    }
+}
+
+#[post("/mfa/disable")]
+pub async fn mfa_disable(
+    req: HttpRequest,
+    body: ValidJson<MfaDisableRequest>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let mut client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    // Users can't opt out of 2FA their role requires
+    let role = match load_role(&client, user_id).await {
+        Ok(Some(role)) => role,
+        Ok(None) => return HttpResponse::NotFound().json("User not found"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Database error");
+        }
+    };
+    match mfa_required_for(&client, role).await {
+        Ok(true) => {
+            return HttpResponse::Forbidden().json("Two-factor authentication is required for your role")
+        }
+        Ok(false) => {}
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Database error");
+        }
+    }
+
+    let password_hash: String = match client
+        .query_one("SELECT password_hash FROM users WHERE id = $1", &[&user_id])
+        .await
+    {
+        Ok(row) => row.get("password_hash"),
+        Err(_) => return HttpResponse::NotFound().json("User not found"),
+    };
+    match verify_password(&body.password, &password_hash) {
+        Ok(true) => {}
+        Ok(false) => return HttpResponse::Unauthorized().json("Invalid password"),
+        Err(_) => return HttpResponse::InternalServerError().json("Password verification error"),
+    }
+
+    let tx = match client.transaction().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let result = async {
+        let outcome = mfa::check_second_factor(
+            &tx,
+            user_id,
+            body.factor.code.as_deref(),
+            body.factor.recovery_code.as_deref(),
+        )
+        .await?;
+        if let SecondFactor::Accepted = outcome {
+            tx.execute("DELETE FROM mfa_recovery_codes WHERE user_id = $1", &[&user_id])
+                .await?;
+            tx.execute("DELETE FROM user_mfa WHERE user_id = $1", &[&user_id])
+                .await?;
+        }
+        tx.commit().await?;
+        Ok::<_, tokio_postgres::Error>(outcome)
+    }
+    .await;
+
+    match result {
+        Ok(SecondFactor::Accepted) => HttpResponse::Ok().json("Two-factor authentication disabled"),
+        Ok(SecondFactor::Rejected) => HttpResponse::Unauthorized().json("Invalid code"),
+        Ok(SecondFactor::LockedOut) => HttpResponse::TooManyRequests().json("Too many wrong codes; try again later or use a recovery code"),
+        Ok(SecondFactor::NotEnabled) => HttpResponse::BadRequest().json("Two-factor authentication is not enabled"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error disabling two-factor authentication")
+        }
+    }
+}
+
+#[post("/mfa/recovery-codes")]
+pub async fn mfa_recovery_codes(
+    req: HttpRequest,
+    body: ValidJson<MfaEnableRequest>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let mut client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let tx = match client.transaction().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    // New codes require a current TOTP code; a recovery code can't mint more of itself
+    let result = async {
+        let outcome = mfa::check_second_factor(&tx, user_id, Some(body.code.as_str()), None).await?;
+        let codes = match outcome {
+            SecondFactor::Accepted => Some(mfa::replace_recovery_codes(&tx, user_id).await?),
+            _ => None,
+        };
+        tx.commit().await?;
+        Ok::<_, tokio_postgres::Error>((outcome, codes))
+    }
+    .await;
+
+    match result {
+        Ok((_, Some(recovery_codes))) => HttpResponse::Ok().json(RecoveryCodesResponse { recovery_codes }),
+        Ok((SecondFactor::LockedOut, None)) => {
+            HttpResponse::TooManyRequests().json("Too many wrong codes; try again later or use a recovery code")
+        }
+        Ok((SecondFactor::NotEnabled, None)) => {
+            HttpResponse::BadRequest().json("Two-factor authentication is not enabled")
+        }
+        Ok(_) => HttpResponse::Unauthorized().json("Invalid code"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error generating recovery codes")
+        }
+    }
+}
+
+#[get("/.well-known/jwks.json")]
+pub async fn jwks(config: web::Data<Config>) -> impl Responder {
+    HttpResponse::Ok()
+        .insert_header(("Cache-Control", "public, max-age=300"))
+        .json(config.keys.jwks())
+}
+
+#[get("/profile")]
+pub async fn profile(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    // Get user from database
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let user_result = client
+        .query_one(
+            &format!("SELECT {} FROM users WHERE id = $1", USER_COLUMNS),
+            &[&user_id],
+        )
+        .await;
+
+    match user_result {
+        Ok(row) => {
+            let user = User::from_row(&row);
+            HttpResponse::Ok().json(user)
+        }
+        Err(_) => HttpResponse::NotFound().json("User not found"),
+    }
+}
+
+#[put("/profile/timezone")]
+pub async fn update_timezone(
+    req: HttpRequest,
+    body: ValidJson<TimezoneRequest>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    // Only IANA names (e.g. "Asia/Jakarta") pass validation; PostgreSQL understands the same set
+    let timezone = canonical_timezone(&body.timezone);
+
+    let mut client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let tx = match client.transaction().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let updated = tx
+        .query_opt(
+            &format!("UPDATE users SET timezone = $2 WHERE id = $1 RETURNING {}", USER_COLUMNS),
+            &[&user_id, &timezone],
+        )
+        .await;
+
+    let user = match updated {
+        Ok(Some(row)) => User::from_row(&row),
+        Ok(None) => return HttpResponse::NotFound().json("User not found"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Error updating timezone");
+        }
+    };
+
+    // Past reading now falls on different local days
+    let result = match aggregation::rebuild_user(&tx, user_id).await {
+        Ok(_) => tx.commit().await,
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(_) => HttpResponse::Ok().json(user),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error updating timezone")
+        }
+    }
+}
+
+#[put("/profile")]
+pub async fn update_profile(
+    req: HttpRequest,
+    body: ValidJson<UpdateProfileRequest>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    mailer: web::Data<dyn Mailer>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let username = body.username.as_deref().map(|username| username.trim().to_string());
+
+    // Stricter than the `email` rule: anything a mail server would refuse
+    let mut errors = FieldErrors::new();
+    let email = match &body.email {
+        Some(email) => match email.trim().parse::<Address>() {
+            Ok(address) => Some(address.to_string()),
+            Err(_) => {
+                errors.add("email", "Invalid email address");
+                None
+            }
+        },
+        None => None,
+    };
+    if let Some(response) = errors.response() {
+        return response;
+    }
+
+    let mut client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let tx = match client.transaction().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let result = async {
+        let current = match tx
+            .query_opt(
+                "SELECT email, password_hash FROM users WHERE id = $1 FOR UPDATE",
+                &[&user_id],
+            )
+            .await?
+        {
+            Some(row) => row,
+            None => return Ok(Err(HttpResponse::NotFound().json("User not found"))),
+        };
+
+        if let Some(username) = &username {
+            let taken: bool = tx
+                .query_one(
+                    "SELECT EXISTS (SELECT 1 FROM users WHERE LOWER(username) = LOWER($1) AND id <> $2)",
+                    &[username, &user_id],
+                )
+                .await?
+                .get(0);
+            if taken {
+                return Ok(Err(HttpResponse::Conflict().json("Username is already taken")));
+            }
+
+            tx.execute("UPDATE users SET username = $2 WHERE id = $1", &[&user_id, username])
+                .await?;
+        }
+
+        let mut issued = None;
+        match &email {
+            // Asking for the current address again cancels a pending change
+            Some(email) if *email == current.get::<_, String>("email") => {
+                tx.execute("UPDATE users SET pending_email = NULL WHERE id = $1", &[&user_id])
+                    .await?;
+            }
+            Some(email) => {
+                // Whoever holds a stolen token must not be able to take over the account
+                let password_hash: String = current.get("password_hash");
+                let confirmed = body
+                    .current_password
+                    .as_deref()
+                    .map(|password| verify_password(password, &password_hash).unwrap_or(false))
+                    .unwrap_or(false);
+                if !confirmed {
+                    return Ok(Err(HttpResponse::Forbidden().json("Current password is incorrect")));
+                }
+
+                let taken: bool = tx
+                    .query_one(
+                        "SELECT EXISTS (SELECT 1 FROM users WHERE email = $1 AND id <> $2)",
+                        &[email, &user_id],
+                    )
+                    .await?
+                    .get(0);
+                if taken {
+                    return Ok(Err(HttpResponse::Conflict().json("Email is already in use")));
+                }
+
+                let wait = verification::seconds_until_next_send(&tx, user_id).await?;
+                if wait > 0 {
+                    return Ok(Err(HttpResponse::TooManyRequests()
+                        .insert_header(("Retry-After", wait.to_string()))
+                        .json("Please wait before requesting another verification email")));
+                }
+
+                // The current address stays in use until the new one is verified
+                tx.execute("UPDATE users SET pending_email = $2 WHERE id = $1", &[&user_id, email])
+                    .await?;
+                issued = Some(verification::issue(&tx, &config, user_id, email).await?);
+            }
+            None => {}
+        }
+
+        let row = tx
+            .query_one(&format!("SELECT {} FROM users WHERE id = $1", USER_COLUMNS), &[&user_id])
+            .await?;
+        tx.commit().await?;
+        Ok::<_, tokio_postgres::Error>(Ok((User::from_row(&row), issued)))
+    }
+    .await;
+
+    let (user, issued) = match result {
+        Ok(Ok(updated)) => updated,
+        Ok(Err(response)) => return response,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Error updating profile");
+        }
+    };
+
+    if let (Some(issued), Some(pending_email)) = (issued, &user.pending_email) {
+        if let Err(e) = verification::send(mailer.get_ref(), &config, &user.username, pending_email, &issued).await {
+            eprintln!("Mail error: {}", e);
+        }
+    }
+
+    HttpResponse::Ok().json(user)
+}
+
+#[put("/profile/password")]
+pub async fn change_password(
+    req: HttpRequest,
+    body: ValidJson<ChangePasswordRequest>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let mut client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let current = match client
+        .query_opt("SELECT username, email, password_hash FROM users WHERE id = $1", &[&user_id])
+        .await
+    {
+        Ok(Some(row)) => row,
+        Ok(None) => return HttpResponse::NotFound().json("User not found"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Database error");
+        }
+    };
+
+    if !verify_password(&body.current_password, current.get("password_hash")).unwrap_or(false) {
+        return HttpResponse::Forbidden().json("Current password is incorrect");
+    }
+
+    let mut errors = FieldErrors::new();
+    errors.extend(
+        "new_password",
+        config
+            .password_policy
+            .check(&body.new_password, &[current.get("username"), current.get("email")]),
+    );
+    if let Some(response) = errors.response() {
+        return response;
+    }
+
+    let hashed_password = match hash_password(&body.new_password, &config) {
+        Ok(hash) => hash,
+        Err(_) => return HttpResponse::InternalServerError().json("Password hashing error"),
+    };
+
+    let tx = match client.transaction().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    // Every token and session from before now stops working; the token returned below
+    // belongs to a new session and is issued after the cutoff, going by the same clock
+    let revoked_before = chrono::Utc::now();
+    let result = async {
+        let row = tx
+            .query_opt(
+                &format!(
+                    "UPDATE users SET password_hash = $2, tokens_invalid_before = $3 WHERE id = $1 RETURNING {}",
+                    USER_COLUMNS
+                ),
+                &[&user_id, &hashed_password, &revoked_before],
+            )
+            .await?;
+        sessions::revoke_all(&tx, user_id).await?;
+        tx.commit().await?;
+        Ok::<_, tokio_postgres::Error>(row.map(|row| User::from_row(&row)))
+    }
+    .await;
+
+    let user = match result {
+        Ok(Some(user)) => user,
+        Ok(None) => return HttpResponse::NotFound().json("User not found"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Error changing password");
+        }
+    };
+
+    match start_session(&client, &user, &req, None, &config).await {
+        Ok(token) => HttpResponse::Ok().json(AuthResponse { token, user }),
+        Err(response) => response,
+    }
+}
+
+#[put("/profile/preferences")]
+pub async fn update_preferences(
+    req: HttpRequest,
+    body: ValidJson<PreferencesRequest>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let timezone = body.timezone.as_deref().map(canonical_timezone);
+
+    let reader_theme = body.reader_theme.map(|theme| theme.to_string());
+
+    let mut client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let tx = match client.transaction().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let result = async {
+        let row = match tx
+            .query_opt(
+                &format!(
+                    "UPDATE users
+                     SET language = COALESCE($2, language),
+                         timezone = COALESCE($3, timezone),
+                         reader_theme = COALESCE($4, reader_theme)
+                     WHERE id = $1
+                     RETURNING {}",
+                    USER_COLUMNS
+                ),
+                &[&user_id, &body.language, &timezone, &reader_theme],
+            )
+            .await?
+        {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+
+        // Past reading now falls on different local days
+        if timezone.is_some() {
+            aggregation::rebuild_user(&tx, user_id).await?;
+        }
+
+        tx.commit().await?;
+        Ok::<_, tokio_postgres::Error>(Some(User::from_row(&row)))
+    }
+    .await;
+
+    match result {
+        Ok(Some(user)) => HttpResponse::Ok().json(user),
+        Ok(None) => HttpResponse::NotFound().json("User not found"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error updating preferences")
+        }
+    }
+}
+
+#[post("/profile/avatar")]
+pub async fn upload_avatar(
+    req: HttpRequest,
+    mut payload: Multipart,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    // Other form fields are skipped
+    let mut image = None;
+    while let Some(field) = payload.next().await {
+        let mut field = match field {
+            Ok(field) => field,
+            Err(_) => return HttpResponse::BadRequest().json("Invalid multipart body"),
+        };
+        if field.name() != Some("avatar") {
+            continue;
+        }
+
+        let mut bytes = Vec::new();
+        while let Some(chunk) = field.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(_) => return HttpResponse::BadRequest().json("Invalid multipart body"),
+            };
+            if bytes.len() + chunk.len() > avatars::MAX_AVATAR_BYTES {
+                return HttpResponse::PayloadTooLarge()
+                    .json(format!("Avatar must be at most {} MB", avatars::MAX_AVATAR_BYTES / (1024 * 1024)));
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+        image = Some(bytes);
+        break;
+    }
+
+    let image = match image {
+        Some(image) => image,
+        None => return HttpResponse::BadRequest().json("No avatar file in the request"),
+    };
+
+    let extension = match avatars::detect_extension(&image) {
+        Some(extension) => extension,
+        None => return HttpResponse::UnsupportedMediaType().json("Avatar must be a PNG, JPEG or WebP image"),
+    };
+
+    let name = match avatars::store(&config, user_id, extension, &image).await {
+        Ok(name) => name,
+        Err(e) => {
+            eprintln!("Avatar storage error: {}", e);
+            return HttpResponse::InternalServerError().json("Error saving avatar");
+        }
+    };
+
+    match replace_avatar(&pool, user_id, Some(&name)).await {
+        Ok(Some((user, previous))) => {
+            if let Some(previous) = previous {
+                avatars::remove(&config, &previous).await;
+            }
+            HttpResponse::Ok().json(user)
+        }
+        Ok(None) => {
+            avatars::remove(&config, &name).await;
+            HttpResponse::NotFound().json("User not found")
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            avatars::remove(&config, &name).await;
+            HttpResponse::InternalServerError().json("Error saving avatar")
+        }
+    }
+}
+
+#[delete("/profile/avatar")]
+pub async fn delete_avatar(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    match replace_avatar(&pool, user_id, None).await {
+        Ok(Some((user, previous))) => {
+            if let Some(previous) = previous {
+                avatars::remove(&config, &previous).await;
+            }
+            HttpResponse::Ok().json(user)
+        }
+        Ok(None) => HttpResponse::NotFound().json("User not found"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error removing avatar")
+        }
+    }
+}
+
+// Public so that avatars work in plain <img> tags; the names are not guessable
+#[get("/avatars/{name}")]
+pub async fn avatar(
+    req: HttpRequest,
+    path: web::Path<String>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    let name = path.into_inner();
+    if !avatars::is_valid_name(&name) {
+        return HttpResponse::NotFound().json("Avatar not found");
+    }
+
+    match NamedFile::open(avatars::path_of(&config, &name)) {
+        Ok(file) => file.into_response(&req),
+        Err(_) => HttpResponse::NotFound().json("Avatar not found"),
+    }
+}
+
+#[delete("/profile")]
+pub async fn delete_account(
+    req: HttpRequest,
+    body: ValidJson<DeleteAccountRequest>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let mut client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let tx = match client.transaction().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let result = async {
+        let row = match tx
+            .query_opt(
+                "SELECT password_hash, avatar_path,
+                        EXISTS (
+                            SELECT 1 FROM user_identities i
+                            WHERE i.user_id = users.id
+                              AND i.last_login_at > CURRENT_TIMESTAMP - make_interval(secs => $2)
+                        ) AS recent_sso
+                 FROM users WHERE id = $1 FOR UPDATE",
+                &[&user_id, &RECENT_SIGN_IN_SECONDS],
+            )
+            .await?
+        {
+            Some(row) => row,
+            None => return Ok(Err(HttpResponse::NotFound().json("User not found"))),
+        };
+
+        match &body.password {
+            Some(password) => {
+                if !verify_password(password, &row.get::<_, String>("password_hash")).unwrap_or(false) {
+                    return Ok(Err(HttpResponse::Forbidden().json("Password is incorrect")));
+                }
+            }
+            // Accounts provisioned by single sign-on got a random password nobody knows, so a
+            // token from a sign-in at the provider moments ago confirms it is really them
+            None => {
+                let token_age = chrono::Utc::now().timestamp() - claims.iat as i64;
+                if !row.get::<_, bool>("recent_sso") || token_age as f64 > RECENT_SIGN_IN_SECONDS {
+                    return Ok(Err(HttpResponse::Forbidden()
+                        .json("Enter your password, or sign in again with single sign-on, to delete the account")));
+                }
+            }
+        }
+
+        // Votes disappear with the account; the counts kept on other users' reviews must follow
+        tx.execute(
+            "UPDATE book_reviews r
+             SET helpful_count = r.helpful_count - CASE WHEN v.helpful THEN 1 ELSE 0 END,
+                 vote_count = r.vote_count - 1
+             FROM review_votes v
+             WHERE v.review_id = r.id AND v.user_id = $1",
+            &[&user_id],
+        )
+        .await?;
+
+        // Progress and analytics predate the migrations and may not cascade
+        tx.execute("DELETE FROM user_reading_progress WHERE user_id = $1", &[&user_id])
+            .await?;
+        tx.execute("DELETE FROM user_analytics WHERE user_id = $1", &[&user_id])
+            .await?;
+
+        // Their rows cascade, but the archives on disk have to be removed too
+        let exports: Vec<String> = tx
+            .query(
+                "SELECT file_path FROM data_exports WHERE user_id = $1 AND file_path IS NOT NULL",
+                &[&user_id],
+            )
+            .await?
+            .iter()
+            .map(|row| row.get("file_path"))
+            .collect();
+
+        // Everything else owned by the user goes with it through ON DELETE CASCADE
+        tx.execute("DELETE FROM users WHERE id = $1", &[&user_id]).await?;
+        tx.commit().await?;
+        Ok::<_, tokio_postgres::Error>(Ok((row.get::<_, Option<String>>("avatar_path"), exports)))
+    }
+    .await;
+
+    match result {
+        Ok(Ok((avatar_path, exports))) => {
+            if let Some(avatar_path) = avatar_path {
+                avatars::remove(&config, &avatar_path).await;
+            }
+            for name in exports {
+                privacy::export::remove(&config.data_export_storage_path, &name).await;
+            }
+            HttpResponse::Ok().json("Account deleted")
+        }
+        Ok(Err(response)) => response,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error deleting account")
+        }
+    }
+}
+
+async fn load_user(
+    client: &deadpool_postgres::Client,
+    user_id: Uuid,
+) -> Result<Option<User>, tokio_postgres::Error> {
+    let row = client
+        .query_opt(
+            &format!("SELECT {} FROM users WHERE id = $1", USER_COLUMNS),
+            &[&user_id],
+        )
+        .await?;
+
+    Ok(row.map(|row| User::from_row(&row)))
+}
+
+// Marks the address verified, making it the user's email if it was the pending one.
+// Affects no row if it is neither, or if another account has taken it meanwhile.
+async fn confirm_email(
+    tx: &deadpool_postgres::Transaction<'_>,
+    user_id: Uuid,
+    email: &str,
+) -> Result<u64, tokio_postgres::Error> {
+    tx.execute(
+        "UPDATE users
+         SET email = $2,
+             pending_email = CASE WHEN pending_email = $2 THEN NULL ELSE pending_email END,
+             email_verified_at = CURRENT_TIMESTAMP
+         WHERE id = $1 AND (email = $2 OR pending_email = $2)
+           AND NOT EXISTS (SELECT 1 FROM users WHERE email = $2 AND id <> $1)",
+        &[&user_id, &email],
+    )
+    .await
+}
+
+// Sets the user's avatar and returns the updated user with the file it replaced
+async fn replace_avatar(
+    pool: &DbPool,
+    user_id: Uuid,
+    name: Option<&str>,
+) -> Result<Option<(User, Option<String>)>, Box<dyn std::error::Error>> {
+    let mut client = pool.get().await?;
+    let tx = client.transaction().await?;
+
+    let previous: Option<String> = match tx
+        .query_opt("SELECT avatar_path FROM users WHERE id = $1 FOR UPDATE", &[&user_id])
+        .await?
+    {
+        Some(row) => row.get("avatar_path"),
+        None => return Ok(None),
+    };
+
+    let row = tx
+        .query_one(
+            &format!("UPDATE users SET avatar_path = $2 WHERE id = $1 RETURNING {}", USER_COLUMNS),
+            &[&user_id, &name],
+        )
+        .await?;
+    tx.commit().await?;
+
+    Ok(Some((User::from_row(&row), previous)))
+}
+
+// Spelling chrono-tz uses for a zone that passed `validation::known_timezone`
+fn canonical_timezone(timezone: &str) -> String {
+    timezone
+        .parse::<chrono_tz::Tz>()
+        .map(|tz| tz.name().to_string())
+        .unwrap_or_else(|_| timezone.to_string())
+}
+
+// Records the session of a completed sign-in and issues its access token
+async fn start_session(
+    client: &deadpool_postgres::Client,
+    user: &User,
+    req: &HttpRequest,
+    device_name: Option<&str>,
+    config: &Config,
+) -> Result<String, HttpResponse> {
+    let session_id = match sessions::start(client, user.id, req, device_name, config).await {
+        Ok(id) => id,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Err(HttpResponse::InternalServerError().json("Database error"));
+        }
+    };
+
+    generate_token(user, session_id, config)
+        .map_err(|_| HttpResponse::InternalServerError().json("Token generation error"))
+}
+
+// Failures are logged; the old hash keeps working until the next login
+async fn rehash_password(
+    client: &deadpool_postgres::Client,
+    user_id: Uuid,
+    password: &str,
+    old_hash: &str,
+    config: &Config,
+) {
+    let new_hash = match hash_password(password, config) {
+        Ok(hash) => hash,
+        Err(e) => {
+            eprintln!("Password hashing error: {}", e);
+            return;
+        }
+    };
+
+    // Skipped if the password was changed in the meantime
+    if let Err(e) = client
+        .execute(
+            "UPDATE users SET password_hash = $2 WHERE id = $1 AND password_hash = $3",
+            &[&user_id, &new_hash, &old_hash],
+        )
+        .await
+    {
+        eprintln!("Database error: {}", e);
+    }
+}
+
+// Audit failures are logged but don't fail the login itself
+async fn audit(
+    client: &deadpool_postgres::Client,
+    attempt: &throttle::Attempt,
+    user_id: Option<Uuid>,
+    method: Method,
+    outcome: Outcome,
+) {
+    if let Err(e) = throttle::record(client, attempt, user_id, method, outcome).await {
+        eprintln!("Database error: {}", e);
+    }
+}
+
+fn too_many_attempts(retry_after: i64) -> HttpResponse {
+    HttpResponse::TooManyRequests()
+        .insert_header(("Retry-After", retry_after.to_string()))
+        .json("Too many failed sign-in attempts; try again later")
 }
\ No newline at end of file
@@ -0,0 +1,285 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use jsonwebtoken::{decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use rsa::pkcs1::{DecodeRsaPrivateKey, DecodeRsaPublicKey};
+use rsa::pkcs8::{DecodePrivateKey, DecodePublicKey};
+use rsa::traits::PublicKeyParts;
+use rsa::{RsaPrivateKey, RsaPublicKey};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+#[derive(Debug, thiserror::Error)]
+pub enum KeyError {
+    #[error("Failed to read key file {0}: {1}")]
+    Io(String, std::io::Error),
+    #[error("Key file {0} holds neither an RSA nor an Ed25519 key")]
+    Unsupported(String),
+    #[error("Signing key {0} not found or has no private part")]
+    MissingSigningKey(String),
+    #[error("Invalid key: {0}")]
+    Jwt(#[from] jsonwebtoken::errors::Error),
+}
+
+struct VerificationKey {
+    algorithm: Algorithm,
+    key: DecodingKey,
+}
+
+// Tokens from before key ids were introduced were HS256 with JWT_SECRET and have no
+// kid, iss or aud. They are only taken until every one of them has expired.
+struct LegacyKey {
+    key: DecodingKey,
+    accepted_until: DateTime<Utc>,
+}
+
+/// Keys for issuing and checking access tokens. Tokens are signed with one
+/// active key and carry its id as `kid`; every key in the ring stays valid for
+/// verification, so a key can be rotated out without signing anyone out.
+pub struct KeyRing {
+    issuer: String,
+    audience: String,
+    signing_kid: String,
+    signing_algorithm: Algorithm,
+    signing_key: EncodingKey,
+    verification_keys: HashMap<String, VerificationKey>,
+    legacy_key: Option<LegacyKey>,
+    jwks: serde_json::Value,
+}
+
+impl KeyRing {
+    /// Loads every `<kid>.pem` in `dir`. Private keys (RSA or Ed25519, PKCS#8 or
+    /// PKCS#1) can sign; public keys are kept for verifying tokens of retired keys.
+    /// Kid-less tokens signed with `legacy_secret` are accepted until `legacy_until`.
+    pub fn from_dir(
+        dir: &Path,
+        signing_kid: &str,
+        legacy_secret: Option<&str>,
+        legacy_until: Option<DateTime<Utc>>,
+        issuer: String,
+        audience: String,
+    ) -> Result<Self, KeyError> {
+        let entries = fs::read_dir(dir).map_err(|e| KeyError::Io(dir.display().to_string(), e))?;
+
+        let mut signing = None;
+        let mut verification_keys = HashMap::new();
+        let mut jwks = Vec::new();
+
+        for entry in entries {
+            let path = entry.map_err(|e| KeyError::Io(dir.display().to_string(), e))?.path();
+            let kid = match (path.extension(), path.file_stem()) {
+                (Some(extension), Some(stem)) if extension == "pem" => stem.to_string_lossy().to_string(),
+                _ => continue,
+            };
+            let pem = fs::read_to_string(&path).map_err(|e| KeyError::Io(path.display().to_string(), e))?;
+            let loaded = load_pem(&pem).ok_or_else(|| KeyError::Unsupported(path.display().to_string()))?;
+
+            jwks.push(loaded.jwk(&kid));
+            verification_keys.insert(
+                kid.clone(),
+                VerificationKey {
+                    algorithm: loaded.algorithm,
+                    key: loaded.decoding_key()?,
+                },
+            );
+
+            if kid == signing_kid {
+                if let Some(encoding_key) = loaded.encoding_key(&pem)? {
+                    signing = Some((loaded.algorithm, encoding_key));
+                }
+            }
+        }
+
+        let (signing_algorithm, signing_key) =
+            signing.ok_or_else(|| KeyError::MissingSigningKey(signing_kid.to_string()))?;
+
+        Ok(KeyRing {
+            issuer,
+            audience,
+            signing_kid: signing_kid.to_string(),
+            signing_algorithm,
+            signing_key,
+            verification_keys,
+            legacy_key: legacy_secret.zip(legacy_until).map(|(secret, until)| LegacyKey::new(secret, until)),
+            jwks: json!({ "keys": jwks }),
+        })
+    }
+
+    /// HS256 with a shared secret, for installations without a key pair. Nothing
+    /// is published in the JWKS, since the secret can't be shared.
+    pub fn from_secret(
+        secret: &str,
+        legacy_until: Option<DateTime<Utc>>,
+        issuer: String,
+        audience: String,
+    ) -> Self {
+        // The kid only tells tokens of different secrets apart; it reveals nothing about the secret
+        let digest = format!("{:x}", Sha256::digest(secret.as_bytes()));
+        let kid = format!("hs256-{}", &digest[..8]);
+
+        let mut verification_keys = HashMap::new();
+        verification_keys.insert(
+            kid.clone(),
+            VerificationKey {
+                algorithm: Algorithm::HS256,
+                key: DecodingKey::from_secret(secret.as_bytes()),
+            },
+        );
+
+        KeyRing {
+            issuer,
+            audience,
+            signing_kid: kid,
+            signing_algorithm: Algorithm::HS256,
+            signing_key: EncodingKey::from_secret(secret.as_bytes()),
+            verification_keys,
+            legacy_key: legacy_until.map(|until| LegacyKey::new(secret, until)),
+            jwks: json!({ "keys": [] }),
+        }
+    }
+
+    pub fn issuer(&self) -> &str {
+        &self.issuer
+    }
+
+    pub fn audience(&self) -> &str {
+        &self.audience
+    }
+
+    /// Public keys as a JWK set, for `/.well-known/jwks.json`.
+    pub fn jwks(&self) -> &serde_json::Value {
+        &self.jwks
+    }
+
+    pub fn sign<T: Serialize>(&self, claims: &T) -> Result<String, jsonwebtoken::errors::Error> {
+        let mut header = Header::new(self.signing_algorithm);
+        header.kid = Some(self.signing_kid.clone());
+        encode(&header, claims, &self.signing_key)
+    }
+
+    /// Checks signature, expiry, issuer and audience.
+    pub fn verify<T: DeserializeOwned>(&self, token: &str) -> Result<T, jsonwebtoken::errors::Error> {
+        let header = decode_header(token)?;
+
+        let (algorithm, key, validate_issuer) = match header.kid.as_deref() {
+            Some(kid) => match self.verification_keys.get(kid) {
+                Some(key) => (key.algorithm, &key.key, true),
+                None => return Err(jsonwebtoken::errors::ErrorKind::InvalidToken.into()),
+            },
+            // Legacy tokens predate the iss and aud claims
+            None => match &self.legacy_key {
+                Some(legacy) if Utc::now() < legacy.accepted_until => (Algorithm::HS256, &legacy.key, false),
+                _ => return Err(jsonwebtoken::errors::ErrorKind::InvalidToken.into()),
+            },
+        };
+
+        // The algorithm comes from the key, never from the token header
+        let mut validation = Validation::new(algorithm);
+        if validate_issuer {
+            validation.set_issuer(&[&self.issuer]);
+            validation.set_audience(&[&self.audience]);
+        }
+
+        Ok(decode::<T>(token, key, &validation)?.claims)
+    }
+}
+
+impl LegacyKey {
+    fn new(secret: &str, accepted_until: DateTime<Utc>) -> Self {
+        LegacyKey {
+            key: DecodingKey::from_secret(secret.as_bytes()),
+            accepted_until,
+        }
+    }
+}
+
+impl std::fmt::Debug for KeyRing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut kids: Vec<&String> = self.verification_keys.keys().collect();
+        kids.sort();
+        f.debug_struct("KeyRing")
+            .field("issuer", &self.issuer)
+            .field("audience", &self.audience)
+            .field("signing_kid", &self.signing_kid)
+            .field("verification_kids", &kids)
+            .finish()
+    }
+}
+
+enum PublicKey {
+    Rsa { n: String, e: String },
+    Ed25519 { x: String },
+}
+
+struct LoadedKey {
+    algorithm: Algorithm,
+    public: PublicKey,
+    private: bool,
+}
+
+impl LoadedKey {
+    fn jwk(&self, kid: &str) -> serde_json::Value {
+        match &self.public {
+            PublicKey::Rsa { n, e } => json!({
+                "kty": "RSA", "use": "sig", "alg": "RS256", "kid": kid, "n": n, "e": e,
+            }),
+            PublicKey::Ed25519 { x } => json!({
+                "kty": "OKP", "use": "sig", "alg": "EdDSA", "crv": "Ed25519", "kid": kid, "x": x,
+            }),
+        }
+    }
+
+    fn decoding_key(&self) -> Result<DecodingKey, jsonwebtoken::errors::Error> {
+        match &self.public {
+            PublicKey::Rsa { n, e } => DecodingKey::from_rsa_components(n, e),
+            PublicKey::Ed25519 { x } => DecodingKey::from_ed_components(x),
+        }
+    }
+
+    fn encoding_key(&self, pem: &str) -> Result<Option<EncodingKey>, jsonwebtoken::errors::Error> {
+        if !self.private {
+            return Ok(None);
+        }
+        let key = match self.public {
+            PublicKey::Rsa { .. } => EncodingKey::from_rsa_pem(pem.as_bytes())?,
+            PublicKey::Ed25519 { .. } => EncodingKey::from_ed_pem(pem.as_bytes())?,
+        };
+        Ok(Some(key))
+    }
+}
+
+fn load_pem(pem: &str) -> Option<LoadedKey> {
+    let rsa = |key: RsaPublicKey, private: bool| LoadedKey {
+        algorithm: Algorithm::RS256,
+        public: PublicKey::Rsa {
+            n: URL_SAFE_NO_PAD.encode(key.n().to_bytes_be()),
+            e: URL_SAFE_NO_PAD.encode(key.e().to_bytes_be()),
+        },
+        private,
+    };
+    let ed25519 = |bytes: [u8; 32], private: bool| LoadedKey {
+        algorithm: Algorithm::EdDSA,
+        public: PublicKey::Ed25519 {
+            x: URL_SAFE_NO_PAD.encode(bytes),
+        },
+        private,
+    };
+
+    if let Ok(key) = RsaPrivateKey::from_pkcs8_pem(pem).or_else(|_| RsaPrivateKey::from_pkcs1_pem(pem)) {
+        return Some(rsa(key.to_public_key(), true));
+    }
+    if let Ok(key) = RsaPublicKey::from_public_key_pem(pem).or_else(|_| RsaPublicKey::from_pkcs1_pem(pem)) {
+        return Some(rsa(key, false));
+    }
+    if let Ok(key) = ed25519_dalek::SigningKey::from_pkcs8_pem(pem) {
+        return Some(ed25519(key.verifying_key().to_bytes(), true));
+    }
+    if let Ok(key) = ed25519_dalek::VerifyingKey::from_public_key_pem(pem) {
+        return Some(ed25519(key.to_bytes(), false));
+    }
+    None
+}
\ No newline at end of file
@@ -0,0 +1,121 @@
+use deadpool_postgres::Transaction;
+use uuid::Uuid;
+
+use super::totp;
+
+// Wrong second factors accepted before codes are refused for LOCKOUT_SECONDS
+pub const MAX_FAILED_ATTEMPTS: i32 = 5;
+
+// A correct password does not lift the lockout, or the password alone would buy
+// unlimited guesses; only time or a recovery code does
+pub const LOCKOUT_SECONDS: f64 = 15.0 * 60.0;
+
+pub enum SecondFactor {
+    Accepted,
+    Rejected,
+    // Too many wrong codes recently; recovery codes are still accepted
+    LockedOut,
+    NotEnabled,
+}
+
+/// Checks a TOTP code and, when that is missing or wrong, a recovery code, and
+/// records the outcome. A single failure is counted when neither gets through.
+/// The caller commits the transaction whatever the result, so failures are counted.
+pub async fn check_second_factor(
+    tx: &Transaction<'_>,
+    user_id: Uuid,
+    code: Option<&str>,
+    recovery_code: Option<&str>,
+) -> Result<SecondFactor, tokio_postgres::Error> {
+    let row = tx
+        .query_opt(
+            "SELECT secret, last_used_step,
+                    COALESCE(failed_attempts >= $2 AND last_failed_at > CURRENT_TIMESTAMP - make_interval(secs => $3), FALSE)
+                        AS locked_out
+             FROM user_mfa
+             WHERE user_id = $1 AND enabled_at IS NOT NULL
+             FOR UPDATE",
+            &[&user_id, &MAX_FAILED_ATTEMPTS, &LOCKOUT_SECONDS],
+        )
+        .await?;
+
+    let row = match row {
+        Some(row) => row,
+        None => return Ok(SecondFactor::NotEnabled),
+    };
+
+    let locked_out: bool = row.get("locked_out");
+    if locked_out && recovery_code.is_none() {
+        return Ok(SecondFactor::LockedOut);
+    }
+
+    // While locked out only a recovery code gets through
+    if let Some(code) = code.filter(|_| !locked_out) {
+        let secret: String = row.get("secret");
+        let now = chrono::Utc::now().timestamp();
+        if let Some(step) = totp::verify(&secret, code, now, row.get("last_used_step")) {
+            tx.execute(
+                "UPDATE user_mfa SET last_used_step = $2, failed_attempts = 0, last_failed_at = NULL WHERE user_id = $1",
+                &[&user_id, &step],
+            )
+            .await?;
+            return Ok(SecondFactor::Accepted);
+        }
+    }
+
+    if let Some(recovery_code) = recovery_code {
+        let used = tx
+            .execute(
+                "UPDATE mfa_recovery_codes SET used_at = CURRENT_TIMESTAMP
+                 WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL",
+                &[&user_id, &totp::hash_recovery_code(recovery_code)],
+            )
+            .await?;
+        if used > 0 {
+            tx.execute(
+                "UPDATE user_mfa SET failed_attempts = 0, last_failed_at = NULL WHERE user_id = $1",
+                &[&user_id],
+            )
+            .await?;
+            return Ok(SecondFactor::Accepted);
+        }
+    }
+
+    // Failures older than the lockout no longer count
+    tx.execute(
+        "UPDATE user_mfa SET
+             failed_attempts = CASE
+                 WHEN last_failed_at > CURRENT_TIMESTAMP - make_interval(secs => $2) THEN failed_attempts + 1
+                 ELSE 1
+             END,
+             last_failed_at = CURRENT_TIMESTAMP
+         WHERE user_id = $1",
+        &[&user_id, &LOCKOUT_SECONDS],
+    )
+    .await?;
+
+    if locked_out {
+        return Ok(SecondFactor::LockedOut);
+    }
+    Ok(SecondFactor::Rejected)
+}
+
+/// Replaces all of the user's recovery codes and returns the new ones in plain text.
+pub async fn replace_recovery_codes(
+    tx: &Transaction<'_>,
+    user_id: Uuid,
+) -> Result<Vec<String>, tokio_postgres::Error> {
+    let codes = totp::generate_recovery_codes();
+
+    tx.execute("DELETE FROM mfa_recovery_codes WHERE user_id = $1", &[&user_id])
+        .await?;
+    for code in &codes {
+        tx.execute(
+            "INSERT INTO mfa_recovery_codes (user_id, code_hash) VALUES ($1, $2)",
+            &[&user_id, &totp::hash_recovery_code(code)],
+        )
+        .await?;
+    }
+
+    Ok(codes)
+}
\ No newline at end of file
@@ -1,29 +1,123 @@
+pub mod api_keys;
+pub mod avatars;
 pub mod handlers;
+pub mod keys;
+pub mod mfa;
+pub mod password_policy;
 pub mod models;
+pub mod revocation;
+pub mod sessions;
+pub mod throttle;
+pub mod totp;
+pub mod verification;
 
-use actix_web::web;
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use actix_web::{web, HttpRequest, HttpResponse};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD}, Engine};
+use jsonwebtoken::errors::ErrorKind;
 use chrono::{Duration, Utc};
+use rand::RngCore;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
 use crate::config::Config;
-use models::User;
+use models::{Role, User};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String,  // user_id
     pub exp: usize,   // expiration time
     pub iat: usize,   // issued at
+    // Tokens from before issuer and audience were added lack them
+    #[serde(default)]
+    pub iss: String,
+    #[serde(default)]
+    pub aud: String,
+    // Set on tokens that only allow one step of the login, e.g. the MFA challenge
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    // Session in `user_sessions` the token belongs to; revoking it revokes the token.
+    // Missing on scoped tokens, API key exchanges and tokens from before sessions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
 }
 
-pub fn hash_password(password: &str) -> Result<String, bcrypt::BcryptError> {
-    bcrypt::hash(password, 10)
+// Scope of the token returned by `login` when a TOTP code is still needed
+pub const MFA_CHALLENGE_SCOPE: &str = "mfa";
+
+// Scope of the token returned by `login` when the user's role requires 2FA
+// but none is set up yet; it only allows enrolling
+pub const MFA_ENROLLMENT_SCOPE: &str = "mfa_enroll";
+
+// Lifetime of MFA challenge and enrollment tokens
+pub const MFA_TOKEN_EXPIRATION_SECONDS: i64 = 300;
+
+#[derive(Debug, thiserror::Error)]
+pub enum PasswordError {
+    #[error("Argon2 error: {0}")]
+    Argon2(#[from] argon2::password_hash::Error),
+    #[error("bcrypt error: {0}")]
+    Bcrypt(#[from] bcrypt::BcryptError),
+}
+
+/// Argon2id hash in PHC string format, with the parameters from the configuration.
+pub fn hash_password(password: &str, config: &Config) -> Result<String, PasswordError> {
+    let mut salt = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut salt);
+    let salt = SaltString::encode_b64(&salt)?;
+
+    let argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, config.argon2_params.clone());
+    Ok(argon2.hash_password(password.as_bytes(), &salt)?.to_string())
+}
+
+/// Checks a password against an Argon2 hash, or a bcrypt hash from before Argon2id
+/// was introduced. Argon2 hashes carry their own parameters.
+pub fn verify_password(password: &str, hash: &str) -> Result<bool, PasswordError> {
+    if !hash.starts_with("$argon2") {
+        return Ok(bcrypt::verify(password, hash)?);
+    }
+
+    let parsed = PasswordHash::new(hash)?;
+    match Argon2::default().verify_password(password.as_bytes(), &parsed) {
+        Ok(()) => Ok(true),
+        Err(argon2::password_hash::Error::Password) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Whether a hash should be replaced at the next successful login: bcrypt hashes,
+/// and Argon2 hashes made with parameters other than the configured ones.
+pub fn needs_rehash(hash: &str, config: &Config) -> bool {
+    let parsed = match PasswordHash::new(hash) {
+        Ok(parsed) if parsed.algorithm == argon2::ARGON2ID_IDENT => parsed,
+        _ => return true,
+    };
+
+    match Params::try_from(&parsed) {
+        Ok(params) => {
+            params.m_cost() != config.argon2_params.m_cost()
+                || params.t_cost() != config.argon2_params.t_cost()
+                || params.p_cost() != config.argon2_params.p_cost()
+        }
+        Err(_) => true,
+    }
+}
+
+/// Random URL-safe token for links sent by email, e.g. password resets.
+pub fn generate_secret_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
 }
 
-pub fn verify_password(password: &str, hash: &str) -> Result<bool, bcrypt::BcryptError> {
-    bcrypt::verify(password, hash)
+/// Hex SHA-256 of a secret token; the only form in which such tokens are stored.
+pub fn hash_secret_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
 }
 
-pub fn generate_token(user: &User, config: &Config) -> Result<String, jsonwebtoken::errors::Error> {
+/// Access token for a session started with `sessions::start`.
+pub fn generate_token(user: &User, session_id: Uuid, config: &Config) -> Result<String, jsonwebtoken::errors::Error> {
     let now = Utc::now();
     let expires_at = now + Duration::seconds(config.jwt_expires_in.as_secs() as i64);
     
@@ -31,23 +125,186 @@ pub fn generate_token(user: &User, config: &Config) -> Result<String, jsonwebtok
         sub: user.id.to_string(),
         exp: expires_at.timestamp() as usize,
         iat: now.timestamp() as usize,
+        iss: config.keys.issuer().to_string(),
+        aud: config.keys.audience().to_string(),
+        scope: None,
+        sid: Some(session_id.to_string()),
     };
     
-    encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(config.jwt_secret.as_bytes()),
-    )
+    config.keys.sign(&claims)
 }
 
 pub fn verify_token(token: &str, config: &Config) -> Result<Claims, jsonwebtoken::errors::Error> {
-    let token_data = decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(config.jwt_secret.as_bytes()),
-        &Validation::default(),
-    )?;
-    
-    Ok(token_data.claims)
+    let claims = decode_claims(token, config)?;
+
+    // Scoped tokens are only good for the step of the login they were issued for
+    if claims.scope.is_some() {
+        return Err(ErrorKind::InvalidToken.into());
+    }
+
+    Ok(claims)
+}
+
+/// Short-lived token for finishing a login with a second factor (`MFA_CHALLENGE_SCOPE`)
+/// or for the enrollment a role policy demands (`MFA_ENROLLMENT_SCOPE`).
+pub fn generate_mfa_token(user_id: Uuid, scope: &str, config: &Config) -> Result<String, jsonwebtoken::errors::Error> {
+    let now = Utc::now();
+
+    let claims = Claims {
+        sub: user_id.to_string(),
+        exp: (now + Duration::seconds(MFA_TOKEN_EXPIRATION_SECONDS)).timestamp() as usize,
+        iat: now.timestamp() as usize,
+        iss: config.keys.issuer().to_string(),
+        aud: config.keys.audience().to_string(),
+        scope: Some(scope.to_string()),
+        sid: None,
+    };
+
+    config.keys.sign(&claims)
+}
+
+/// Accepts only a token issued with the given scope.
+pub fn verify_scoped_token(token: &str, scope: &str, config: &Config) -> Result<Claims, jsonwebtoken::errors::Error> {
+    let claims = decode_claims(token, config)?;
+
+    if claims.scope.as_deref() != Some(scope) {
+        return Err(ErrorKind::InvalidToken.into());
+    }
+
+    Ok(claims)
+}
+
+fn decode_claims(token: &str, config: &Config) -> Result<Claims, jsonwebtoken::errors::Error> {
+    config.keys.verify(token)
+}
+
+pub enum BasicAuth {
+    Valid(Uuid),
+    Invalid,
+    // Too many failures for the account or address; retry after this many seconds
+    Throttled(i64),
+    // Right password, but the account has or must set up 2FA, which Basic auth cannot carry
+    MfaRequired,
+}
+
+/// Checks HTTP Basic credentials (email or username, then password). E-reader apps
+/// send these to OPDS acquisition links because they cannot obtain a bearer token.
+/// Failures count towards the same backoff as `login`; successes are not audited
+/// because the apps send credentials with every download. Accounts with 2FA, or
+/// whose role requires it, are refused so the password alone never opens them.
+pub async fn verify_basic_credentials(
+    client: &deadpool_postgres::Client,
+    encoded: &str,
+    req: &HttpRequest,
+    config: &Config,
+) -> Result<BasicAuth, tokio_postgres::Error> {
+    let decoded = match STANDARD
+        .decode(encoded.trim())
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+    {
+        Some(decoded) => decoded,
+        None => return Ok(BasicAuth::Invalid),
+    };
+
+    let (login, password) = match decoded.split_once(':') {
+        Some(credentials) => credentials,
+        None => return Ok(BasicAuth::Invalid),
+    };
+
+    let attempt = throttle::Attempt::new(req, config, login);
+    let retry_after = throttle::retry_after(client, &attempt).await?;
+    if retry_after > 0 {
+        return Ok(BasicAuth::Throttled(retry_after));
+    }
+
+    // An exact email match wins over a username that happens to look like one
+    let row = client
+        .query_opt(
+            "SELECT u.id, u.password_hash,
+                    m.enabled_at IS NOT NULL OR COALESCE(p.require_mfa, FALSE) AS needs_mfa
+             FROM users u
+             LEFT JOIN user_mfa m ON m.user_id = u.id
+             LEFT JOIN role_security_policies p ON p.role = u.role
+             WHERE (u.email = $1 OR u.username = $1) AND NOT u.is_service_account
+             ORDER BY (u.email = $1) DESC
+             LIMIT 1",
+            &[&login],
+        )
+        .await?;
+
+    let user_id = match row {
+        Some(row) => {
+            let hash: String = row.get("password_hash");
+            match verify_password(password, &hash) {
+                Ok(true) if row.get::<_, bool>("needs_mfa") => return Ok(BasicAuth::MfaRequired),
+                Ok(true) => return Ok(BasicAuth::Valid(row.get("id"))),
+                _ => Some(row.get("id")),
+            }
+        }
+        None => {
+            throttle::verify_dummy_password(password, config);
+            None
+        }
+    };
+
+    throttle::record(client, &attempt, user_id, throttle::Method::Basic, throttle::Outcome::InvalidCredentials)
+        .await?;
+    Ok(BasicAuth::Invalid)
+}
+
+/// Current role of a user. Read from the database rather than the token so that
+/// demoting a staff member takes effect immediately.
+pub async fn load_role(
+    client: &deadpool_postgres::Client,
+    user_id: Uuid,
+) -> Result<Option<Role>, tokio_postgres::Error> {
+    let row = client
+        .query_opt("SELECT role FROM users WHERE id = $1", &[&user_id])
+        .await?;
+
+    Ok(row.map(|row| row.get::<_, String>("role").parse().unwrap_or_default()))
+}
+
+/// Allows only librarians and admins through; anyone else gets the response to return.
+pub async fn require_staff(client: &deadpool_postgres::Client, user_id: Uuid) -> Result<(), HttpResponse> {
+    match load_role(client, user_id).await {
+        Ok(Some(role)) if role.is_staff() => Ok(()),
+        Ok(Some(_)) => Err(HttpResponse::Forbidden().json("Staff access required")),
+        Ok(None) => Err(HttpResponse::Unauthorized().json("User not found")),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Err(HttpResponse::InternalServerError().json("Database error"))
+        }
+    }
+}
+
+/// Allows only admins through; anyone else gets the response to return.
+pub async fn require_admin(client: &deadpool_postgres::Client, user_id: Uuid) -> Result<(), HttpResponse> {
+    match load_role(client, user_id).await {
+        Ok(Some(Role::Admin)) => Ok(()),
+        Ok(Some(_)) => Err(HttpResponse::Forbidden().json("Admin access required")),
+        Ok(None) => Err(HttpResponse::Unauthorized().json("User not found")),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Err(HttpResponse::InternalServerError().json("Database error"))
+        }
+    }
+}
+
+/// Whether the security policy of a role makes two-factor authentication mandatory.
+pub async fn mfa_required_for(
+    client: &deadpool_postgres::Client,
+    role: Role,
+) -> Result<bool, tokio_postgres::Error> {
+    let row = client
+        .query_opt(
+            "SELECT require_mfa FROM role_security_policies WHERE role = $1",
+            &[&role.to_string()],
+        )
+        .await?;
+
+    Ok(row.map(|row| row.get("require_mfa")).unwrap_or(false))
 }
 
 pub fn configure(cfg: &mut web::ServiceConfig) {
@@ -56,6 +313,29 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
             .service(handlers::register)
             .service(handlers::login)
             .service(handlers::logout)
-            .service(handlers::profile),
+            .service(handlers::list_sessions)
+            .service(handlers::revoke_session)
+            .service(handlers::forgot_password)
+            .service(handlers::reset_password)
+            .service(handlers::verify_email)
+            .service(handlers::verify_email_code)
+            .service(handlers::resend_verification)
+            .service(handlers::mfa_setup)
+            .service(handlers::mfa_enable)
+            .service(handlers::mfa_verify)
+            .service(handlers::mfa_disable)
+            .service(handlers::mfa_recovery_codes)
+            .service(handlers::profile)
+            .service(handlers::update_timezone)
+            .service(handlers::update_profile)
+            .service(handlers::change_password)
+            .service(handlers::update_preferences)
+            .service(handlers::upload_avatar)
+            .service(handlers::delete_avatar)
+            .service(handlers::avatar)
+            .service(handlers::delete_account),
     );
+
+    // Public keys for services that verify our tokens
+    cfg.service(handlers::jwks);
 }
\ No newline at end of file
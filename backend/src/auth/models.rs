@@ -7,13 +7,151 @@ pub struct User {
     pub id: Uuid,
     pub username: String,
     pub email: String,
+    // New address waiting for verification; `email` stays in use until then
+    pub pending_email: Option<String>,
+    pub timezone: String,
+    pub role: Role,
+    pub email_verified_at: Option<DateTime<Utc>>,
+    pub avatar_url: Option<String>,
+    pub language: String,
+    pub reader_theme: ReaderTheme,
     pub created_at: DateTime<Utc>,
 }
 
+// Columns read by `User::from_row`
+pub const USER_COLUMNS: &str = "id, username, email, pending_email, timezone, role, email_verified_at, \
+                                avatar_path, language, reader_theme, created_at";
+
+impl User {
+    pub fn from_row(row: &tokio_postgres::Row) -> Self {
+        User {
+            id: row.get("id"),
+            username: row.get("username"),
+            email: row.get("email"),
+            pending_email: row.get("pending_email"),
+            timezone: row.get("timezone"),
+            role: row.get::<_, String>("role").parse().unwrap_or_default(),
+            email_verified_at: row.get("email_verified_at"),
+            avatar_url: row
+                .get::<_, Option<String>>("avatar_path")
+                .map(|path| format!("/auth/avatars/{}", path)),
+            language: row.get("language"),
+            reader_theme: row.get::<_, String>("reader_theme").parse().unwrap_or_default(),
+            created_at: row.get("created_at"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Session {
+    pub id: Uuid,
+    pub device_name: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip_address: String,
+    pub created_at: DateTime<Utc>,
+    pub last_active_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    // The session of the token the list was requested with
+    pub current: bool,
+}
+
+// Columns read by `Session::from_row`
+pub const SESSION_COLUMNS: &str = "id, device_name, user_agent, ip_address, created_at, last_active_at, expires_at";
+
+impl Session {
+    pub fn from_row(row: &tokio_postgres::Row, current_id: Option<Uuid>) -> Self {
+        let id: Uuid = row.get("id");
+
+        Session {
+            id,
+            device_name: row.get("device_name"),
+            user_agent: row.get("user_agent"),
+            ip_address: row.get("ip_address"),
+            created_at: row.get("created_at"),
+            last_active_at: row.get("last_active_at"),
+            expires_at: row.get("expires_at"),
+            current: current_id == Some(id),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct CreateUser {
     pub id: Uuid,
     pub username: String,
     pub email: String,
     pub password_hash: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    Reader,
+    Librarian,
+    Admin,
+}
+
+impl Role {
+    // Librarians and admins may see library-wide usage
+    pub fn is_staff(&self) -> bool {
+        matches!(self, Role::Librarian | Role::Admin)
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Role::Reader => "reader",
+            Role::Librarian => "librarian",
+            Role::Admin => "admin",
+        };
+        f.write_str(name)
+    }
+}
+
+impl std::str::FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reader" => Ok(Role::Reader),
+            "librarian" => Ok(Role::Librarian),
+            "admin" => Ok(Role::Admin),
+            other => Err(format!("Unknown role: {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReaderTheme {
+    #[default]
+    Light,
+    Dark,
+    Sepia,
+}
+
+impl std::fmt::Display for ReaderTheme {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ReaderTheme::Light => "light",
+            ReaderTheme::Dark => "dark",
+            ReaderTheme::Sepia => "sepia",
+        };
+        f.write_str(name)
+    }
+}
+
+impl std::str::FromStr for ReaderTheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "light" => Ok(ReaderTheme::Light),
+            "dark" => Ok(ReaderTheme::Dark),
+            "sepia" => Ok(ReaderTheme::Sepia),
+            other => Err(format!("Unknown reader theme: {}", other)),
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,158 @@
+use std::collections::HashSet;
+use std::io;
+use std::path::Path;
+
+// Longest password accepted; hashing cost grows with the input
+pub const MAX_PASSWORD_LENGTH: usize = 256;
+
+// Refused even when no breached-password list is configured
+const COMMON_PASSWORDS: [&str; 20] = [
+    "password", "password1", "passw0rd", "12345678", "123456789", "1234567890", "11111111", "00000000",
+    "qwertyuiop", "qwerty123", "abc12345", "iloveyou", "sunshine", "princess", "football", "baseball",
+    "superman", "letmein1", "welcome1", "admin123",
+];
+
+/// Rules for new passwords: a minimum length and a list of known breached
+/// passwords, compared case-insensitively.
+pub struct PasswordPolicy {
+    min_length: usize,
+    breached: HashSet<String>,
+}
+
+impl PasswordPolicy {
+    /// `breached_list` is a text file with one password per line, e.g. a published
+    /// list of the most common breached passwords.
+    pub fn new(min_length: usize, breached_list: Option<&Path>) -> io::Result<Self> {
+        let mut breached: HashSet<String> = COMMON_PASSWORDS.iter().map(|password| password.to_string()).collect();
+
+        if let Some(path) = breached_list {
+            let contents = std::fs::read_to_string(path)?;
+            breached.extend(
+                contents
+                    .lines()
+                    .map(|line| line.trim())
+                    .filter(|line| !line.is_empty())
+                    .map(|line| line.to_lowercase()),
+            );
+        }
+
+        Ok(PasswordPolicy { min_length, breached })
+    }
+
+    /// Why the password is refused; empty when it is acceptable. `personal` holds
+    /// account details such as the username and email, which must not be used.
+    pub fn check(&self, password: &str, personal: &[&str]) -> Vec<String> {
+        let mut problems = Vec::new();
+        let length = password.chars().count();
+
+        if length < self.min_length {
+            problems.push(format!("Must be at least {} characters", self.min_length));
+        }
+        if length > MAX_PASSWORD_LENGTH {
+            problems.push(format!("Must be at most {} characters", MAX_PASSWORD_LENGTH));
+        }
+
+        let lowercase = password.to_lowercase();
+        if self.breached.contains(&lowercase) {
+            problems.push("Is too common and has appeared in data breaches".to_string());
+        }
+        if personal
+            .iter()
+            .any(|value| !value.is_empty() && value.to_lowercase() == lowercase)
+        {
+            problems.push("Must not be the same as your username or email".to_string());
+        }
+
+        problems
+    }
+}
+
+impl std::fmt::Debug for PasswordPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PasswordPolicy")
+            .field("min_length", &self.min_length)
+            .field("breached_passwords", &self.breached.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> PasswordPolicy {
+        PasswordPolicy::new(12, None).unwrap()
+    }
+
+    #[test]
+    fn accepts_a_long_uncommon_password() {
+        assert!(policy().check("correct horse battery staple", &["reader", "reader@example.com"]).is_empty());
+    }
+
+    #[test]
+    fn enforces_length_limits_in_characters() {
+        let policy = policy();
+
+        assert_eq!(policy.check("short pass", &[]), vec!["Must be at least 12 characters"]);
+        assert!(policy.check("twelve chars", &[]).is_empty());
+        // Twelve characters but more than twelve bytes
+        assert!(policy.check("pässwörd-çàfé", &[]).is_empty());
+
+        let too_long = "a".repeat(MAX_PASSWORD_LENGTH + 1);
+        assert_eq!(policy.check(&too_long, &[]), vec![format!("Must be at most {} characters", MAX_PASSWORD_LENGTH)]);
+        assert!(policy.check(&"a".repeat(MAX_PASSWORD_LENGTH), &[]).is_empty());
+    }
+
+    #[test]
+    fn refuses_built_in_common_passwords_in_any_case() {
+        let policy = PasswordPolicy::new(8, None).unwrap();
+
+        assert_eq!(policy.check("Password1", &[]), vec!["Is too common and has appeared in data breaches"]);
+        assert_eq!(policy.check("QWERTYUIOP", &[]).len(), 1);
+    }
+
+    #[test]
+    fn loads_breached_list_from_file() {
+        let path = std::env::temp_dir().join(format!("breached-{}.txt", std::process::id()));
+        std::fs::write(&path, "  Hunter2Hunter2  \n\ndragonslayer99\n").unwrap();
+        let policy = PasswordPolicy::new(12, Some(&path)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(policy.check("hunter2hunter2", &[]).len(), 1);
+        assert_eq!(policy.check("DragonSlayer99", &[]).len(), 1);
+        // The built-in list still applies alongside the file
+        assert_eq!(policy.check("password", &[]).len(), 2);
+        assert!(policy.check("dragonslayer100", &[]).is_empty());
+    }
+
+    #[test]
+    fn missing_breached_list_is_an_error() {
+        let path = std::env::temp_dir().join("breached-list-that-does-not-exist.txt");
+        assert!(PasswordPolicy::new(12, Some(&path)).is_err());
+    }
+
+    #[test]
+    fn refuses_username_or_email_as_password() {
+        let policy = policy();
+        let personal = ["bookworm_reader", "Bookworm.Reader@example.com"];
+
+        assert_eq!(
+            policy.check("BOOKWORM_READER", &personal),
+            vec!["Must not be the same as your username or email"]
+        );
+        assert_eq!(policy.check("bookworm.reader@example.com", &personal).len(), 1);
+        assert!(policy.check("bookworm_reader_2024!", &personal).is_empty());
+    }
+
+    #[test]
+    fn ignores_empty_personal_values() {
+        assert!(policy().check("correct horse battery staple", &["", ""]).is_empty());
+    }
+
+    #[test]
+    fn reports_every_problem_at_once() {
+        let policy = policy();
+
+        assert_eq!(policy.check("password", &["password"]).len(), 3);
+    }
+}
\ No newline at end of file
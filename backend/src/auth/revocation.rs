@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    middleware::Next,
+    web, Error, HttpResponse,
+};
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::db::DbPool;
+use super::{sessions, throttle, verify_token};
+
+/// Rejects bearer tokens of deleted accounts, tokens of revoked sessions and tokens
+/// issued before the user's `tokens_invalid_before`, which a password change moves
+/// forward, and keeps the last activity of sessions up to date. Tokens are taken
+/// from the Authorization header or, failing that, the `token` query parameter used
+/// by the sync socket. Requests without a valid token pass through for the handlers
+/// to reject.
+pub async fn reject_revoked_tokens<B: MessageBody>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, Error> {
+    match check(&req).await {
+        Some(response) => Ok(req.into_response(response).map_into_right_body()),
+        None => next.call(req).await.map(ServiceResponse::map_into_left_body),
+    }
+}
+
+// The response to return instead of calling the handler, if any
+async fn check(req: &ServiceRequest) -> Option<HttpResponse> {
+    // The same order as the handlers that accept both, so the token checked is the one used
+    let header_token = req
+        .headers()
+        .get("Authorization")
+        .and_then(|header| header.to_str().ok())
+        .and_then(|header| header.strip_prefix("Bearer "))
+        .map(str::to_string);
+    let token = match header_token {
+        Some(token) => token,
+        None => web::Query::<HashMap<String, String>>::from_query(req.query_string())
+            .ok()?
+            .into_inner()
+            .remove("token")?,
+    };
+
+    let config = req.app_data::<web::Data<Config>>()?;
+    let claims = verify_token(&token, config).ok()?;
+    let user_id = Uuid::parse_str(&claims.sub).ok()?;
+    let session_id = match claims.sid.as_deref().map(Uuid::parse_str) {
+        Some(Ok(id)) => Some(id),
+        Some(Err(_)) => return Some(HttpResponse::Unauthorized().json("Invalid token")),
+        None => None,
+    };
+
+    let pool = req.app_data::<web::Data<DbPool>>()?;
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return Some(HttpResponse::InternalServerError().json("Database error")),
+    };
+
+    // Tokens carry whole seconds, so one issued in the same second as the cutoff still counts
+    let row = client
+        .query_opt(
+            "SELECT COALESCE(FLOOR(EXTRACT(EPOCH FROM u.tokens_invalid_before)), 0)::bigint AS cutoff,
+                    s.id IS NOT NULL AND s.revoked_at IS NULL AS session_active,
+                    COALESCE(s.last_active_at < CURRENT_TIMESTAMP - make_interval(secs => $3), FALSE)
+                        AS activity_stale
+             FROM users u
+             LEFT JOIN user_sessions s ON s.id = $2 AND s.user_id = u.id
+             WHERE u.id = $1",
+            &[&user_id, &session_id, &sessions::ACTIVITY_UPDATE_INTERVAL_SECONDS],
+        )
+        .await;
+
+    let row = match row {
+        Ok(Some(row)) => row,
+        Ok(None) => return Some(HttpResponse::Unauthorized().json("Token has been revoked")),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Some(HttpResponse::InternalServerError().json("Database error"));
+        }
+    };
+
+    // Tokens without a session, e.g. from API keys, are only checked against the cutoff
+    let session_active = session_id.is_none() || row.get::<_, bool>("session_active");
+    if (claims.iat as i64) < row.get::<_, i64>("cutoff") || !session_active {
+        return Some(HttpResponse::Unauthorized().json("Token has been revoked"));
+    }
+
+    // Losing an activity update is harmless, so failures don't fail the request
+    if row.get::<_, bool>("activity_stale") {
+        if let Err(e) = client
+            .execute(
+                "UPDATE user_sessions SET last_active_at = CURRENT_TIMESTAMP, ip_address = $2 WHERE id = $1",
+                &[&session_id, &throttle::client_ip(req.request(), config)],
+            )
+            .await
+        {
+            eprintln!("Database error: {}", e);
+        }
+    }
+
+    None
+}
\ No newline at end of file
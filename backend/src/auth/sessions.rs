@@ -0,0 +1,96 @@
+use actix_web::HttpRequest;
+use chrono::{Duration, Utc};
+use deadpool_postgres::Transaction;
+use uuid::Uuid;
+
+use crate::config::Config;
+use super::throttle;
+
+// Longest device name kept, as in `user_sessions.device_name`
+pub const MAX_DEVICE_NAME_LENGTH: u64 = 100;
+
+// Last activity is only written when it is older than this, not on every request
+pub const ACTIVITY_UPDATE_INTERVAL_SECONDS: f64 = 60.0;
+
+/// Records a sign-in and returns the session id for the token's `sid` claim. Without
+/// a `device_name` from the client, one is made up from the user agent.
+pub async fn start(
+    client: &deadpool_postgres::Client,
+    user_id: Uuid,
+    req: &HttpRequest,
+    device_name: Option<&str>,
+    config: &Config,
+) -> Result<Uuid, tokio_postgres::Error> {
+    let user_agent = req
+        .headers()
+        .get("User-Agent")
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+
+    let device_name = match device_name.map(str::trim).filter(|name| !name.is_empty()) {
+        Some(name) => Some(name.chars().take(MAX_DEVICE_NAME_LENGTH as usize).collect()),
+        None => user_agent.as_deref().and_then(describe_device),
+    };
+
+    let id = Uuid::new_v4();
+    let expires_at = Utc::now() + Duration::seconds(config.jwt_expires_in.as_secs() as i64);
+
+    // Sessions whose tokens have all expired are of no more use to anyone
+    client
+        .execute(
+            "DELETE FROM user_sessions WHERE user_id = $1 AND expires_at <= CURRENT_TIMESTAMP",
+            &[&user_id],
+        )
+        .await?;
+
+    client
+        .execute(
+            "INSERT INTO user_sessions (id, user_id, device_name, user_agent, ip_address, expires_at)
+             VALUES ($1, $2, $3, $4, $5, $6)",
+            &[&id, &user_id, &device_name, &user_agent, &throttle::client_ip(req, config), &expires_at],
+        )
+        .await?;
+
+    Ok(id)
+}
+
+/// Ends every session of the user, e.g. after a password change.
+pub async fn revoke_all(tx: &Transaction<'_>, user_id: Uuid) -> Result<u64, tokio_postgres::Error> {
+    tx.execute(
+        "UPDATE user_sessions SET revoked_at = CURRENT_TIMESTAMP WHERE user_id = $1 AND revoked_at IS NULL",
+        &[&user_id],
+    )
+    .await
+}
+
+// e.g. "Firefox on Windows"; None when neither part is recognised
+fn describe_device(user_agent: &str) -> Option<String> {
+    // Order matters: Edge and Opera also claim to be Chrome, and Chrome to be Safari
+    const BROWSERS: [(&str, &str); 7] = [
+        ("Edg/", "Edge"),
+        ("OPR/", "Opera"),
+        ("Firefox/", "Firefox"),
+        ("Chrome/", "Chrome"),
+        ("Safari/", "Safari"),
+        ("KOReader", "KOReader"),
+        ("curl/", "curl"),
+    ];
+    const SYSTEMS: [(&str, &str); 7] = [
+        ("Android", "Android"),
+        ("iPhone", "iPhone"),
+        ("iPad", "iPad"),
+        ("Windows", "Windows"),
+        ("Mac OS X", "macOS"),
+        ("CrOS", "ChromeOS"),
+        ("Linux", "Linux"),
+    ];
+
+    let browser = BROWSERS.iter().find(|(marker, _)| user_agent.contains(marker)).map(|(_, name)| *name);
+    let system = SYSTEMS.iter().find(|(marker, _)| user_agent.contains(marker)).map(|(_, name)| *name);
+
+    match (browser, system) {
+        (Some(browser), Some(system)) => Some(format!("{} on {}", browser, system)),
+        (Some(name), None) | (None, Some(name)) => Some(name.to_string()),
+        (None, None) => None,
+    }
+}
\ No newline at end of file
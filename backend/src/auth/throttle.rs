@@ -0,0 +1,230 @@
+use std::sync::OnceLock;
+
+use actix_web::HttpRequest;
+use uuid::Uuid;
+
+use crate::config::Config;
+use super::{hash_password, verify_password};
+
+struct Policy {
+    scope: &'static str,
+    // Failures allowed before any delay is imposed
+    free_attempts: i32,
+    // Failures after which the key is locked out rather than delayed
+    lockout_after: i32,
+    lockout_seconds: i64,
+    max_backoff_seconds: i64,
+}
+
+// One account is attacked from many addresses, so it gets the tighter limits
+const ACCOUNT_POLICY: Policy = Policy {
+    scope: "account",
+    free_attempts: 3,
+    lockout_after: 10,
+    lockout_seconds: 30 * 60,
+    max_backoff_seconds: 5 * 60,
+};
+
+// An address may be shared by a whole library or campus behind NAT
+const IP_POLICY: Policy = Policy {
+    scope: "ip",
+    free_attempts: 20,
+    lockout_after: 100,
+    lockout_seconds: 60 * 60,
+    max_backoff_seconds: 10 * 60,
+};
+
+// Failures older than this no longer count towards backoff
+const FAILURE_WINDOW_SECONDS: f64 = 3600.0;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Method {
+    Password,
+    Mfa,
+    Basic,
+    Oidc,
+    Koreader,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Outcome {
+    Success,
+    // Password was right; a second factor is still outstanding
+    MfaPending,
+    InvalidCredentials,
+    InvalidCode,
+    Throttled,
+}
+
+/// Who is trying to sign in, as recorded in `login_attempts` and used for throttling.
+pub struct Attempt {
+    pub email: String,
+    pub ip_address: String,
+    pub user_agent: Option<String>,
+}
+
+impl Attempt {
+    pub fn new(req: &HttpRequest, config: &Config, email: &str) -> Self {
+        Attempt {
+            email: email.trim().to_lowercase(),
+            ip_address: client_ip(req, config),
+            user_agent: req
+                .headers()
+                .get("User-Agent")
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string),
+        }
+    }
+}
+
+/// Address of the client. Forwarding headers are only believed when the server
+/// sits behind a proxy that sets them, otherwise anyone could pick their own address.
+pub fn client_ip(req: &HttpRequest, config: &Config) -> String {
+    let info = req.connection_info();
+    let address = if config.trust_proxy_headers {
+        info.realip_remote_addr()
+    } else {
+        info.peer_addr()
+    };
+    address.unwrap_or("unknown").to_string()
+}
+
+/// Seconds the attempt has to wait, or 0 when neither the account nor the address is blocked.
+pub async fn retry_after(
+    client: &deadpool_postgres::Client,
+    attempt: &Attempt,
+) -> Result<i64, tokio_postgres::Error> {
+    let row = client
+        .query_one(
+            "SELECT GREATEST(0, CEIL(EXTRACT(EPOCH FROM MAX(blocked_until) - CURRENT_TIMESTAMP)))::bigint
+             FROM login_throttles
+             WHERE (scope = $1 AND key = $2) OR (scope = $3 AND key = $4)",
+            &[&ACCOUNT_POLICY.scope, &attempt.email, &IP_POLICY.scope, &attempt.ip_address],
+        )
+        .await?;
+
+    Ok(row.get(0))
+}
+
+/// Records a sign-in attempt and updates the failure counters: failures extend
+/// the backoff of both the account and the address, a completed sign-in clears the
+/// account's. A correct password awaiting its second factor clears nothing, or it
+/// would wipe the backoff that wrong codes built up.
+pub async fn record(
+    client: &deadpool_postgres::Client,
+    attempt: &Attempt,
+    user_id: Option<Uuid>,
+    method: Method,
+    outcome: Outcome,
+) -> Result<(), tokio_postgres::Error> {
+    client
+        .execute(
+            "INSERT INTO login_attempts (user_id, email, ip_address, user_agent, method, outcome)
+             VALUES ($1, $2, $3, $4, $5, $6)",
+            &[
+                &user_id,
+                &attempt.email,
+                &attempt.ip_address,
+                &attempt.user_agent,
+                &method.as_str(),
+                &outcome.as_str(),
+            ],
+        )
+        .await?;
+
+    match outcome {
+        Outcome::InvalidCredentials | Outcome::InvalidCode => {
+            register_failure(client, &ACCOUNT_POLICY, &attempt.email).await?;
+            register_failure(client, &IP_POLICY, &attempt.ip_address).await?;
+        }
+        Outcome::Success => {
+            client
+                .execute(
+                    "DELETE FROM login_throttles WHERE scope = $1 AND key = $2",
+                    &[&ACCOUNT_POLICY.scope, &attempt.email],
+                )
+                .await?;
+        }
+        Outcome::MfaPending | Outcome::Throttled => {}
+    }
+
+    Ok(())
+}
+
+async fn register_failure(
+    client: &deadpool_postgres::Client,
+    policy: &Policy,
+    key: &str,
+) -> Result<(), tokio_postgres::Error> {
+    let failed_count: i32 = client
+        .query_one(
+            "INSERT INTO login_throttles (scope, key, failed_count) VALUES ($1, $2, 1)
+             ON CONFLICT (scope, key) DO UPDATE SET
+                 failed_count = CASE
+                     WHEN login_throttles.last_failed_at < CURRENT_TIMESTAMP - make_interval(secs => $3) THEN 1
+                     ELSE login_throttles.failed_count + 1
+                 END,
+                 last_failed_at = CURRENT_TIMESTAMP
+             RETURNING failed_count",
+            &[&policy.scope, &key, &FAILURE_WINDOW_SECONDS],
+        )
+        .await?
+        .get(0);
+
+    let block_seconds = if failed_count >= policy.lockout_after {
+        policy.lockout_seconds
+    } else if failed_count > policy.free_attempts {
+        // 1, 2, 4, 8... seconds for each failure beyond the free ones
+        let exponent = (failed_count - policy.free_attempts - 1).min(30) as u32;
+        2i64.saturating_pow(exponent).min(policy.max_backoff_seconds)
+    } else {
+        0
+    };
+
+    if block_seconds > 0 {
+        client
+            .execute(
+                "UPDATE login_throttles
+                 SET blocked_until = CURRENT_TIMESTAMP + make_interval(secs => $3)
+                 WHERE scope = $1 AND key = $2",
+                &[&policy.scope, &key, &(block_seconds as f64)],
+            )
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Spends the same hashing work as a real password check, so an unknown email
+/// can't be told apart from a wrong password by how long the answer takes.
+pub fn verify_dummy_password(password: &str, config: &Config) {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+    let hash = DUMMY_HASH.get_or_init(|| {
+        hash_password("not the password of any account", config).expect("hashing of a constant")
+    });
+    let _ = verify_password(password, hash);
+}
+
+impl Method {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Method::Password => "password",
+            Method::Mfa => "mfa",
+            Method::Basic => "basic",
+            Method::Oidc => "oidc",
+            Method::Koreader => "koreader",
+        }
+    }
+}
+
+impl Outcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Success => "success",
+            Outcome::MfaPending => "mfa_pending",
+            Outcome::InvalidCredentials => "invalid_credentials",
+            Outcome::InvalidCode => "invalid_code",
+            Outcome::Throttled => "throttled",
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,181 @@
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha1::Sha1;
+
+use super::hash_secret_token;
+
+// Authenticator apps default to SHA-1, six digits and 30 second steps
+const STEP_SECONDS: i64 = 30;
+const DIGITS: u32 = 6;
+
+// Steps either side of the current one still accepted, for clock drift
+const ALLOWED_DRIFT: i64 = 1;
+
+const ISSUER: &str = "E-Library";
+
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+/// New random 160-bit secret, base32 encoded as authenticator apps expect.
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; 20];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    BASE32_NOPAD.encode(&bytes)
+}
+
+/// `otpauth://` URI for the secret; the frontend renders it as a QR code.
+pub fn provisioning_uri(secret: &str, account: &str) -> String {
+    format!(
+        "otpauth://totp/{issuer}:{account}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={digits}&period={period}",
+        issuer = encode_uri_component(ISSUER),
+        account = encode_uri_component(account),
+        secret = secret,
+        digits = DIGITS,
+        period = STEP_SECONDS,
+    )
+}
+
+/// Checks a code against the secret at `now` (Unix seconds). Returns the matched
+/// time step, which must be later than `last_used_step` so codes are single-use.
+pub fn verify(secret: &str, code: &str, now: i64, last_used_step: i64) -> Option<i64> {
+    let key = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let current = now / STEP_SECONDS;
+    (current - ALLOWED_DRIFT..=current + ALLOWED_DRIFT)
+        .filter(|step| *step > last_used_step)
+        .find(|step| format!("{:0width$}", code_at(&key, *step), width = DIGITS as usize) == code)
+}
+
+// RFC 4226 HOTP value for a counter
+fn code_at(key: &[u8], step: i64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(&(step as u64).to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let value = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+    value % 10u32.pow(DIGITS)
+}
+
+/// Fresh recovery codes in `xxxxx-xxxxx` form, as shown to the user once.
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let mut bytes = [0u8; 7];
+            rand::thread_rng().fill_bytes(&mut bytes);
+            let code = BASE32_NOPAD.encode(&bytes).to_lowercase();
+            format!("{}-{}", &code[..5], &code[5..10])
+        })
+        .collect()
+}
+
+/// Hash under which a recovery code is stored. Case and dashes are ignored so
+/// codes can be typed loosely.
+pub fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    hash_secret_token(&normalized)
+}
+
+fn encode_uri_component(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'@' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The RFC 6238 test key, "12345678901234567890" in ASCII
+    const SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    fn code_for(step: i64) -> String {
+        let key = BASE32_NOPAD.decode(SECRET.as_bytes()).unwrap();
+        format!("{:06}", code_at(&key, step))
+    }
+
+    #[test]
+    fn matches_rfc_6238_vectors() {
+        // The RFC lists eight digits; authenticators show the last six
+        assert_eq!(verify(SECRET, "287082", 59, 0), Some(1));
+        assert_eq!(verify(SECRET, "081804", 1111111109, 0), Some(37037036));
+        assert_eq!(verify(SECRET, "050471", 1111111111, 0), Some(37037037));
+        assert_eq!(verify(SECRET, "005924", 1234567890, 0), Some(41152263));
+        assert_eq!(verify(SECRET, "279037", 2000000000, 0), Some(66666666));
+    }
+
+    #[test]
+    fn accepts_one_step_of_drift_either_way() {
+        let now = 1_700_000_000;
+        let current = now / STEP_SECONDS;
+
+        assert_eq!(verify(SECRET, &code_for(current - 1), now, 0), Some(current - 1));
+        assert_eq!(verify(SECRET, &code_for(current), now, 0), Some(current));
+        assert_eq!(verify(SECRET, &code_for(current + 1), now, 0), Some(current + 1));
+    }
+
+    #[test]
+    fn rejects_codes_outside_the_window() {
+        let now = 1_700_000_000;
+        let current = now / STEP_SECONDS;
+
+        assert_eq!(verify(SECRET, &code_for(current - 2), now, 0), None);
+        assert_eq!(verify(SECRET, &code_for(current + 2), now, 0), None);
+    }
+
+    #[test]
+    fn rejects_replayed_and_older_steps() {
+        let now = 1_700_000_000;
+        let current = now / STEP_SECONDS;
+        let code = code_for(current);
+
+        assert_eq!(verify(SECRET, &code, now, current), None);
+        assert_eq!(verify(SECRET, &code, now, current + 1), None);
+        // After a code from the previous step, the current one is still good once
+        assert_eq!(verify(SECRET, &code, now, current - 1), Some(current));
+        assert_eq!(verify(SECRET, &code_for(current - 1), now, current - 1), None);
+    }
+
+    #[test]
+    fn rejects_malformed_codes() {
+        let now = 1_700_000_000;
+        let code = code_for(now / STEP_SECONDS);
+
+        assert_eq!(verify(SECRET, &code[..5], now, 0), None);
+        assert_eq!(verify(SECRET, &format!("{}0", code), now, 0), None);
+        assert_eq!(verify(SECRET, "12345a", now, 0), None);
+        assert_eq!(verify(SECRET, "", now, 0), None);
+        assert_eq!(verify("not base32!", &code, now, 0), None);
+        // Surrounding whitespace from copy and paste is ignored
+        assert!(verify(SECRET, &format!(" {} ", code), now, 0).is_some());
+    }
+
+    #[test]
+    fn recovery_codes_ignore_case_and_dashes() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+
+        let code = &codes[0];
+        let expected = hash_recovery_code(code);
+        assert_eq!(hash_recovery_code(&code.to_uppercase()), expected);
+        assert_eq!(hash_recovery_code(&code.replace('-', "")), expected);
+        assert_ne!(hash_recovery_code(&codes[1]), expected);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,122 @@
+use deadpool_postgres::Transaction;
+use rand::Rng;
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::mailer::{Email, Mailer, MailError};
+use super::{generate_secret_token, hash_secret_token};
+
+// Wrong codes allowed per verification email before it has to be resent
+pub const MAX_CODE_ATTEMPTS: i32 = 5;
+
+// Minimum time between two verification emails to the same user
+pub const RESEND_COOLDOWN_SECONDS: i64 = 60;
+
+// Verification emails allowed per user per hour
+pub const MAX_SENDS_PER_HOUR: i64 = 5;
+
+pub struct IssuedVerification {
+    pub token: String,
+    pub code: String,
+}
+
+/// Replaces any outstanding verification of the user with a new link token and code.
+pub async fn issue(
+    tx: &Transaction<'_>,
+    config: &Config,
+    user_id: Uuid,
+    email: &str,
+) -> Result<IssuedVerification, tokio_postgres::Error> {
+    let token = generate_secret_token();
+    let code = format!("{:06}", rand::thread_rng().gen_range(0..1_000_000));
+    let expires_at = chrono::Utc::now()
+        + chrono::Duration::seconds(config.email_verification_expires_in.as_secs() as i64);
+
+    // Superseded rows are expired rather than deleted; they still count towards the resend limit
+    tx.execute(
+        "UPDATE email_verification_tokens SET expires_at = LEAST(expires_at, CURRENT_TIMESTAMP)
+         WHERE user_id = $1 AND used_at IS NULL",
+        &[&user_id],
+    )
+    .await?;
+    tx.execute(
+        "INSERT INTO email_verification_tokens (user_id, email, token_hash, code_hash, expires_at)
+         VALUES ($1, $2, $3, $4, $5)",
+        &[&user_id, &email, &hash_secret_token(&token), &hash_secret_token(&code), &expires_at],
+    )
+    .await?;
+
+    Ok(IssuedVerification { token, code })
+}
+
+/// Seconds until another verification email may go to the user: after the cooldown
+/// since the last one, and once the oldest email of the past hour drops out of the
+/// hourly limit. Zero when one may be sent now.
+pub async fn seconds_until_next_send(
+    tx: &Transaction<'_>,
+    user_id: Uuid,
+) -> Result<i64, tokio_postgres::Error> {
+    let row = tx
+        .query_one(
+            "SELECT GREATEST(
+                 COALESCE(MAX(EXTRACT(EPOCH FROM created_at + make_interval(secs => $2) - CURRENT_TIMESTAMP)), 0),
+                 CASE WHEN COUNT(*) >= $3
+                      THEN EXTRACT(EPOCH FROM MIN(created_at) + INTERVAL '1 hour' - CURRENT_TIMESTAMP)
+                      ELSE 0 END
+             )::bigint
+             FROM email_verification_tokens
+             WHERE user_id = $1 AND created_at > CURRENT_TIMESTAMP - INTERVAL '1 hour'",
+            &[&user_id, &(RESEND_COOLDOWN_SECONDS as f64), &MAX_SENDS_PER_HOUR],
+        )
+        .await?;
+
+    Ok(row.get(0))
+}
+
+pub async fn send(
+    mailer: &dyn Mailer,
+    config: &Config,
+    username: &str,
+    email: &str,
+    issued: &IssuedVerification,
+) -> Result<(), MailError> {
+    mailer
+        .send(Email {
+            to: email.to_string(),
+            subject: "Verify your E-Library email address".to_string(),
+            body: format!(
+                "Hi {},\n\n\
+                 Please confirm that this is your email address by opening this link:\n\n\
+                 {}?token={}\n\n\
+                 Or enter this code in the app: {}\n\n\
+                 The link and code expire in {} hours. \
+                 If you did not create an E-Library account, you can ignore this email.\n",
+                username,
+                config.email_verification_url,
+                issued.token,
+                issued.code,
+                config.email_verification_expires_in.as_secs() / 3600,
+            ),
+        })
+        .await
+}
+
+/// Whether the user may read books under the configured verification policy.
+pub async fn may_read(
+    client: &deadpool_postgres::Client,
+    config: &Config,
+    user_id: Uuid,
+) -> Result<bool, tokio_postgres::Error> {
+    if !config.require_verified_email {
+        return Ok(true);
+    }
+
+    let row = client
+        .query_opt(
+            "SELECT email_verified_at IS NOT NULL AS verified FROM users WHERE id = $1",
+            &[&user_id],
+        )
+        .await?;
+
+    Ok(row.map(|row| row.get("verified")).unwrap_or(false))
+}
\ No newline at end of file
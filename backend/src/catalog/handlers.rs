@@ -1,12 +1,27 @@
-use actix_web::{web, HttpResponse, Responder, get};
+use actix_web::{web, HttpResponse, Responder, get, put, post, delete, HttpRequest};
 use uuid::Uuid;
+use validator::Validate;
+use crate::auth::{require_staff, verify_token};
+use crate::config::Config;
 use crate::db::DbPool;
-use super::models::{Book, BookSummary, Category, BookFormat};
+use crate::validation::{not_blank, ValidJson, ValidQuery, MAX_PAGE};
+use super::models::{
+    Book, BookSummary, Category, BookFormat,
+    Review, ReviewRequest, ReviewStatus, VoteRequest, ModerationRequest,
+};
+
+// Largest page of results a client can ask for
+const MAX_PAGE_SIZE: usize = 100;
+
+const REVIEW_SELECT: &str = "SELECT r.id, r.book_id, r.user_id, u.username, r.rating, r.body, r.status,
+        r.helpful_count, r.vote_count, r.created_at, r.updated_at
+    FROM book_reviews r
+    JOIN users u ON u.id = r.user_id";
 
 #[get("/books")]
 pub async fn get_books(
     pool: web::Data<DbPool>,
-    query: web::Query<GetBooksQuery>,
+    query: ValidQuery<GetBooksQuery>,
 ) -> impl Responder {
     let client = match pool.get().await {
         Ok(client) => client,
@@ -14,9 +29,11 @@ pub async fn get_books(
     };
 
     let mut sql = String::from(
-        "SELECT b.id, b.title, b.author, b.cover_image, b.category_id, c.name as category_name, b.format 
+        "SELECT b.id, b.title, b.author, b.cover_image, b.category_id, c.name as category_name, b.format,
+                rs.average_rating, COALESCE(rs.rating_count, 0) AS rating_count
          FROM books b 
-         JOIN categories c ON b.category_id = c.id"
+         JOIN categories c ON b.category_id = c.id
+         LEFT JOIN book_rating_stats rs ON rs.book_id = b.id"
     );
     
     let mut params: Vec<Box<dyn tokio_postgres::types::ToSql + Sync>> = Vec::new();
@@ -31,12 +48,19 @@ pub async fn get_books(
         }
     }
     
+    // Best rated first; unrated books go last
+    let order_by = match query.sort.as_deref() {
+        None | Some("newest") => "b.created_at DESC",
+        Some("rating") => "rs.average_rating DESC NULLS LAST, rs.rating_count DESC NULLS LAST, b.created_at DESC",
+        Some(_) => return HttpResponse::BadRequest().json("Invalid sort, expected newest or rating"),
+    };
+    
     // Add limit and offset for pagination
-    let limit = query.limit.unwrap_or(20).min(100);
+    let limit = query.limit.unwrap_or(20);
     let offset = query.page.unwrap_or(1).saturating_sub(1) * limit;
     
-    sql.push_str(&format!(" ORDER BY b.created_at DESC LIMIT ${} OFFSET ${}", 
-        param_count, param_count + 1));
+    sql.push_str(&format!(" ORDER BY {} LIMIT ${} OFFSET ${}", 
+        order_by, param_count, param_count + 1));
     params.push(Box::new(limit as i32));
     params.push(Box::new(offset as i32));
 
@@ -56,6 +80,8 @@ pub async fn get_books(
                     category_id: row.get("category_id"),
                     category_name: row.get("category_name"),
                     format: row.get("format"),
+                    average_rating: row.get("average_rating"),
+                    rating_count: row.get("rating_count"),
                 })
                 .collect();
 
@@ -87,7 +113,7 @@ pub async fn get_book(
         .query_one(
             "SELECT b.id, b.title, b.author, b.description, b.cover_image, 
                    b.category_id, c.name as category_name, b.format, b.file_path, 
-                   b.total_pages, b.published_date, b.created_at
+                   b.total_pages, b.tags, b.published_date, b.created_at
             FROM books b
             JOIN categories c ON b.category_id = c.id
             WHERE b.id = $1",
@@ -106,6 +132,7 @@ pub async fn get_book(
                 format: row.get("format"),
                 file_path: row.get("file_path"),
                 total_pages: row.get("total_pages"),
+                tags: row.get("tags"),
                 published_date: row.get("published_date"),
                 created_at: row.get("created_at"),
             };
@@ -197,27 +224,25 @@ pub async fn get_category(
 #[get("/books/search")]
 pub async fn search_books(
     pool: web::Data<DbPool>,
-    query: web::Query<SearchQuery>,
+    query: ValidQuery<SearchQuery>,
 ) -> impl Responder {
     let client = match pool.get().await {
         Ok(client) => client,
         Err(_) => return HttpResponse::InternalServerError().json("Database error"),
     };
 
-    if query.q.is_empty() {
-        return HttpResponse::BadRequest().json("Search query is required");
-    }
-
     let search_query = format!("%{}%", query.q.to_lowercase());
 
-    let limit = query.limit.unwrap_or(20).min(100);
+    let limit = query.limit.unwrap_or(20);
     let offset = query.page.unwrap_or(1).saturating_sub(1) * limit;
 
     match client
         .query(
-            "SELECT b.id, b.title, b.author, b.cover_image, b.category_id, c.name as category_name, b.format
+            "SELECT b.id, b.title, b.author, b.cover_image, b.category_id, c.name as category_name, b.format,
+                   rs.average_rating, COALESCE(rs.rating_count, 0) AS rating_count
             FROM books b
             JOIN categories c ON b.category_id = c.id
+            LEFT JOIN book_rating_stats rs ON rs.book_id = b.id
             WHERE LOWER(b.title) LIKE $1 
                OR LOWER(b.author) LIKE $1
                OR LOWER(b.description) LIKE $1
@@ -244,6 +269,8 @@ pub async fn search_books(
                     category_id: row.get("category_id"),
                     category_name: row.get("category_name"),
                     format: row.get("format"),
+                    average_rating: row.get("average_rating"),
+                    rating_count: row.get("rating_count"),
                 })
                 .collect();
 
@@ -260,7 +287,7 @@ pub async fn search_books(
 pub async fn get_books_by_category(
     pool: web::Data<DbPool>,
     path: web::Path<(String,)>,
-    query: web::Query<PaginationQuery>,
+    query: ValidQuery<PaginationQuery>,
 ) -> impl Responder {
     let client = match pool.get().await {
         Ok(client) => client,
@@ -272,14 +299,16 @@ pub async fn get_books_by_category(
         Err(_) => return HttpResponse::BadRequest().json("Invalid category ID"),
     };
 
-    let limit = query.limit.unwrap_or(20).min(100);
+    let limit = query.limit.unwrap_or(20);
     let offset = query.page.unwrap_or(1).saturating_sub(1) * limit;
 
     match client
         .query(
-            "SELECT b.id, b.title, b.author, b.cover_image, b.category_id, c.name as category_name, b.format
+            "SELECT b.id, b.title, b.author, b.cover_image, b.category_id, c.name as category_name, b.format,
+                   rs.average_rating, COALESCE(rs.rating_count, 0) AS rating_count
             FROM books b
             JOIN categories c ON b.category_id = c.id
+            LEFT JOIN book_rating_stats rs ON rs.book_id = b.id
             WHERE b.category_id = $1
             ORDER BY b.created_at DESC
             LIMIT $2 OFFSET $3",
@@ -298,6 +327,8 @@ pub async fn get_books_by_category(
                     category_id: row.get("category_id"),
                     category_name: row.get("category_name"),
                     format: row.get("format"),
+                    average_rating: row.get("average_rating"),
+                    rating_count: row.get("rating_count"),
                 })
                 .collect();
 
@@ -310,23 +341,547 @@ pub async fn get_books_by_category(
     }
 }
 
+#[get("/books/{id}/reviews")]
+pub async fn get_book_reviews(
+    pool: web::Data<DbPool>,
+    path: web::Path<(String,)>,
+    query: ValidQuery<ReviewsQuery>,
+) -> impl Responder {
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let book_id = match Uuid::parse_str(&path.0) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().json("Invalid book ID"),
+    };
+
+    let order_by = match query.sort.as_deref() {
+        None | Some("helpful") => "r.helpful_count DESC, r.created_at DESC",
+        Some("newest") => "r.created_at DESC",
+        Some(_) => return HttpResponse::BadRequest().json("Invalid sort, expected helpful or newest"),
+    };
+
+    let limit = query.limit.unwrap_or(20);
+    let offset = query.page.unwrap_or(1).saturating_sub(1) * limit;
+
+    // Bare ratings only count towards the average; listed reviews need text and approval
+    let sql = format!(
+        "{} WHERE r.book_id = $1 AND r.status = 'approved' AND r.body IS NOT NULL
+         ORDER BY {} LIMIT $2 OFFSET $3",
+        REVIEW_SELECT, order_by
+    );
+
+    match client
+        .query(&sql, &[&book_id, &(limit as i64), &(offset as i64)])
+        .await
+    {
+        Ok(rows) => {
+            let reviews: Vec<Review> = rows.iter().map(Review::from_row).collect();
+            HttpResponse::Ok().json(reviews)
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error fetching reviews")
+        }
+    }
+}
+
+#[put("/books/{id}/review")]
+pub async fn save_review(
+    req: HttpRequest,
+    path: web::Path<(String,)>,
+    body: ValidJson<ReviewRequest>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let book_id = match Uuid::parse_str(&path.0) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().json("Invalid book ID"),
+    };
+
+    let text = body
+        .body
+        .as_deref()
+        .map(str::trim)
+        .filter(|text| !text.is_empty())
+        .map(str::to_string);
+
+    // Any new or edited text goes back to the moderation queue
+    let status = if text.is_some() {
+        ReviewStatus::Pending
+    } else {
+        ReviewStatus::Approved
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    match client
+        .query_opt("SELECT 1 FROM books WHERE id = $1", &[&book_id])
+        .await
+    {
+        Ok(Some(_)) => {}
+        Ok(None) => return HttpResponse::NotFound().json("Book not found"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Database error");
+        }
+    }
+
+    let saved = client
+        .query_one(
+            "INSERT INTO book_reviews (id, user_id, book_id, rating, body, status)
+             VALUES ($1, $2, $3, $4, $5, $6)
+             ON CONFLICT (user_id, book_id)
+             DO UPDATE SET
+                rating = EXCLUDED.rating,
+                body = EXCLUDED.body,
+                status = CASE
+                    WHEN book_reviews.body IS NOT DISTINCT FROM EXCLUDED.body
+                        AND book_reviews.status <> 'pending'
+                    THEN book_reviews.status
+                    ELSE EXCLUDED.status
+                END,
+                updated_at = CURRENT_TIMESTAMP
+             RETURNING id",
+            &[&Uuid::new_v4(), &user_id, &book_id, &body.rating, &text, &status.to_string()],
+        )
+        .await;
+
+    let review_id: Uuid = match saved {
+        Ok(row) => row.get("id"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Error saving review");
+        }
+    };
+
+    match client
+        .query_one(&format!("{} WHERE r.id = $1", REVIEW_SELECT), &[&review_id])
+        .await
+    {
+        Ok(row) => HttpResponse::Ok().json(Review::from_row(&row)),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error fetching review")
+        }
+    }
+}
+
+#[delete("/books/{id}/review")]
+pub async fn delete_review(
+    req: HttpRequest,
+    path: web::Path<(String,)>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let book_id = match Uuid::parse_str(&path.0) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().json("Invalid book ID"),
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    match client
+        .execute(
+            "DELETE FROM book_reviews WHERE user_id = $1 AND book_id = $2",
+            &[&user_id, &book_id],
+        )
+        .await
+    {
+        Ok(0) => HttpResponse::NotFound().json("Review not found"),
+        Ok(_) => HttpResponse::Ok().json("Review deleted successfully"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error deleting review")
+        }
+    }
+}
+
+#[post("/reviews/{id}/vote")]
+pub async fn vote_review(
+    req: HttpRequest,
+    path: web::Path<(String,)>,
+    body: ValidJson<VoteRequest>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let review_id = match Uuid::parse_str(&path.0) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().json("Invalid review ID"),
+    };
+
+    let mut client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let tx = match client.transaction().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    // Lock the review so concurrent votes don't lose count updates
+    let author: Uuid = match tx
+        .query_opt(
+            "SELECT user_id FROM book_reviews WHERE id = $1 AND status = 'approved' FOR UPDATE",
+            &[&review_id],
+        )
+        .await
+    {
+        Ok(Some(row)) => row.get("user_id"),
+        Ok(None) => return HttpResponse::NotFound().json("Review not found"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Database error");
+        }
+    };
+
+    if author == user_id {
+        return HttpResponse::BadRequest().json("You cannot vote on your own review");
+    }
+
+    let result = async {
+        tx.execute(
+            "INSERT INTO review_votes (review_id, user_id, helpful)
+             VALUES ($1, $2, $3)
+             ON CONFLICT (review_id, user_id)
+             DO UPDATE SET helpful = EXCLUDED.helpful, created_at = CURRENT_TIMESTAMP",
+            &[&review_id, &user_id, &body.helpful],
+        )
+        .await?;
+
+        tx.execute(
+            "UPDATE book_reviews r
+             SET helpful_count = v.helpful_count, vote_count = v.vote_count
+             FROM (
+                 SELECT COUNT(*) FILTER (WHERE helpful)::int AS helpful_count, COUNT(*)::int AS vote_count
+                 FROM review_votes
+                 WHERE review_id = $1
+             ) v
+             WHERE r.id = $1",
+            &[&review_id],
+        )
+        .await?;
+
+        let row = tx
+            .query_one(&format!("{} WHERE r.id = $1", REVIEW_SELECT), &[&review_id])
+            .await?;
+
+        Ok::<_, tokio_postgres::Error>(Review::from_row(&row))
+    }
+    .await;
+
+    let review = match result {
+        Ok(review) => review,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Error recording vote");
+        }
+    };
+
+    match tx.commit().await {
+        Ok(_) => HttpResponse::Ok().json(review),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error recording vote")
+        }
+    }
+}
+
+#[get("/reviews/moderation")]
+pub async fn get_moderation_queue(
+    req: HttpRequest,
+    query: ValidQuery<ModerationQuery>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    if let Err(response) = require_staff(&client, user_id).await {
+        return response;
+    }
+
+    let status = query.status.unwrap_or(ReviewStatus::Pending);
+    let limit = query.limit.unwrap_or(20);
+    let offset = query.page.unwrap_or(1).saturating_sub(1) * limit;
+
+    // Oldest first so the queue is worked through in order
+    let sql = format!(
+        "{} WHERE r.status = $1 AND r.body IS NOT NULL
+         ORDER BY r.updated_at LIMIT $2 OFFSET $3",
+        REVIEW_SELECT
+    );
+
+    match client
+        .query(&sql, &[&status.to_string(), &(limit as i64), &(offset as i64)])
+        .await
+    {
+        Ok(rows) => {
+            let reviews: Vec<Review> = rows.iter().map(Review::from_row).collect();
+            HttpResponse::Ok().json(reviews)
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error fetching moderation queue")
+        }
+    }
+}
+
+#[put("/reviews/{id}/status")]
+pub async fn moderate_review(
+    req: HttpRequest,
+    path: web::Path<(String,)>,
+    body: ValidJson<ModerationRequest>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let review_id = match Uuid::parse_str(&path.0) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().json("Invalid review ID"),
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    if let Err(response) = require_staff(&client, user_id).await {
+        return response;
+    }
+
+    match client
+        .execute(
+            "UPDATE book_reviews
+             SET status = $2, moderated_by = $3, moderated_at = CURRENT_TIMESTAMP
+             WHERE id = $1",
+            &[&review_id, &body.status.to_string(), &user_id],
+        )
+        .await
+    {
+        Ok(0) => return HttpResponse::NotFound().json("Review not found"),
+        Ok(_) => {}
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Error updating review");
+        }
+    }
+
+    match client
+        .query_one(&format!("{} WHERE r.id = $1", REVIEW_SELECT), &[&review_id])
+        .await
+    {
+        Ok(row) => HttpResponse::Ok().json(Review::from_row(&row)),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error fetching review")
+        }
+    }
+}
+
 // Query parameters
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, Validate)]
 pub struct GetBooksQuery {
     pub category: Option<String>,
+    pub sort: Option<String>,
+    #[validate(range(min = 1, max = MAX_PAGE))]
     pub page: Option<usize>,
+    #[validate(range(min = 1, max = MAX_PAGE_SIZE))]
     pub limit: Option<usize>,
 }
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, Validate)]
 pub struct PaginationQuery {
+    #[validate(range(min = 1, max = MAX_PAGE))]
     pub page: Option<usize>,
+    #[validate(range(min = 1, max = MAX_PAGE_SIZE))]
     pub limit: Option<usize>,
 }
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, Validate)]
 pub struct SearchQuery {
+    #[validate(custom(function = "not_blank"), length(max = 200))]
     pub q: String,
+    #[validate(range(min = 1, max = MAX_PAGE))]
+    pub page: Option<usize>,
+    #[validate(range(min = 1, max = MAX_PAGE_SIZE))]
+    pub limit: Option<usize>,
+}
+
+#[derive(serde::Deserialize, Validate)]
+pub struct ReviewsQuery {
+    pub sort: Option<String>,
+    #[validate(range(min = 1, max = MAX_PAGE))]
+    pub page: Option<usize>,
+    #[validate(range(min = 1, max = MAX_PAGE_SIZE))]
+    pub limit: Option<usize>,
+}
+
+#[derive(serde::Deserialize, Validate)]
+pub struct ModerationQuery {
+    pub status: Option<ReviewStatus>,
+    #[validate(range(min = 1, max = MAX_PAGE))]
     pub page: Option<usize>,
+    #[validate(range(min = 1, max = MAX_PAGE_SIZE))]
     pub limit: Option<usize>,
 }
\ No newline at end of file
@@ -9,6 +9,12 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
             .service(handlers::get_books)
             .service(handlers::get_book)
             .service(handlers::get_categories)
-            .service(handlers::get_books_by_category),
+            .service(handlers::get_books_by_category)
+            .service(handlers::get_book_reviews)
+            .service(handlers::save_review)
+            .service(handlers::delete_review)
+            .service(handlers::vote_review)
+            .service(handlers::get_moderation_queue)
+            .service(handlers::moderate_review),
     );
 }
\ No newline at end of file
@@ -1,6 +1,10 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
+use validator::Validate;
+
+// Longest review text accepted, in characters
+pub const MAX_REVIEW_LENGTH: u64 = 5000;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Book {
@@ -13,6 +17,7 @@ pub struct Book {
     pub format: BookFormat,
     pub file_path: String,
     pub total_pages: i32,
+    pub tags: Vec<String>,
     pub published_date: DateTime<Utc>,
     pub created_at: DateTime<Utc>,
 }
@@ -26,6 +31,8 @@ pub struct BookSummary {
     pub category_id: Uuid,
     pub category_name: String,
     pub format: BookFormat,
+    pub average_rating: Option<f64>,
+    pub rating_count: i64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -44,6 +51,16 @@ pub enum BookFormat {
     MOBI,
 }
 
+impl BookFormat {
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            BookFormat::PDF => "application/pdf",
+            BookFormat::EPUB => "application/epub+zip",
+            BookFormat::MOBI => "application/x-mobipocket-ebook",
+        }
+    }
+}
+
 impl std::fmt::Display for BookFormat {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -65,4 +82,89 @@ impl std::str::FromStr for BookFormat {
             _ => Err(format!("Unknown book format: {}", s)),
         }
     }
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReviewStatus {
+    Pending,
+    Approved,
+    Hidden,
+}
+
+impl std::fmt::Display for ReviewStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReviewStatus::Pending => write!(f, "pending"),
+            ReviewStatus::Approved => write!(f, "approved"),
+            ReviewStatus::Hidden => write!(f, "hidden"),
+        }
+    }
+}
+
+impl std::str::FromStr for ReviewStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(ReviewStatus::Pending),
+            "approved" => Ok(ReviewStatus::Approved),
+            "hidden" => Ok(ReviewStatus::Hidden),
+            _ => Err(format!("Unknown review status: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Review {
+    pub id: Uuid,
+    pub book_id: Uuid,
+    pub user_id: Uuid,
+    pub username: String,
+    pub rating: i16,
+    pub body: Option<String>,
+    pub status: ReviewStatus,
+    pub helpful_count: i32,
+    pub vote_count: i32,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Review {
+    pub fn from_row(row: &tokio_postgres::Row) -> Self {
+        Review {
+            id: row.get("id"),
+            book_id: row.get("book_id"),
+            user_id: row.get("user_id"),
+            username: row.get("username"),
+            rating: row.get("rating"),
+            body: row.get("body"),
+            status: row
+                .get::<_, String>("status")
+                .parse()
+                .unwrap_or(ReviewStatus::Pending),
+            helpful_count: row.get("helpful_count"),
+            vote_count: row.get("vote_count"),
+            created_at: row.get("created_at"),
+            updated_at: row.get("updated_at"),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ReviewRequest {
+    #[validate(range(min = 1, max = 5))]
+    pub rating: i16,
+    #[validate(length(max = MAX_REVIEW_LENGTH))]
+    pub body: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct VoteRequest {
+    pub helpful: bool,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ModerationRequest {
+    pub status: ReviewStatus,
+}
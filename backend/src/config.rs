@@ -1,15 +1,71 @@
+use chrono::{DateTime, Utc};
 use serde::Deserialize;
 use std::env;
+use std::path::Path;
+use std::sync::Arc;
 use std::time::Duration;
 
+use crate::auth::keys::KeyRing;
+use crate::auth::password_policy::PasswordPolicy;
+use crate::auth::models::Role;
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MailTransport {
+    Smtp,
+    // Writes messages to MAIL_OUTBOX_PATH instead of sending them, for local development
+    Outbox,
+}
+
+/// Single sign-on through an OpenID Connect provider, enabled by setting OIDC_ISSUER.
 #[derive(Clone, Debug, Deserialize)]
+pub struct OidcConfig {
+    pub issuer: String,
+    pub client_id: String,
+    // Confidential clients only; public clients rely on PKCE alone
+    pub client_secret: Option<String>,
+    pub redirect_url: String,
+    pub scopes: String,
+    // Frontend page that receives the session token in the URL fragment
+    pub login_redirect_url: String,
+    // ID token claim holding group or role names, e.g. "groups"
+    pub role_claim: String,
+    // Claim values mapped to library roles; when empty, roles are managed locally
+    pub role_mapping: Vec<(String, Role)>,
+    // Create accounts for unknown users on their first sign-in
+    pub provision_users: bool,
+}
+
+#[derive(Clone, Debug)]
 pub struct Config {
     pub host: String,
     pub port: u16,
+    pub public_url: String,
     pub database_url: String,
-    pub jwt_secret: String,
+    pub keys: Arc<KeyRing>,
     pub jwt_expires_in: Duration,
+    pub argon2_params: argon2::Params,
+    pub password_policy: Arc<PasswordPolicy>,
     pub book_storage_path: String,
+    pub avatar_storage_path: String,
+    pub data_export_storage_path: String,
+    pub data_export_expires_in: Duration,
+    pub analytics_aggregation_interval: Duration,
+    pub recommendation_refresh_interval: Duration,
+    pub mail_transport: MailTransport,
+    pub mail_from: String,
+    pub mail_outbox_path: String,
+    pub smtp_host: String,
+    pub smtp_port: u16,
+    pub smtp_username: Option<String>,
+    pub smtp_password: Option<String>,
+    pub password_reset_url: String,
+    pub password_reset_expires_in: Duration,
+    pub email_verification_url: String,
+    pub email_verification_expires_in: Duration,
+    pub require_verified_email: bool,
+    pub trust_proxy_headers: bool,
+    pub oidc: Option<OidcConfig>,
 }
 
 impl Config {
@@ -19,22 +75,197 @@ impl Config {
             .unwrap_or_else(|_| "8080".to_string())
             .parse()
             .expect("PORT must be a number");
+        // Base of absolute links handed to clients, e.g. in OPDS feeds
+        let public_url = env::var("PUBLIC_URL")
+            .unwrap_or_else(|_| format!("http://{}:{}", host, port))
+            .trim_end_matches('/')
+            .to_string();
         let database_url = env::var("DATABASE_URL")?;
-        let jwt_secret = env::var("JWT_SECRET")?;
+        let jwt_secret = env::var("JWT_SECRET").ok();
+        let jwt_issuer = env::var("JWT_ISSUER").unwrap_or_else(|_| public_url.clone());
+        let jwt_audience = env::var("JWT_AUDIENCE").unwrap_or_else(|_| "e-library".to_string());
+        // Tokens without a key id, issued before key ids were introduced, are accepted
+        // until this RFC 3339 time: the upgrade plus JWT_EXPIRATION. Unset, none are.
+        let jwt_legacy_until = env::var("JWT_LEGACY_TOKENS_UNTIL").ok().filter(|value| !value.is_empty()).map(|value| {
+            DateTime::parse_from_rfc3339(&value)
+                .expect("JWT_LEGACY_TOKENS_UNTIL must be an RFC 3339 timestamp")
+                .with_timezone(&Utc)
+        });
+        // Key pairs in JWT_KEYS_DIR when set, otherwise HS256 with JWT_SECRET. JWT_SECRET
+        // alongside a key directory keeps tokens issued before the switch valid.
+        let keys = match env::var("JWT_KEYS_DIR") {
+            Ok(dir) => KeyRing::from_dir(
+                Path::new(&dir),
+                &env::var("JWT_SIGNING_KEY_ID")?,
+                jwt_secret.as_deref(),
+                jwt_legacy_until,
+                jwt_issuer,
+                jwt_audience,
+            )
+            .expect("Failed to load JWT keys"),
+            Err(_) => KeyRing::from_secret(
+                jwt_secret.as_deref().expect("JWT_SECRET or JWT_KEYS_DIR must be set"),
+                jwt_legacy_until,
+                jwt_issuer,
+                jwt_audience,
+            ),
+        };
         let jwt_expiration = env::var("JWT_EXPIRATION")
             .unwrap_or_else(|_| "86400".to_string())
             .parse()
             .expect("JWT_EXPIRATION must be a number");
+        // Defaults follow the OWASP recommendation for Argon2id: 19 MiB, 2 passes, 1 lane
+        let argon2_params = argon2::Params::new(
+            env::var("ARGON2_MEMORY_KIB")
+                .unwrap_or_else(|_| "19456".to_string())
+                .parse()
+                .expect("ARGON2_MEMORY_KIB must be a number"),
+            env::var("ARGON2_ITERATIONS")
+                .unwrap_or_else(|_| "2".to_string())
+                .parse()
+                .expect("ARGON2_ITERATIONS must be a number"),
+            env::var("ARGON2_PARALLELISM")
+                .unwrap_or_else(|_| "1".to_string())
+                .parse()
+                .expect("ARGON2_PARALLELISM must be a number"),
+            None,
+        )
+        .expect("Invalid Argon2 parameters");
+        let password_min_length = env::var("PASSWORD_MIN_LENGTH")
+            .unwrap_or_else(|_| "8".to_string())
+            .parse()
+            .expect("PASSWORD_MIN_LENGTH must be a number");
+        let password_policy = PasswordPolicy::new(
+            password_min_length,
+            env::var("PASSWORD_BREACHED_LIST")
+                .ok()
+                .filter(|path| !path.is_empty())
+                .as_deref()
+                .map(Path::new),
+        )
+        .expect("Failed to read PASSWORD_BREACHED_LIST");
         let book_storage_path = env::var("BOOK_STORAGE_PATH")
             .unwrap_or_else(|_| "./storage/books".to_string());
+        let avatar_storage_path = env::var("AVATAR_STORAGE_PATH")
+            .unwrap_or_else(|_| "./storage/avatars".to_string());
+        let data_export_storage_path = env::var("DATA_EXPORT_STORAGE_PATH")
+            .unwrap_or_else(|_| "./storage/exports".to_string());
+        let data_export_expiration = env::var("DATA_EXPORT_EXPIRATION")
+            .unwrap_or_else(|_| "604800".to_string())
+            .parse()
+            .expect("DATA_EXPORT_EXPIRATION must be a number");
+        let analytics_aggregation_interval = env::var("ANALYTICS_AGGREGATION_INTERVAL")
+            .unwrap_or_else(|_| "60".to_string())
+            .parse()
+            .expect("ANALYTICS_AGGREGATION_INTERVAL must be a number");
+        let recommendation_refresh_interval = env::var("RECOMMENDATION_REFRESH_INTERVAL")
+            .unwrap_or_else(|_| "3600".to_string())
+            .parse()
+            .expect("RECOMMENDATION_REFRESH_INTERVAL must be a number");
+        let mail_transport = match env::var("MAIL_TRANSPORT")
+            .unwrap_or_else(|_| "outbox".to_string())
+            .as_str()
+        {
+            "smtp" => MailTransport::Smtp,
+            "outbox" => MailTransport::Outbox,
+            _ => panic!("MAIL_TRANSPORT must be smtp or outbox"),
+        };
+        let mail_from = env::var("MAIL_FROM")
+            .unwrap_or_else(|_| "E-Library <no-reply@localhost>".to_string());
+        let mail_outbox_path = env::var("MAIL_OUTBOX_PATH")
+            .unwrap_or_else(|_| "./storage/outbox".to_string());
+        let smtp_host = env::var("SMTP_HOST").unwrap_or_else(|_| "localhost".to_string());
+        let smtp_port = env::var("SMTP_PORT")
+            .unwrap_or_else(|_| "587".to_string())
+            .parse()
+            .expect("SMTP_PORT must be a number");
+        let smtp_username = env::var("SMTP_USERNAME").ok();
+        let smtp_password = env::var("SMTP_PASSWORD").ok();
+        // Page of the web app that reads the token from the query string
+        let password_reset_url = env::var("PASSWORD_RESET_URL")
+            .unwrap_or_else(|_| format!("{}/reset-password", public_url));
+        let password_reset_expiration = env::var("PASSWORD_RESET_EXPIRATION")
+            .unwrap_or_else(|_| "3600".to_string())
+            .parse()
+            .expect("PASSWORD_RESET_EXPIRATION must be a number");
+        let email_verification_url = env::var("EMAIL_VERIFICATION_URL")
+            .unwrap_or_else(|_| format!("{}/verify-email", public_url));
+        let email_verification_expiration = env::var("EMAIL_VERIFICATION_EXPIRATION")
+            .unwrap_or_else(|_| "86400".to_string())
+            .parse()
+            .expect("EMAIL_VERIFICATION_EXPIRATION must be a number");
+        // Whether users must verify their email address before they can open books
+        let require_verified_email = env::var("REQUIRE_VERIFIED_EMAIL")
+            .unwrap_or_else(|_| "false".to_string())
+            .parse()
+            .expect("REQUIRE_VERIFIED_EMAIL must be true or false");
+        // Only enable behind a reverse proxy that sets X-Forwarded-For itself
+        let trust_proxy_headers = env::var("TRUST_PROXY_HEADERS")
+            .unwrap_or_else(|_| "false".to_string())
+            .parse()
+            .expect("TRUST_PROXY_HEADERS must be true or false");
+        let oidc = match env::var("OIDC_ISSUER") {
+            Ok(issuer) => Some(OidcConfig {
+                issuer: issuer.trim_end_matches('/').to_string(),
+                client_id: env::var("OIDC_CLIENT_ID")?,
+                client_secret: env::var("OIDC_CLIENT_SECRET").ok(),
+                redirect_url: env::var("OIDC_REDIRECT_URL")
+                    .unwrap_or_else(|_| format!("{}/oidc/callback", public_url)),
+                scopes: env::var("OIDC_SCOPES")
+                    .unwrap_or_else(|_| "openid email profile".to_string()),
+                login_redirect_url: env::var("OIDC_LOGIN_REDIRECT_URL")
+                    .unwrap_or_else(|_| format!("{}/login/sso", public_url)),
+                role_claim: env::var("OIDC_ROLE_CLAIM").unwrap_or_else(|_| "groups".to_string()),
+                // Comma separated claim=role pairs, e.g. "library-staff=librarian,it-admins=admin"
+                role_mapping: env::var("OIDC_ROLE_MAPPING")
+                    .unwrap_or_default()
+                    .split(',')
+                    .filter(|pair| !pair.trim().is_empty())
+                    .map(|pair| {
+                        let (value, role) = pair
+                            .split_once('=')
+                            .expect("OIDC_ROLE_MAPPING entries must look like claim=role");
+                        let role = role.trim().parse().expect("OIDC_ROLE_MAPPING roles must be reader, librarian or admin");
+                        (value.trim().to_string(), role)
+                    })
+                    .collect(),
+                provision_users: env::var("OIDC_PROVISION_USERS")
+                    .unwrap_or_else(|_| "true".to_string())
+                    .parse()
+                    .expect("OIDC_PROVISION_USERS must be true or false"),
+            }),
+            Err(_) => None,
+        };
 
         Ok(Config {
             host,
             port,
+            public_url,
             database_url,
-            jwt_secret,
+            keys: Arc::new(keys),
             jwt_expires_in: Duration::from_secs(jwt_expiration),
+            argon2_params,
+            password_policy: Arc::new(password_policy),
             book_storage_path,
+            avatar_storage_path,
+            data_export_storage_path,
+            data_export_expires_in: Duration::from_secs(data_export_expiration),
+            analytics_aggregation_interval: Duration::from_secs(analytics_aggregation_interval),
+            recommendation_refresh_interval: Duration::from_secs(recommendation_refresh_interval),
+            mail_transport,
+            mail_from,
+            mail_outbox_path,
+            smtp_host,
+            smtp_port,
+            smtp_username,
+            smtp_password,
+            password_reset_url,
+            password_reset_expires_in: Duration::from_secs(password_reset_expiration),
+            email_verification_url,
+            email_verification_expires_in: Duration::from_secs(email_verification_expiration),
+            require_verified_email,
+            trust_proxy_headers,
+            oidc,
         })
     }
 }
\ No newline at end of file
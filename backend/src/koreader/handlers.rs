@@ -0,0 +1,499 @@
+use actix_web::{web, HttpResponse, Responder, get, post, put, delete, HttpRequest};
+use actix_web::http::StatusCode;
+use md5::{Digest, Md5};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::auth::{hash_password, throttle, verify_password, verify_token};
+use crate::config::Config;
+use crate::db::DbPool;
+use crate::validation::ValidJson;
+use crate::reader::progress;
+use crate::reader::sync::{SyncAction, SyncEventKind, SyncHub};
+use super::models::{Account, AccountRequest, DocumentProgress, ProgressRequest};
+
+// Error codes of the KOReader sync protocol, which clients show to the user
+const ERROR_UNAUTHORIZED: u32 = 2001;
+const ERROR_INVALID_FIELDS: u32 = 2003;
+const ERROR_DOCUMENT_MISSING: u32 = 2004;
+const ERROR_REGISTRATION_DISABLED: u32 = 2005;
+const ERROR_UNKNOWN: u32 = 2000;
+
+// Longest document key, device name or device id accepted
+const MAX_FIELD_LENGTH: usize = 64;
+const MAX_DEVICE_LENGTH: usize = 100;
+
+#[get("/healthcheck")]
+pub async fn healthcheck() -> impl Responder {
+    HttpResponse::Ok().json(json!({ "state": "OK" }))
+}
+
+// Accounts are created on the website; KOReader only signs in
+#[post("/users/create")]
+pub async fn create_user() -> impl Responder {
+    kosync_error(
+        StatusCode::PAYMENT_REQUIRED,
+        ERROR_REGISTRATION_DISABLED,
+        "User registration is disabled. Enable KOReader sync from your e-library profile.",
+    )
+}
+
+#[get("/users/auth")]
+pub async fn authorize_user(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return kosync_error(StatusCode::INTERNAL_SERVER_ERROR, ERROR_UNKNOWN, "Database error"),
+    };
+
+    match authenticate(&req, &client, &config).await {
+        Ok(_) => HttpResponse::Ok().json(json!({ "authorized": "OK" })),
+        Err(response) => response,
+    }
+}
+
+#[put("/syncs/progress")]
+pub async fn update_progress(
+    req: HttpRequest,
+    body: web::Json<ProgressRequest>,
+    pool: web::Data<DbPool>,
+    hub: web::Data<SyncHub>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return kosync_error(StatusCode::INTERNAL_SERVER_ERROR, ERROR_UNKNOWN, "Database error"),
+    };
+
+    let user_id = match authenticate(&req, &client, &config).await {
+        Ok(user_id) => user_id,
+        Err(response) => return response,
+    };
+
+    let document = match body.document.as_deref().filter(|document| !document.is_empty()) {
+        Some(document) => document,
+        None => return kosync_error(StatusCode::FORBIDDEN, ERROR_DOCUMENT_MISSING, "Field 'document' not provided."),
+    };
+
+    let (progress_value, percentage, device, device_id) = match (
+        body.progress.as_deref(),
+        body.percentage,
+        body.device.as_deref(),
+        body.device_id.as_deref(),
+    ) {
+        (Some(progress), Some(percentage), Some(device), Some(device_id))
+            if document.len() <= MAX_FIELD_LENGTH
+                && (0.0..=1.0).contains(&percentage)
+                && device.len() <= MAX_DEVICE_LENGTH
+                && device_id.len() <= MAX_DEVICE_LENGTH =>
+        {
+            (progress, percentage, device, device_id)
+        }
+        _ => return kosync_error(StatusCode::FORBIDDEN, ERROR_INVALID_FIELDS, "Invalid request"),
+    };
+
+    // Binary matching hashes the file contents; file name matching hashes the name
+    let book = client
+        .query_opt(
+            "SELECT id, total_pages FROM books
+             WHERE document_hash = $1 OR md5(regexp_replace(file_path, '^.*/', '')) = $1
+             ORDER BY (document_hash = $1) DESC
+             LIMIT 1",
+            &[&document],
+        )
+        .await;
+
+    let book: Option<(Uuid, i32)> = match book {
+        Ok(row) => row.map(|row| (row.get("id"), row.get("total_pages"))),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return kosync_error(StatusCode::INTERNAL_SERVER_ERROR, ERROR_UNKNOWN, "Database error");
+        }
+    };
+
+    let stored = client
+        .query_one(
+            "INSERT INTO koreader_progress (user_id, document, book_id, progress, percentage, device, device_id)
+             VALUES ($1, $2, $3, $4, $5, $6, $7)
+             ON CONFLICT (user_id, document)
+             DO UPDATE SET book_id = EXCLUDED.book_id, progress = EXCLUDED.progress,
+                 percentage = EXCLUDED.percentage, device = EXCLUDED.device,
+                 device_id = EXCLUDED.device_id, updated_at = CURRENT_TIMESTAMP
+             RETURNING updated_at",
+            &[
+                &user_id,
+                &document,
+                &book.map(|(book_id, _)| book_id),
+                &progress_value,
+                &percentage,
+                &device,
+                &device_id,
+            ],
+        )
+        .await;
+
+    let timestamp = match stored {
+        Ok(row) => row.get::<_, chrono::DateTime<chrono::Utc>>("updated_at").timestamp(),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return kosync_error(StatusCode::INTERNAL_SERVER_ERROR, ERROR_UNKNOWN, "Database error");
+        }
+    };
+
+    // KOReader positions are in its own units; the library tracks pages of the book
+    if let Some((book_id, total_pages)) = book.filter(|(_, total_pages)| *total_pages > 0) {
+        let current_page = ((percentage * total_pages as f64).round() as i32).clamp(1, total_pages);
+
+        match progress::save(&client, user_id, book_id, current_page, total_pages).await {
+            Ok(progress) => {
+                hub.publish(
+                    user_id,
+                    None,
+                    SyncEventKind::Progress,
+                    SyncAction::Upsert,
+                    book_id,
+                    serde_json::to_value(&progress).unwrap_or_default(),
+                );
+            }
+            Err(e) => {
+                eprintln!("Database error: {}", e);
+                return kosync_error(StatusCode::INTERNAL_SERVER_ERROR, ERROR_UNKNOWN, "Database error");
+            }
+        }
+    }
+
+    HttpResponse::Ok().json(json!({ "document": document, "timestamp": timestamp }))
+}
+
+#[get("/syncs/progress/{document}")]
+pub async fn get_progress(
+    req: HttpRequest,
+    path: web::Path<(String,)>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return kosync_error(StatusCode::INTERNAL_SERVER_ERROR, ERROR_UNKNOWN, "Database error"),
+    };
+
+    let user_id = match authenticate(&req, &client, &config).await {
+        Ok(user_id) => user_id,
+        Err(response) => return response,
+    };
+
+    let result = client
+        .query_opt(
+            "SELECT document, progress, percentage, device, device_id, updated_at
+             FROM koreader_progress
+             WHERE user_id = $1 AND document = $2",
+            &[&user_id, &path.0],
+        )
+        .await;
+
+    match result {
+        Ok(Some(row)) => HttpResponse::Ok().json(DocumentProgress {
+            document: row.get("document"),
+            progress: row.get("progress"),
+            percentage: row.get("percentage"),
+            device: row.get("device"),
+            device_id: row.get("device_id"),
+            timestamp: row.get::<_, chrono::DateTime<chrono::Utc>>("updated_at").timestamp(),
+        }),
+        // The protocol answers an unknown document with an empty object
+        Ok(None) => HttpResponse::Ok().json(json!({})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            kosync_error(StatusCode::INTERNAL_SERVER_ERROR, ERROR_UNKNOWN, "Database error")
+        }
+    }
+}
+
+#[get("/account")]
+pub async fn get_account(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    match client
+        .query_opt(
+            "SELECT username, created_at, updated_at FROM koreader_accounts WHERE user_id = $1",
+            &[&user_id],
+        )
+        .await
+    {
+        Ok(Some(row)) => HttpResponse::Ok().json(Account {
+            username: row.get("username"),
+            created_at: row.get("created_at"),
+            updated_at: row.get("updated_at"),
+        }),
+        Ok(None) => HttpResponse::NotFound().json("KOReader sync is not enabled"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error fetching KOReader account")
+        }
+    }
+}
+
+#[put("/account")]
+pub async fn save_account(
+    req: HttpRequest,
+    body: ValidJson<AccountRequest>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let username = match body.username.as_deref().map(str::trim) {
+        Some(username) => username.to_string(),
+        None => match client
+            .query_opt("SELECT username FROM users WHERE id = $1", &[&user_id])
+            .await
+        {
+            Ok(Some(row)) => row.get("username"),
+            Ok(None) => return HttpResponse::NotFound().json("User not found"),
+            Err(e) => {
+                eprintln!("Database error: {}", e);
+                return HttpResponse::InternalServerError().json("Database error");
+            }
+        },
+    };
+
+    match client
+        .query_opt(
+            "SELECT 1 FROM koreader_accounts WHERE username = $1 AND user_id <> $2",
+            &[&username, &user_id],
+        )
+        .await
+    {
+        Ok(Some(_)) => return HttpResponse::Conflict().json("This KOReader username is already taken"),
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Database error");
+        }
+    }
+
+    // KOReader sends md5(password) as its key, so that is what gets hashed
+    let key = format!("{:x}", Md5::digest(body.password.as_bytes()));
+    let key_hash = match hash_password(&key, &config) {
+        Ok(hash) => hash,
+        Err(_) => return HttpResponse::InternalServerError().json("Password hashing error"),
+    };
+
+    let result = client
+        .query_one(
+            "INSERT INTO koreader_accounts (user_id, username, key_hash)
+             VALUES ($1, $2, $3)
+             ON CONFLICT (user_id)
+             DO UPDATE SET username = EXCLUDED.username, key_hash = EXCLUDED.key_hash, updated_at = CURRENT_TIMESTAMP
+             RETURNING username, created_at, updated_at",
+            &[&user_id, &username, &key_hash],
+        )
+        .await;
+
+    match result {
+        Ok(row) => HttpResponse::Ok().json(Account {
+            username: row.get("username"),
+            created_at: row.get("created_at"),
+            updated_at: row.get("updated_at"),
+        }),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error saving KOReader account")
+        }
+    }
+}
+
+#[delete("/account")]
+pub async fn delete_account(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    match client
+        .execute("DELETE FROM koreader_accounts WHERE user_id = $1", &[&user_id])
+        .await
+    {
+        Ok(0) => HttpResponse::NotFound().json("KOReader sync is not enabled"),
+        Ok(_) => HttpResponse::Ok().json("KOReader sync disabled"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error disabling KOReader sync")
+        }
+    }
+}
+
+// KOReader signs every request with the x-auth-user and x-auth-key headers. Failures
+// count towards the same backoff as `login`; successes are not audited because
+// KOReader sends its credentials with every request.
+async fn authenticate(
+    req: &HttpRequest,
+    client: &deadpool_postgres::Client,
+    config: &Config,
+) -> Result<Uuid, HttpResponse> {
+    let header = |name: &str| {
+        req.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    };
+
+    let (username, key) = match (header("x-auth-user"), header("x-auth-key")) {
+        (Some(username), Some(key)) => (username, key),
+        _ => return Err(kosync_error(StatusCode::UNAUTHORIZED, ERROR_UNAUTHORIZED, "Unauthorized")),
+    };
+
+    let database_error = |e: tokio_postgres::Error| {
+        eprintln!("Database error: {}", e);
+        kosync_error(StatusCode::INTERNAL_SERVER_ERROR, ERROR_UNKNOWN, "Database error")
+    };
+
+    let attempt = throttle::Attempt::new(req, config, &username);
+    let retry_after = throttle::retry_after(client, &attempt).await.map_err(database_error)?;
+    if retry_after > 0 {
+        return Err(HttpResponse::TooManyRequests()
+            .insert_header(("Retry-After", retry_after.to_string()))
+            .json(json!({
+                "code": ERROR_UNAUTHORIZED,
+                "message": "Too many failed sign-in attempts; try again later",
+            })));
+    }
+
+    let row = client
+        .query_opt(
+            "SELECT user_id, key_hash FROM koreader_accounts WHERE username = $1",
+            &[&username],
+        )
+        .await
+        .map_err(database_error)?;
+
+    let user_id = match row {
+        Some(row) => {
+            let hash: String = row.get("key_hash");
+            if verify_password(&key, &hash).unwrap_or(false) {
+                return Ok(row.get("user_id"));
+            }
+            Some(row.get("user_id"))
+        }
+        None => {
+            throttle::verify_dummy_password(&key, config);
+            None
+        }
+    };
+
+    throttle::record(client, &attempt, user_id, throttle::Method::Koreader, throttle::Outcome::InvalidCredentials)
+        .await
+        .map_err(database_error)?;
+    Err(kosync_error(StatusCode::UNAUTHORIZED, ERROR_UNAUTHORIZED, "Unauthorized"))
+}
+
+fn kosync_error(status: StatusCode, code: u32, message: &str) -> HttpResponse {
+    HttpResponse::build(status).json(json!({ "code": code, "message": message }))
+}
\ No newline at end of file
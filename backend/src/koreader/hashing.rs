@@ -0,0 +1,43 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use md5::{Digest, Md5};
+
+// KOReader samples 1 KiB at each of a dozen offsets growing by a factor of four
+const SAMPLE_SIZE: usize = 1024;
+
+/// The document hash KOReader computes with its default "binary" matching: an MD5
+/// over 1 KiB samples at offsets 1024 << 2i for i in -1..=10, stopping at the end
+/// of the file. LuaJIT's `bit.lshift` wraps the i = -1 shift around, so that first
+/// sample is taken at offset 0.
+pub fn partial_md5(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Md5::new();
+    let mut buffer = [0u8; SAMPLE_SIZE];
+
+    for i in -1i32..=10 {
+        let offset = if i < 0 { 0 } else { (SAMPLE_SIZE as u64) << (2 * i) };
+        file.seek(SeekFrom::Start(offset))?;
+
+        let read = read_sample(&mut file, &mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+// Fills the buffer unless the file ends first
+fn read_sample(file: &mut File, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match file.read(&mut buffer[filled..])? {
+            0 => break,
+            read => filled += read,
+        }
+    }
+    Ok(filled)
+}
\ No newline at end of file
@@ -0,0 +1,145 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use chrono::Utc;
+use log::{error, info};
+use uuid::Uuid;
+
+use crate::db::DbPool;
+use super::hashing;
+
+// How often books without a document hash are looked for
+const HASH_INTERVAL: Duration = Duration::from_secs(600);
+
+// Books hashed per run, to keep each run short
+const HASH_BATCH_SIZE: i64 = 200;
+
+// Wait before retrying a book whose file couldn't be hashed, doubled for each
+// further failure up to the maximum
+const RETRY_BASE_SECONDS: i64 = 3600;
+const RETRY_MAX_SECONDS: i64 = 7 * 24 * 3600;
+
+// How long to leave a book alone after its `failures`-th failed hash
+fn retry_delay(failures: i32) -> chrono::Duration {
+    let doublings = failures.saturating_sub(1).clamp(0, 30) as u32;
+    let seconds = RETRY_BASE_SECONDS.saturating_mul(1 << doublings).min(RETRY_MAX_SECONDS);
+    chrono::Duration::seconds(seconds)
+}
+
+/// Periodically computes KOReader document hashes for books that don't have one yet.
+pub fn spawn_document_hasher(pool: DbPool, storage_path: String) {
+    actix_web::rt::spawn(async move {
+        let mut interval = tokio::time::interval(HASH_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            let client = match pool.get().await {
+                Ok(client) => client,
+                Err(e) => {
+                    error!("Document hasher could not get a connection: {}", e);
+                    continue;
+                }
+            };
+
+            // Books that failed recently are skipped so they can't fill every batch
+            let books: Vec<(Uuid, String, i32)> = match client
+                .query(
+                    "SELECT id, file_path, document_hash_failures FROM books
+                     WHERE document_hash IS NULL
+                       AND (document_hash_retry_at IS NULL OR document_hash_retry_at <= CURRENT_TIMESTAMP)
+                     ORDER BY created_at LIMIT $1",
+                    &[&HASH_BATCH_SIZE],
+                )
+                .await
+            {
+                Ok(rows) => rows
+                    .iter()
+                    .map(|row| (row.get("id"), row.get("file_path"), row.get("document_hash_failures")))
+                    .collect(),
+                Err(e) => {
+                    error!("Document hasher could not list books: {}", e);
+                    continue;
+                }
+            };
+
+            let mut hashed = 0;
+            for (book_id, file_path, failures) in books {
+                let path = PathBuf::from(&storage_path).join(&file_path);
+                let hash = match actix_web::rt::task::spawn_blocking(move || hashing::partial_md5(&path)).await {
+                    Ok(Ok(hash)) => hash,
+                    Ok(Err(e)) => {
+                        error!("Could not hash {}: {}", file_path, e);
+                        record_failure(&client, book_id, failures.saturating_add(1)).await;
+                        continue;
+                    }
+                    Err(e) => {
+                        error!("Document hashing task failed: {}", e);
+                        record_failure(&client, book_id, failures.saturating_add(1)).await;
+                        continue;
+                    }
+                };
+
+                match client
+                    .execute(
+                        "UPDATE books SET document_hash = $2, document_hash_failures = 0, document_hash_retry_at = NULL
+                         WHERE id = $1",
+                        &[&book_id, &hash],
+                    )
+                    .await
+                {
+                    Ok(_) => hashed += 1,
+                    Err(e) => error!("Could not store document hash: {}", e),
+                }
+            }
+
+            if hashed > 0 {
+                info!("Computed KOReader document hashes for {} books", hashed);
+            }
+        }
+    });
+}
+
+// Puts the book off until its retry delay has passed
+async fn record_failure(client: &deadpool_postgres::Client, book_id: Uuid, failures: i32) {
+    let retry_at = Utc::now() + retry_delay(failures);
+    if let Err(e) = client
+        .execute(
+            "UPDATE books SET document_hash_failures = $2, document_hash_retry_at = $3 WHERE id = $1",
+            &[&book_id, &failures, &retry_at],
+        )
+        .await
+    {
+        error!("Could not record failed document hash: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_failure_waits_the_base_delay() {
+        assert_eq!(retry_delay(1), chrono::Duration::seconds(RETRY_BASE_SECONDS));
+    }
+
+    #[test]
+    fn delay_doubles_with_each_failure() {
+        assert_eq!(retry_delay(2), chrono::Duration::hours(2));
+        assert_eq!(retry_delay(3), chrono::Duration::hours(4));
+        assert_eq!(retry_delay(6), chrono::Duration::hours(32));
+    }
+
+    #[test]
+    fn delay_stops_growing_at_the_maximum() {
+        assert_eq!(retry_delay(9), chrono::Duration::seconds(RETRY_MAX_SECONDS));
+        assert_eq!(retry_delay(1000), chrono::Duration::seconds(RETRY_MAX_SECONDS));
+        assert_eq!(retry_delay(i32::MAX), chrono::Duration::seconds(RETRY_MAX_SECONDS));
+    }
+
+    #[test]
+    fn nonsensical_counts_are_treated_as_a_first_failure() {
+        assert_eq!(retry_delay(0), chrono::Duration::seconds(RETRY_BASE_SECONDS));
+        assert_eq!(retry_delay(-5), chrono::Duration::seconds(RETRY_BASE_SECONDS));
+    }
+}
\ No newline at end of file
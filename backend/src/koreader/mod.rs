@@ -0,0 +1,21 @@
+pub mod handlers;
+pub mod hashing;
+pub mod jobs;
+pub mod models;
+
+use actix_web::web;
+
+// KOReader is pointed at {PUBLIC_URL}/koreader as a custom sync server
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/koreader")
+            .service(handlers::healthcheck)
+            .service(handlers::create_user)
+            .service(handlers::authorize_user)
+            .service(handlers::update_progress)
+            .service(handlers::get_progress)
+            .service(handlers::get_account)
+            .service(handlers::save_account)
+            .service(handlers::delete_account)
+    );
+}
\ No newline at end of file
@@ -0,0 +1,43 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::validation::not_blank;
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct AccountRequest {
+    // Defaults to the account's own username
+    #[validate(custom(function = "not_blank"), length(max = 100))]
+    pub username: Option<String>,
+    // The password typed into KOReader; it doesn't have to be the account password
+    #[validate(length(min = 1))]
+    pub password: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Account {
+    pub username: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Body of KOReader's `PUT /syncs/progress`. Every field is optional here so that
+/// missing ones get the protocol's own error codes instead of a generic 400.
+#[derive(Debug, Deserialize)]
+pub struct ProgressRequest {
+    pub document: Option<String>,
+    pub progress: Option<String>,
+    pub percentage: Option<f64>,
+    pub device: Option<String>,
+    pub device_id: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DocumentProgress {
+    pub document: String,
+    pub progress: String,
+    pub percentage: f64,
+    pub device: String,
+    pub device_id: String,
+    pub timestamp: i64,
+}
\ No newline at end of file
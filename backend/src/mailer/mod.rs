@@ -0,0 +1,55 @@
+pub mod outbox;
+pub mod smtp;
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use lettre::message::{header::ContentType, Mailbox};
+use lettre::Message;
+
+use crate::config::{Config, MailTransport};
+
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum MailError {
+    #[error("Invalid address: {0}")]
+    Address(#[from] lettre::address::AddressError),
+    #[error("Failed to build message: {0}")]
+    Message(#[from] lettre::error::Error),
+    #[error("SMTP error: {0}")]
+    Smtp(#[from] lettre::transport::smtp::Error),
+    #[error("Failed to write to outbox: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// Delivers outgoing email. Handlers take it as `web::Data<dyn Mailer>` so the
+/// transport can be swapped through configuration.
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, email: Email) -> Result<(), MailError>;
+}
+
+pub fn from_config(config: &Config) -> Result<Arc<dyn Mailer>, MailError> {
+    let mailer: Arc<dyn Mailer> = match config.mail_transport {
+        MailTransport::Smtp => Arc::new(smtp::SmtpMailer::new(config)?),
+        MailTransport::Outbox => Arc::new(outbox::OutboxMailer::new(config)?),
+    };
+    Ok(mailer)
+}
+
+// Both transports send the same plain text message
+fn build_message(from: &Mailbox, email: Email) -> Result<Message, MailError> {
+    let message = Message::builder()
+        .from(from.clone())
+        .to(email.to.parse()?)
+        .subject(email.subject)
+        .header(ContentType::TEXT_PLAIN)
+        .body(email.body)?;
+    Ok(message)
+}
\ No newline at end of file
@@ -0,0 +1,48 @@
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use lettre::message::Mailbox;
+use log::info;
+use uuid::Uuid;
+
+use crate::config::Config;
+use super::{build_message, Email, MailError, Mailer};
+
+/// Writes each message to `MAIL_OUTBOX_PATH` as an `.eml` file instead of sending it,
+/// so reset links can be followed locally without a mail server.
+pub struct OutboxMailer {
+    from: Mailbox,
+    path: PathBuf,
+}
+
+impl OutboxMailer {
+    pub fn new(config: &Config) -> Result<Self, MailError> {
+        let path = PathBuf::from(&config.mail_outbox_path);
+        std::fs::create_dir_all(&path)?;
+
+        Ok(OutboxMailer {
+            from: config.mail_from.parse()?,
+            path,
+        })
+    }
+}
+
+#[async_trait]
+impl Mailer for OutboxMailer {
+    async fn send(&self, email: Email) -> Result<(), MailError> {
+        let to = email.to.clone();
+        let subject = email.subject.clone();
+        let message = build_message(&self.from, email)?;
+
+        // Timestamp first so the files sort in the order they were sent
+        let file = self.path.join(format!(
+            "{}-{}.eml",
+            chrono::Utc::now().format("%Y%m%dT%H%M%S%.3f"),
+            Uuid::new_v4()
+        ));
+        tokio::fs::write(&file, message.formatted()).await?;
+
+        info!("Mail to {} ({}) written to {}", to, subject, file.display());
+        Ok(())
+    }
+}
\ No newline at end of file
@@ -8,6 +8,15 @@ mod auth;
 mod catalog;
 mod reader;
 mod analytics;
+mod admin;
+mod shelves;
+mod recommendations;
+mod opds;
+mod koreader;
+mod mailer;
+mod oidc;
+mod privacy;
+mod validation;
 mod db;
 mod config;
 mod routes;
@@ -22,6 +31,36 @@ async fn main() -> std::io::Result<()> {
     
     // Set up database connection pool
     let pool = db::init_pool(&config.database_url).await.expect("Failed to create pool");
+
+    // Outgoing email, over SMTP or into a local outbox directory
+    let mailer = web::Data::from(mailer::from_config(&config).expect("Failed to set up mailer"));
+
+    // Single sign-on, when an identity provider is configured
+    let oidc_client = config.oidc.clone().map(|oidc| {
+        web::Data::new(oidc::provider::OidcClient::new(oidc).expect("Failed to set up OIDC client"))
+    });
+
+    // Shared across workers so every device of a user lands on the same hub
+    let sync_hub = web::Data::new(reader::sync::SyncHub::new());
+
+    // Close reading sessions whose reader went idle without ending them
+    analytics::jobs::spawn_session_reaper(pool.clone());
+
+    // Roll reading events into daily, weekly and monthly summaries
+    analytics::jobs::spawn_analytics_aggregator(pool.clone(), config.analytics_aggregation_interval);
+
+    // Recompute book similarities and per-user recommendations
+    recommendations::jobs::spawn_recommendation_builder(pool.clone(), config.recommendation_refresh_interval);
+
+    // Hash book files the way KOReader identifies documents
+    koreader::jobs::spawn_document_hasher(pool.clone(), config.book_storage_path.clone());
+
+    // Build requested personal data exports and delete them once they expire
+    privacy::jobs::spawn_export_worker(
+        pool.clone(),
+        config.data_export_storage_path.clone(),
+        config.data_export_expires_in,
+    );
     
     // Log startup information
     info!("Starting server at http://{}:{}", config.host, config.port);
@@ -32,20 +71,34 @@ async fn main() -> std::io::Result<()> {
         let cors = Cors::default()
             .allowed_origin("https://book.margabagus.com")
             .allowed_methods(vec!["GET", "POST", "PUT", "DELETE"])
-            .allowed_headers(vec!["Authorization", "Content-Type"])
+            .allowed_headers(vec!["Authorization", "Content-Type", "X-Sync-Session", "X-Auth-User", "X-Auth-Key"])
             .max_age(3600);
 
-        App::new()
+        let mut app = App::new()
             // Add database pool to app state
             .app_data(web::Data::new(pool.clone()))
             // Add config to app state
             .app_data(web::Data::new(config.clone()))
+            // Add reader sync hub to app state
+            .app_data(sync_hub.clone())
+            // Add mailer to app state
+            .app_data(mailer.clone())
+            // Refuse tokens revoked by a password change, sign-out or account deletion
+            .wrap(middleware::from_fn(auth::revocation::reject_revoked_tokens))
+            // Let integrations authenticate with API keys; runs before the check above
+            .wrap(middleware::from_fn(auth::api_keys::authenticate_api_keys))
             // Enable logger and compression
             .wrap(middleware::Logger::default())
             .wrap(middleware::Compress::default())
             .wrap(cors)
             // Configure routes
-            .configure(routes::configure)
+            .configure(routes::configure);
+
+        if let Some(oidc_client) = &oidc_client {
+            app = app.app_data(oidc_client.clone());
+        }
+
+        app
     })
     .bind(format!("{}:{}", config.host, config.port))?
     .run()
@@ -0,0 +1,186 @@
+use deadpool_postgres::Transaction;
+use rand::Rng;
+use uuid::Uuid;
+
+use crate::auth::models::Role;
+use crate::auth::{generate_secret_token, hash_password};
+use crate::config::{Config, OidcConfig};
+use crate::shelves;
+use super::provider::IdTokenClaims;
+
+// Attempts at finding a free username before giving up
+const USERNAME_ATTEMPTS: usize = 5;
+
+pub enum Resolution {
+    Existing(Uuid),
+    Linked(Uuid),
+    Provisioned(Uuid),
+    // No local account matched and provisioning is off, or the token lacks an email
+    NoAccount,
+}
+
+/// Finds the library user behind an ID token: a previously linked identity first,
+/// then an account with the same email if the provider has verified it, and
+/// otherwise a newly created account.
+pub async fn resolve_user(
+    tx: &Transaction<'_>,
+    config: &Config,
+    oidc: &OidcConfig,
+    claims: &IdTokenClaims,
+) -> Result<Resolution, tokio_postgres::Error> {
+    let linked = tx
+        .query_opt(
+            "UPDATE user_identities SET email = $3, last_login_at = CURRENT_TIMESTAMP
+             WHERE issuer = $1 AND subject = $2
+             RETURNING user_id",
+            &[&oidc.issuer, &claims.sub, &claims.email],
+        )
+        .await?;
+    if let Some(row) = linked {
+        return Ok(Resolution::Existing(row.get("user_id")));
+    }
+
+    let email = match claims.email.as_deref() {
+        Some(email) if !email.is_empty() => email,
+        _ => return Ok(Resolution::NoAccount),
+    };
+
+    // An unverified email at the provider proves nothing about the local account
+    if claims.email_verified {
+        let existing = tx
+            .query_opt("SELECT id FROM users WHERE lower(email) = lower($1)", &[&email])
+            .await?;
+        if let Some(row) = existing {
+            let user_id: Uuid = row.get("id");
+            tx.execute(
+                "UPDATE users SET email_verified_at = COALESCE(email_verified_at, CURRENT_TIMESTAMP) WHERE id = $1",
+                &[&user_id],
+            )
+            .await?;
+            link(tx, oidc, claims, user_id).await?;
+            return Ok(Resolution::Linked(user_id));
+        }
+    }
+
+    if !oidc.provision_users {
+        return Ok(Resolution::NoAccount);
+    }
+
+    // The address may belong to a local account the provider hasn't vouched for
+    let taken = tx
+        .query_opt("SELECT 1 FROM users WHERE lower(email) = lower($1)", &[&email])
+        .await?;
+    if taken.is_some() {
+        return Ok(Resolution::NoAccount);
+    }
+
+    let username = match free_username(tx, claims, email).await? {
+        Some(username) => username,
+        None => return Ok(Resolution::NoAccount),
+    };
+
+    // Nobody knows this password; the user can set one through the reset flow
+    let password_hash = match hash_password(&generate_secret_token(), config) {
+        Ok(hash) => hash,
+        Err(_) => return Ok(Resolution::NoAccount),
+    };
+
+    let user_id = Uuid::new_v4();
+    tx.execute(
+        "INSERT INTO users (id, username, email, password_hash, email_verified_at)
+         VALUES ($1, $2, $3, $4, CASE WHEN $5 THEN CURRENT_TIMESTAMP END)",
+        &[&user_id, &username, &email, &password_hash, &claims.email_verified],
+    )
+    .await?;
+    shelves::create_default_shelves(tx, user_id).await?;
+    link(tx, oidc, claims, user_id).await?;
+
+    Ok(Resolution::Provisioned(user_id))
+}
+
+/// Applies the configured claim-to-role mapping. The provider is authoritative
+/// once a mapping exists, so users it no longer lists fall back to reader.
+pub async fn sync_role(
+    tx: &Transaction<'_>,
+    config: &OidcConfig,
+    claims: &IdTokenClaims,
+    user_id: Uuid,
+) -> Result<(), tokio_postgres::Error> {
+    if config.role_mapping.is_empty() {
+        return Ok(());
+    }
+
+    let values: Vec<&str> = match claims.other.get(&config.role_claim) {
+        Some(serde_json::Value::String(value)) => vec![value.as_str()],
+        Some(serde_json::Value::Array(values)) => values.iter().filter_map(|v| v.as_str()).collect(),
+        _ => Vec::new(),
+    };
+
+    let role = config
+        .role_mapping
+        .iter()
+        .filter(|(value, _)| values.contains(&value.as_str()))
+        .map(|(_, role)| *role)
+        .max_by_key(|role| match role {
+            Role::Reader => 0,
+            Role::Librarian => 1,
+            Role::Admin => 2,
+        })
+        .unwrap_or(Role::Reader);
+
+    tx.execute(
+        "UPDATE users SET role = $2 WHERE id = $1",
+        &[&user_id, &role.to_string()],
+    )
+    .await?;
+
+    Ok(())
+}
+
+async fn link(
+    tx: &Transaction<'_>,
+    config: &OidcConfig,
+    claims: &IdTokenClaims,
+    user_id: Uuid,
+) -> Result<(), tokio_postgres::Error> {
+    tx.execute(
+        "INSERT INTO user_identities (user_id, issuer, subject, email) VALUES ($1, $2, $3, $4)",
+        &[&user_id, &config.issuer, &claims.sub, &claims.email],
+    )
+    .await?;
+    Ok(())
+}
+
+// The provider's preferred username if free, else with a random suffix
+async fn free_username(
+    tx: &Transaction<'_>,
+    claims: &IdTokenClaims,
+    email: &str,
+) -> Result<Option<String>, tokio_postgres::Error> {
+    let base: String = claims
+        .preferred_username
+        .as_deref()
+        .unwrap_or_else(|| email.split('@').next().unwrap_or(email))
+        .chars()
+        .filter(|c| c.is_alphanumeric() || matches!(c, '.' | '_' | '-'))
+        .take(40)
+        .collect();
+    let base = if base.is_empty() { "reader".to_string() } else { base };
+
+    for attempt in 0..USERNAME_ATTEMPTS {
+        let candidate = if attempt == 0 {
+            base.clone()
+        } else {
+            format!("{}-{:04}", base, rand::thread_rng().gen_range(0..10_000))
+        };
+
+        let taken = tx
+            .query_opt("SELECT 1 FROM users WHERE username = $1", &[&candidate])
+            .await?;
+        if taken.is_none() {
+            return Ok(Some(candidate));
+        }
+    }
+
+    Ok(None)
+}
\ No newline at end of file
@@ -0,0 +1,229 @@
+use actix_web::cookie::{time, Cookie, SameSite};
+use actix_web::{web, HttpResponse, Responder, get, HttpRequest};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use sha2::{Digest, Sha256};
+
+use crate::auth::models::{User, USER_COLUMNS};
+use crate::auth::sessions;
+use crate::auth::throttle::{self, Method, Outcome};
+use crate::auth::{generate_secret_token, generate_token};
+use crate::config::{Config, OidcConfig};
+use crate::db::DbPool;
+use super::accounts::{self, Resolution};
+use super::models::CallbackQuery;
+use super::provider::OidcClient;
+
+// How long a login may take at the provider before its state is discarded
+const LOGIN_STATE_MAX_AGE_SECONDS: f64 = 600.0;
+
+// Holds the state in the browser that started the login, so a callback URL made
+// from someone else's login can't sign this browser in to their account
+const STATE_COOKIE: &str = "oidc_state";
+
+// Error codes of OAuth 2.0 and OpenID Connect passed on to the frontend; anything
+// else a provider sends is reported as `sso_failed`
+const PROVIDER_ERRORS: [&str; 11] = [
+    "access_denied",
+    "invalid_request",
+    "unauthorized_client",
+    "unsupported_response_type",
+    "invalid_scope",
+    "server_error",
+    "temporarily_unavailable",
+    "interaction_required",
+    "login_required",
+    "account_selection_required",
+    "consent_required",
+];
+
+#[get("/login")]
+pub async fn login(
+    pool: web::Data<DbPool>,
+    oidc: Option<web::Data<OidcClient>>,
+) -> impl Responder {
+    let oidc = match oidc {
+        Some(oidc) => oidc,
+        None => return HttpResponse::NotFound().json("Single sign-on is not configured"),
+    };
+
+    let state = generate_secret_token();
+    let nonce = generate_secret_token();
+    let code_verifier = generate_secret_token();
+    let code_challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()));
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    // Abandoned logins are cleared out as new ones start
+    let result = async {
+        client
+            .execute(
+                "DELETE FROM oidc_login_states
+                 WHERE created_at < CURRENT_TIMESTAMP - make_interval(secs => $1)",
+                &[&LOGIN_STATE_MAX_AGE_SECONDS],
+            )
+            .await?;
+        client
+            .execute(
+                "INSERT INTO oidc_login_states (state, code_verifier, nonce) VALUES ($1, $2, $3)",
+                &[&state, &code_verifier, &nonce],
+            )
+            .await
+    }
+    .await;
+
+    if let Err(e) = result {
+        eprintln!("Database error: {}", e);
+        return HttpResponse::InternalServerError().json("Error starting single sign-on");
+    }
+
+    match oidc.authorization_url(&state, &nonce, &code_challenge).await {
+        Ok(url) => HttpResponse::Found()
+            .insert_header(("Location", url))
+            .cookie(state_cookie(oidc.config(), state))
+            .finish(),
+        Err(e) => {
+            eprintln!("OIDC error: {}", e);
+            HttpResponse::BadGateway().json("Identity provider is unavailable")
+        }
+    }
+}
+
+// The browser arrives here from the provider, so outcomes are redirects to the
+// frontend rather than JSON
+#[get("/callback")]
+pub async fn callback(
+    req: HttpRequest,
+    query: web::Query<CallbackQuery>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    oidc: Option<web::Data<OidcClient>>,
+) -> impl Responder {
+    let oidc = match oidc {
+        Some(oidc) => oidc,
+        None => return HttpResponse::NotFound().json("Single sign-on is not configured"),
+    };
+    let login_redirect_url = &oidc.config().login_redirect_url;
+
+    if let Some(error) = &query.error {
+        let error = PROVIDER_ERRORS.iter().find(|known| *known == error).copied().unwrap_or("sso_failed");
+        return finish(login_redirect_url, "error", error);
+    }
+
+    let (code, state) = match (&query.code, &query.state) {
+        (Some(code), Some(state)) => (code, state),
+        _ => return finish(login_redirect_url, "error", "invalid_request"),
+    };
+
+    if req.cookie(STATE_COOKIE).as_ref().map(Cookie::value) != Some(state.as_str()) {
+        return finish(login_redirect_url, "error", "invalid_state");
+    }
+
+    let mut client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return finish(login_redirect_url, "error", "server_error"),
+    };
+
+    // Each state is good for one callback only
+    let pending = client
+        .query_opt(
+            "DELETE FROM oidc_login_states
+             WHERE state = $1 AND created_at > CURRENT_TIMESTAMP - make_interval(secs => $2)
+             RETURNING code_verifier, nonce",
+            &[state, &LOGIN_STATE_MAX_AGE_SECONDS],
+        )
+        .await;
+
+    let (code_verifier, nonce): (String, String) = match pending {
+        Ok(Some(row)) => (row.get("code_verifier"), row.get("nonce")),
+        Ok(None) => return finish(login_redirect_url, "error", "invalid_state"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return finish(login_redirect_url, "error", "server_error");
+        }
+    };
+
+    let claims = match oidc.exchange_code(code, &code_verifier, &nonce).await {
+        Ok(claims) => claims,
+        Err(e) => {
+            eprintln!("OIDC error: {}", e);
+            return finish(login_redirect_url, "error", "sso_failed");
+        }
+    };
+
+    let tx = match client.transaction().await {
+        Ok(tx) => tx,
+        Err(_) => return finish(login_redirect_url, "error", "server_error"),
+    };
+
+    let result = async {
+        let resolution = accounts::resolve_user(&tx, &config, oidc.config(), &claims).await?;
+        let user_id = match resolution {
+            Resolution::Existing(user_id) | Resolution::Linked(user_id) | Resolution::Provisioned(user_id) => user_id,
+            Resolution::NoAccount => return Ok(None),
+        };
+        accounts::sync_role(&tx, oidc.config(), &claims, user_id).await?;
+
+        let row = tx
+            .query_one(&format!("SELECT {} FROM users WHERE id = $1", USER_COLUMNS), &[&user_id])
+            .await?;
+        tx.commit().await?;
+
+        Ok::<_, tokio_postgres::Error>(Some(User::from_row(&row)))
+    }
+    .await;
+
+    let user = match result {
+        Ok(Some(user)) => user,
+        Ok(None) => return finish(login_redirect_url, "error", "no_account"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return finish(login_redirect_url, "error", "server_error");
+        }
+    };
+
+    // Second factors are the provider's business for these logins
+    let attempt = throttle::Attempt::new(&req, &config, &user.email);
+    if let Err(e) = throttle::record(&client, &attempt, Some(user.id), Method::Oidc, Outcome::Success).await {
+        eprintln!("Database error: {}", e);
+    }
+
+    let session_id = match sessions::start(&client, user.id, &req, None, &config).await {
+        Ok(id) => id,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return finish(login_redirect_url, "error", "server_error");
+        }
+    };
+
+    match generate_token(&user, session_id, &config) {
+        Ok(token) => finish(login_redirect_url, "token", &token),
+        Err(_) => finish(login_redirect_url, "error", "server_error"),
+    }
+}
+
+// Hands the result to the frontend in the URL fragment, which browsers never send
+// to servers, so the token stays out of access logs. `value` is a token or one of
+// our own codes, never text from the request, so it needs no escaping.
+fn finish(login_redirect_url: &str, key: &str, value: &str) -> HttpResponse {
+    HttpResponse::Found()
+        .insert_header(("Location", format!("{}#{}={}", login_redirect_url, key, value)))
+        .finish()
+}
+
+// Sent only to the callback, and Lax so it survives the top-level redirect back from the provider
+fn state_cookie(settings: &OidcConfig, state: String) -> Cookie<'static> {
+    let path = reqwest::Url::parse(&settings.redirect_url)
+        .map(|url| url.path().to_string())
+        .unwrap_or_else(|_| "/".to_string());
+
+    Cookie::build(STATE_COOKIE, state)
+        .path(path)
+        .http_only(true)
+        .secure(settings.redirect_url.starts_with("https://"))
+        .same_site(SameSite::Lax)
+        .max_age(time::Duration::seconds(LOGIN_STATE_MAX_AGE_SECONDS as i64))
+        .finish()
+}
\ No newline at end of file
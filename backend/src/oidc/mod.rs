@@ -0,0 +1,15 @@
+pub mod accounts;
+pub mod handlers;
+pub mod models;
+pub mod provider;
+
+use actix_web::web;
+
+// Kept out of the /auth scope, whose routes would otherwise shadow these
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/oidc")
+            .service(handlers::login)
+            .service(handlers::callback)
+    );
+}
\ No newline at end of file
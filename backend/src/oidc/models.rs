@@ -0,0 +1,9 @@
+use serde::Deserialize;
+
+/// Query string the identity provider sends back to the callback.
+#[derive(Debug, Deserialize)]
+pub struct CallbackQuery {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+}
\ No newline at end of file
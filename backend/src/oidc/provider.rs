@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use reqwest::Url;
+use serde::Deserialize;
+use tokio::sync::RwLock;
+
+use crate::config::OidcConfig;
+
+// Discovery document and signing keys are refetched after this long
+const METADATA_MAX_AGE: Duration = Duration::from_secs(60 * 60);
+
+// Keys may rotate at any time; an unknown key id triggers a refetch, but no more often than this
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, thiserror::Error)]
+pub enum OidcError {
+    #[error("Request to identity provider failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("Invalid ID token: {0}")]
+    Token(#[from] jsonwebtoken::errors::Error),
+    #[error("Identity provider rejected the request: {0}")]
+    Provider(String),
+    #[error("Discovery document is for issuer {0}")]
+    IssuerMismatch(String),
+    #[error("ID token is signed with an unknown key")]
+    UnknownKey,
+    #[error("ID token uses an unsupported algorithm")]
+    UnsupportedAlgorithm,
+    #[error("ID token nonce does not match the login")]
+    NonceMismatch,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+struct Provider {
+    metadata: ProviderMetadata,
+    jwks: JwkSet,
+    fetched_at: Instant,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IdTokenClaims {
+    pub sub: String,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    pub preferred_username: Option<String>,
+    pub nonce: Option<String>,
+    // Everything else, where the configured role claim is looked up
+    #[serde(flatten)]
+    pub other: HashMap<String, serde_json::Value>,
+}
+
+/// Relying-party side of the OpenID Connect authorization code flow.
+pub struct OidcClient {
+    config: OidcConfig,
+    http: reqwest::Client,
+    provider: RwLock<Option<Arc<Provider>>>,
+}
+
+impl OidcClient {
+    pub fn new(config: OidcConfig) -> Result<Self, OidcError> {
+        Ok(OidcClient {
+            config,
+            http: reqwest::Client::builder().timeout(HTTP_TIMEOUT).build()?,
+            provider: RwLock::new(None),
+        })
+    }
+
+    pub fn config(&self) -> &OidcConfig {
+        &self.config
+    }
+
+    /// Where to send the browser to sign in at the provider.
+    pub async fn authorization_url(
+        &self,
+        state: &str,
+        nonce: &str,
+        code_challenge: &str,
+    ) -> Result<String, OidcError> {
+        let provider = self.provider(false).await?;
+
+        let url = Url::parse_with_params(
+            &provider.metadata.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", self.config.client_id.as_str()),
+                ("redirect_uri", self.config.redirect_url.as_str()),
+                ("scope", self.config.scopes.as_str()),
+                ("state", state),
+                ("nonce", nonce),
+                ("code_challenge", code_challenge),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .map_err(|e| OidcError::Provider(format!("Invalid authorization endpoint: {}", e)))?;
+
+        Ok(url.to_string())
+    }
+
+    /// Redeems an authorization code and returns the validated ID token claims.
+    pub async fn exchange_code(
+        &self,
+        code: &str,
+        code_verifier: &str,
+        nonce: &str,
+    ) -> Result<IdTokenClaims, OidcError> {
+        let provider = self.provider(false).await?;
+
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", self.config.redirect_url.as_str()),
+            ("client_id", self.config.client_id.as_str()),
+            ("code_verifier", code_verifier),
+        ];
+        if let Some(secret) = &self.config.client_secret {
+            form.push(("client_secret", secret.as_str()));
+        }
+
+        let response: TokenResponse = self
+            .http
+            .post(&provider.metadata.token_endpoint)
+            .form(&form)
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        let id_token = match (response.id_token, response.error) {
+            (Some(id_token), None) => id_token,
+            (_, error) => {
+                return Err(OidcError::Provider(format!(
+                    "{}: {}",
+                    error.unwrap_or_else(|| "missing_id_token".to_string()),
+                    response.error_description.unwrap_or_default()
+                )))
+            }
+        };
+
+        let claims = self.validate_id_token(&id_token).await?;
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err(OidcError::NonceMismatch);
+        }
+
+        Ok(claims)
+    }
+
+    async fn validate_id_token(&self, id_token: &str) -> Result<IdTokenClaims, OidcError> {
+        let header = decode_header(id_token)?;
+
+        // Only the provider's published asymmetric keys are trusted, never a shared secret
+        if matches!(header.alg, Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512) {
+            return Err(OidcError::UnsupportedAlgorithm);
+        }
+
+        let mut provider = self.provider(false).await?;
+        let mut key = find_key(&provider.jwks, header.kid.as_deref());
+        if key.is_none() && provider.fetched_at.elapsed() >= MIN_REFRESH_INTERVAL {
+            provider = self.provider(true).await?;
+            key = find_key(&provider.jwks, header.kid.as_deref());
+        }
+        let key = DecodingKey::from_jwk(key.ok_or(OidcError::UnknownKey)?)?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&provider.metadata.issuer]);
+        validation.set_audience(&[&self.config.client_id]);
+
+        Ok(decode::<IdTokenClaims>(id_token, &key, &validation)?.claims)
+    }
+
+    async fn provider(&self, force_refresh: bool) -> Result<Arc<Provider>, OidcError> {
+        if !force_refresh {
+            if let Some(provider) = self.provider.read().await.as_ref() {
+                if provider.fetched_at.elapsed() < METADATA_MAX_AGE {
+                    return Ok(provider.clone());
+                }
+            }
+        }
+
+        let mut cached = self.provider.write().await;
+
+        // Another request may have refreshed it while this one waited for the lock
+        if let Some(provider) = cached.as_ref() {
+            if provider.fetched_at.elapsed() < MIN_REFRESH_INTERVAL {
+                return Ok(provider.clone());
+            }
+        }
+
+        let metadata: ProviderMetadata = self
+            .http
+            .get(format!("{}/.well-known/openid-configuration", self.config.issuer))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        if metadata.issuer.trim_end_matches('/') != self.config.issuer {
+            return Err(OidcError::IssuerMismatch(metadata.issuer));
+        }
+
+        let jwks: JwkSet = self
+            .http
+            .get(&metadata.jwks_uri)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let provider = Arc::new(Provider {
+            metadata,
+            jwks,
+            fetched_at: Instant::now(),
+        });
+        *cached = Some(provider.clone());
+        Ok(provider)
+    }
+}
+
+fn find_key<'a>(jwks: &'a JwkSet, kid: Option<&str>) -> Option<&'a jsonwebtoken::jwk::Jwk> {
+    match kid {
+        Some(kid) => jwks.find(kid),
+        // Providers with a single key may leave the key id out
+        None if jwks.keys.len() == 1 => jwks.keys.first(),
+        None => None,
+    }
+}
\ No newline at end of file
@@ -0,0 +1,395 @@
+use actix_web::{web, HttpResponse, Responder, get};
+use tokio_postgres::types::ToSql;
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::db::DbPool;
+use crate::validation::ValidQuery;
+use super::models::{
+    Feed, FeedKind, FeedVersion, NavigationEntry, Publication, PageQuery, SearchQuery,
+};
+use super::render;
+
+// Publications per acquisition feed page
+const PAGE_SIZE: i64 = 25;
+
+// Window used to rank popular books
+const POPULAR_WINDOW_DAYS: i32 = 30;
+
+#[get("/opensearch.xml")]
+pub async fn opensearch_description(config: web::Data<Config>) -> impl Responder {
+    HttpResponse::Ok()
+        .content_type(render::OPENSEARCH_TYPE)
+        .body(render::opensearch(&config.public_url))
+}
+
+#[get("/{version}")]
+pub async fn root_feed(
+    path: web::Path<(String,)>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    let version: FeedVersion = match path.0.parse() {
+        Ok(version) => version,
+        Err(message) => return HttpResponse::NotFound().json(message),
+    };
+
+    let feed = Feed {
+        title: "E-Library".to_string(),
+        path: String::new(),
+        kind: FeedKind::Navigation,
+        navigation: vec![
+            NavigationEntry {
+                title: "New books".to_string(),
+                path: "/new".to_string(),
+                description: Some("Recently added to the library".to_string()),
+                count: None,
+            },
+            NavigationEntry {
+                title: "Popular books".to_string(),
+                path: "/popular".to_string(),
+                description: Some(format!("Most read in the last {} days", POPULAR_WINDOW_DAYS)),
+                count: None,
+            },
+            NavigationEntry {
+                title: "Categories".to_string(),
+                path: "/categories".to_string(),
+                description: Some("Browse by category".to_string()),
+                count: None,
+            },
+        ],
+        publications: Vec::new(),
+        page: 1,
+        has_next: false,
+        query: None,
+    };
+
+    respond(&feed, version, &config)
+}
+
+#[get("/{version}/categories")]
+pub async fn categories_feed(
+    path: web::Path<(String,)>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    let version: FeedVersion = match path.0.parse() {
+        Ok(version) => version,
+        Err(message) => return HttpResponse::NotFound().json(message),
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let result = client
+        .query(
+            "SELECT c.id, c.name, c.description, COUNT(b.id) AS book_count
+             FROM categories c
+             LEFT JOIN books b ON c.id = b.category_id
+             GROUP BY c.id, c.name, c.description
+             ORDER BY c.name",
+            &[],
+        )
+        .await;
+
+    let navigation = match result {
+        Ok(rows) => rows
+            .iter()
+            .map(|row| NavigationEntry {
+                title: row.get("name"),
+                path: format!("/categories/{}", row.get::<_, Uuid>("id")),
+                description: row.get("description"),
+                count: Some(row.get("book_count")),
+            })
+            .collect(),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Error fetching categories");
+        }
+    };
+
+    let feed = Feed {
+        title: "Categories".to_string(),
+        path: "/categories".to_string(),
+        kind: FeedKind::Navigation,
+        navigation,
+        publications: Vec::new(),
+        page: 1,
+        has_next: false,
+        query: None,
+    };
+
+    respond(&feed, version, &config)
+}
+
+#[get("/{version}/categories/{id}")]
+pub async fn category_feed(
+    path: web::Path<(String, String)>,
+    query: ValidQuery<PageQuery>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    let version: FeedVersion = match path.0.parse() {
+        Ok(version) => version,
+        Err(message) => return HttpResponse::NotFound().json(message),
+    };
+
+    let category_id = match Uuid::parse_str(&path.1) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().json("Invalid category ID"),
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let name: String = match client
+        .query_opt("SELECT name FROM categories WHERE id = $1", &[&category_id])
+        .await
+    {
+        Ok(Some(row)) => row.get("name"),
+        Ok(None) => return HttpResponse::NotFound().json("Category not found"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Database error");
+        }
+    };
+
+    let page = query.page.unwrap_or(1);
+    let result = load_publications(
+        &client,
+        "WHERE b.category_id = $1",
+        "b.title",
+        &[&category_id],
+        page,
+    )
+    .await;
+
+    match result {
+        Ok((publications, has_next)) => {
+            let feed = Feed {
+                title: name,
+                path: format!("/categories/{}", category_id),
+                kind: FeedKind::Acquisition,
+                navigation: Vec::new(),
+                publications,
+                page,
+                has_next,
+                query: None,
+            };
+            respond(&feed, version, &config)
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error fetching books")
+        }
+    }
+}
+
+#[get("/{version}/new")]
+pub async fn new_books_feed(
+    path: web::Path<(String,)>,
+    query: ValidQuery<PageQuery>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    let version: FeedVersion = match path.0.parse() {
+        Ok(version) => version,
+        Err(message) => return HttpResponse::NotFound().json(message),
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let page = query.page.unwrap_or(1);
+    match load_publications(&client, "", "b.created_at DESC", &[], page).await {
+        Ok((publications, has_next)) => {
+            let feed = Feed {
+                title: "New books".to_string(),
+                path: "/new".to_string(),
+                kind: FeedKind::Acquisition,
+                navigation: Vec::new(),
+                publications,
+                page,
+                has_next,
+                query: None,
+            };
+            respond(&feed, version, &config)
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error fetching books")
+        }
+    }
+}
+
+#[get("/{version}/popular")]
+pub async fn popular_books_feed(
+    path: web::Path<(String,)>,
+    query: ValidQuery<PageQuery>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    let version: FeedVersion = match path.0.parse() {
+        Ok(version) => version,
+        Err(message) => return HttpResponse::NotFound().json(message),
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let page = query.page.unwrap_or(1);
+    let result = load_publications(
+        &client,
+        "",
+        "(SELECT COUNT(*) FROM user_reading_progress p
+          WHERE p.book_id = b.id AND p.last_read_at >= CURRENT_TIMESTAMP - make_interval(days => $1)) DESC,
+         b.created_at DESC",
+        &[&POPULAR_WINDOW_DAYS],
+        page,
+    )
+    .await;
+
+    match result {
+        Ok((publications, has_next)) => {
+            let feed = Feed {
+                title: "Popular books".to_string(),
+                path: "/popular".to_string(),
+                kind: FeedKind::Acquisition,
+                navigation: Vec::new(),
+                publications,
+                page,
+                has_next,
+                query: None,
+            };
+            respond(&feed, version, &config)
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error fetching books")
+        }
+    }
+}
+
+#[get("/{version}/search")]
+pub async fn search_feed(
+    path: web::Path<(String,)>,
+    query: ValidQuery<SearchQuery>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    let version: FeedVersion = match path.0.parse() {
+        Ok(version) => version,
+        Err(message) => return HttpResponse::NotFound().json(message),
+    };
+
+    let terms = query.q.as_deref().unwrap_or("").trim().to_string();
+    if terms.is_empty() {
+        return HttpResponse::BadRequest().json("Search query is required");
+    }
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    // Same matching and ranking as the catalog search
+    let pattern = format!("%{}%", terms.to_lowercase());
+    let page = query.page.unwrap_or(1);
+    let result = load_publications(
+        &client,
+        "WHERE LOWER(b.title) LIKE $1 OR LOWER(b.author) LIKE $1 OR LOWER(b.description) LIKE $1",
+        "CASE WHEN LOWER(b.title) LIKE $1 THEN 0 WHEN LOWER(b.author) LIKE $1 THEN 1 ELSE 2 END,
+         b.created_at DESC",
+        &[&pattern],
+        page,
+    )
+    .await;
+
+    match result {
+        Ok((publications, has_next)) => {
+            let feed = Feed {
+                title: format!("Search: {}", terms),
+                path: "/search".to_string(),
+                kind: FeedKind::Acquisition,
+                navigation: Vec::new(),
+                publications,
+                page,
+                has_next,
+                query: Some(format!("q={}", render::encode_query_value(&terms))),
+            };
+            respond(&feed, version, &config)
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error searching books")
+        }
+    }
+}
+
+// Loads one page of books, plus whether another page follows
+async fn load_publications(
+    client: &deadpool_postgres::Client,
+    condition: &str,
+    order_by: &str,
+    params: &[&(dyn ToSql + Sync)],
+    page: i64,
+) -> Result<(Vec<Publication>, bool), tokio_postgres::Error> {
+    let sql = format!(
+        "SELECT b.id, b.title, b.author, b.description, b.cover_image, b.format,
+                b.published_date, b.created_at, c.name AS category_name
+         FROM books b
+         JOIN categories c ON b.category_id = c.id
+         {}
+         ORDER BY {}
+         LIMIT {} OFFSET {}",
+        condition,
+        order_by,
+        PAGE_SIZE + 1,
+        (page - 1) * PAGE_SIZE
+    );
+
+    let rows = client.query(&sql, params).await?;
+    let has_next = rows.len() as i64 > PAGE_SIZE;
+
+    let publications = rows
+        .iter()
+        .take(PAGE_SIZE as usize)
+        .map(|row| Publication {
+            id: row.get("id"),
+            title: row.get("title"),
+            author: row.get("author"),
+            description: row.get("description"),
+            cover_image: row.get("cover_image"),
+            category_name: row.get("category_name"),
+            format: row.get("format"),
+            published_date: row.get("published_date"),
+            updated_at: row.get("created_at"),
+        })
+        .collect();
+
+    Ok((publications, has_next))
+}
+
+fn respond(feed: &Feed, version: FeedVersion, config: &Config) -> HttpResponse {
+    match version {
+        FeedVersion::Atom => {
+            let content_type = match feed.kind {
+                FeedKind::Navigation => render::NAVIGATION_TYPE,
+                FeedKind::Acquisition => render::ACQUISITION_TYPE,
+            };
+            HttpResponse::Ok()
+                .content_type(content_type)
+                .body(render::atom(feed, &config.public_url))
+        }
+        FeedVersion::Json => HttpResponse::Ok()
+            .content_type(render::OPDS2_TYPE)
+            .body(render::json(feed, &config.public_url).to_string()),
+    }
+}
\ No newline at end of file
@@ -0,0 +1,19 @@
+pub mod handlers;
+pub mod models;
+pub mod render;
+
+use actix_web::web;
+
+// OPDS 1.2 (Atom) feeds live under /opds/v1 and OPDS 2.0 (JSON) under /opds/v2
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/opds")
+            .service(handlers::opensearch_description)
+            .service(handlers::root_feed)
+            .service(handlers::categories_feed)
+            .service(handlers::category_feed)
+            .service(handlers::new_books_feed)
+            .service(handlers::popular_books_feed)
+            .service(handlers::search_feed)
+    );
+}
\ No newline at end of file
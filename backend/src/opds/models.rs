@@ -0,0 +1,97 @@
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::catalog::models::BookFormat;
+use crate::validation::MAX_PAGE;
+
+// Feeds count pages as i64
+const MAX_FEED_PAGE: i64 = MAX_PAGE as i64;
+
+/// Which OPDS flavour a feed is served as, from the `v1`/`v2` path segment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeedVersion {
+    // OPDS 1.2, Atom XML
+    Atom,
+    // OPDS 2.0, JSON
+    Json,
+}
+
+impl FeedVersion {
+    pub fn path(&self) -> &'static str {
+        match self {
+            FeedVersion::Atom => "v1",
+            FeedVersion::Json => "v2",
+        }
+    }
+}
+
+impl std::str::FromStr for FeedVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "v1" => Ok(FeedVersion::Atom),
+            "v2" => Ok(FeedVersion::Json),
+            _ => Err(format!("Unknown OPDS version: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeedKind {
+    Navigation,
+    Acquisition,
+}
+
+#[derive(Debug)]
+pub struct NavigationEntry {
+    pub title: String,
+    // Path below the feed root, e.g. "/new"
+    pub path: String,
+    pub description: Option<String>,
+    pub count: Option<i64>,
+}
+
+#[derive(Debug)]
+pub struct Publication {
+    pub id: Uuid,
+    pub title: String,
+    pub author: String,
+    pub description: String,
+    pub cover_image: String,
+    pub category_name: String,
+    pub format: BookFormat,
+    pub published_date: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A feed independent of how it is rendered.
+#[derive(Debug)]
+pub struct Feed {
+    pub title: String,
+    // Path below the feed root, without query string; "" for the root
+    pub path: String,
+    pub kind: FeedKind,
+    pub navigation: Vec<NavigationEntry>,
+    pub publications: Vec<Publication>,
+    pub page: i64,
+    pub has_next: bool,
+    // Extra query parameters carried over to paging links, e.g. the search terms
+    pub query: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct PageQuery {
+    #[validate(range(min = 1, max = MAX_FEED_PAGE))]
+    pub page: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct SearchQuery {
+    #[validate(length(max = 200))]
+    pub q: Option<String>,
+    #[validate(range(min = 1, max = MAX_FEED_PAGE))]
+    pub page: Option<i64>,
+}
\ No newline at end of file
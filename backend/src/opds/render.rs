@@ -0,0 +1,241 @@
+use chrono::{SecondsFormat, Utc};
+use serde_json::{json, Value};
+
+use super::models::{Feed, FeedKind, FeedVersion, Publication};
+
+pub const NAVIGATION_TYPE: &str = "application/atom+xml;profile=opds-catalog;kind=navigation";
+pub const ACQUISITION_TYPE: &str = "application/atom+xml;profile=opds-catalog;kind=acquisition";
+pub const OPDS2_TYPE: &str = "application/opds+json";
+pub const OPENSEARCH_TYPE: &str = "application/opensearchdescription+xml";
+
+const CATALOG_TITLE: &str = "E-Library";
+
+/// Renders a feed as OPDS 1.2 Atom.
+pub fn atom(feed: &Feed, public_url: &str) -> String {
+    let root = feed_root(public_url, FeedVersion::Atom);
+    let self_type = match feed.kind {
+        FeedKind::Navigation => NAVIGATION_TYPE,
+        FeedKind::Acquisition => ACQUISITION_TYPE,
+    };
+    let updated = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+
+    let mut xml = String::from(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    xml.push_str(
+        r#"<feed xmlns="http://www.w3.org/2005/Atom" xmlns:dc="http://purl.org/dc/terms/" xmlns:opds="http://opds-spec.org/2010/catalog">"#,
+    );
+    xml.push_str(&format!("<id>{}</id>", escape_xml(&page_href(&root, feed, feed.page))));
+    xml.push_str(&format!("<title>{}</title>", escape_xml(&feed.title)));
+    xml.push_str(&format!("<updated>{}</updated>", updated));
+    xml.push_str(&format!("<author><name>{}</name></author>", CATALOG_TITLE));
+
+    xml.push_str(&link("self", &page_href(&root, feed, feed.page), self_type));
+    xml.push_str(&link("start", &root, NAVIGATION_TYPE));
+    xml.push_str(&link("search", &format!("{}/opds/opensearch.xml", public_url), OPENSEARCH_TYPE));
+    if feed.page > 1 {
+        xml.push_str(&link("previous", &page_href(&root, feed, feed.page - 1), self_type));
+    }
+    if feed.has_next {
+        xml.push_str(&link("next", &page_href(&root, feed, feed.page + 1), self_type));
+    }
+
+    for entry in &feed.navigation {
+        let href = format!("{}{}", root, entry.path);
+        xml.push_str("<entry>");
+        xml.push_str(&format!("<title>{}</title>", escape_xml(&entry.title)));
+        xml.push_str(&format!("<id>{}</id>", escape_xml(&href)));
+        xml.push_str(&format!("<updated>{}</updated>", updated));
+        if let Some(description) = &entry.description {
+            xml.push_str(&format!("<content type=\"text\">{}</content>", escape_xml(description)));
+        }
+        xml.push_str(&link("subsection", &href, ACQUISITION_TYPE));
+        xml.push_str("</entry>");
+    }
+
+    for publication in &feed.publications {
+        xml.push_str("<entry>");
+        xml.push_str(&format!("<title>{}</title>", escape_xml(&publication.title)));
+        xml.push_str(&format!("<id>urn:uuid:{}</id>", publication.id));
+        xml.push_str(&format!(
+            "<updated>{}</updated>",
+            publication.updated_at.to_rfc3339_opts(SecondsFormat::Secs, true)
+        ));
+        xml.push_str(&format!("<author><name>{}</name></author>", escape_xml(&publication.author)));
+        xml.push_str(&format!(
+            "<dc:issued>{}</dc:issued>",
+            publication.published_date.format("%Y-%m-%d")
+        ));
+        xml.push_str(&format!(
+            "<category term=\"{0}\" label=\"{0}\"/>",
+            escape_xml(&publication.category_name)
+        ));
+        if !publication.description.is_empty() {
+            xml.push_str(&format!("<summary type=\"text\">{}</summary>", escape_xml(&publication.description)));
+        }
+        if let Some(cover) = cover_url(publication, public_url) {
+            xml.push_str(&format!(
+                "<link rel=\"http://opds-spec.org/image\" href=\"{0}\"/><link rel=\"http://opds-spec.org/image/thumbnail\" href=\"{0}\"/>",
+                escape_xml(&cover)
+            ));
+        }
+        xml.push_str(&link(
+            "http://opds-spec.org/acquisition",
+            &acquisition_url(publication, public_url),
+            publication.format.mime_type(),
+        ));
+        xml.push_str("</entry>");
+    }
+
+    xml.push_str("</feed>");
+    xml
+}
+
+/// Renders a feed as OPDS 2.0 JSON.
+pub fn json(feed: &Feed, public_url: &str) -> Value {
+    let root = feed_root(public_url, FeedVersion::Json);
+
+    let mut links = vec![
+        json!({ "rel": "self", "href": page_href(&root, feed, feed.page), "type": OPDS2_TYPE }),
+        json!({ "rel": "start", "href": root, "type": OPDS2_TYPE }),
+        json!({ "rel": "search", "href": format!("{}/search{{?q}}", root), "type": OPDS2_TYPE, "templated": true }),
+    ];
+    if feed.page > 1 {
+        links.push(json!({ "rel": "previous", "href": page_href(&root, feed, feed.page - 1), "type": OPDS2_TYPE }));
+    }
+    if feed.has_next {
+        links.push(json!({ "rel": "next", "href": page_href(&root, feed, feed.page + 1), "type": OPDS2_TYPE }));
+    }
+
+    let mut document = json!({
+        "metadata": { "title": feed.title },
+        "links": links,
+    });
+
+    if feed.kind == FeedKind::Acquisition {
+        document["metadata"]["currentPage"] = json!(feed.page);
+    }
+
+    if !feed.navigation.is_empty() {
+        document["navigation"] = feed
+            .navigation
+            .iter()
+            .map(|entry| {
+                let mut link = json!({
+                    "href": format!("{}{}", root, entry.path),
+                    "title": entry.title,
+                    "type": OPDS2_TYPE,
+                    "rel": "subsection",
+                });
+                if let Some(count) = entry.count {
+                    link["properties"] = json!({ "numberOfItems": count });
+                }
+                link
+            })
+            .collect();
+    }
+
+    if feed.kind == FeedKind::Acquisition {
+        document["publications"] = feed
+            .publications
+            .iter()
+            .map(|publication| {
+                let mut entry = json!({
+                    "metadata": {
+                        "@type": "http://schema.org/Book",
+                        "identifier": format!("urn:uuid:{}", publication.id),
+                        "title": publication.title,
+                        "author": [{ "name": publication.author }],
+                        "description": publication.description,
+                        "published": publication.published_date.format("%Y-%m-%d").to_string(),
+                        "modified": publication.updated_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+                        "subject": [{ "name": publication.category_name }],
+                    },
+                    "links": [{
+                        "rel": "http://opds-spec.org/acquisition",
+                        "href": acquisition_url(publication, public_url),
+                        "type": publication.format.mime_type(),
+                    }],
+                });
+                if let Some(cover) = cover_url(publication, public_url) {
+                    entry["images"] = json!([{ "href": cover }]);
+                }
+                entry
+            })
+            .collect();
+    }
+
+    document
+}
+
+/// OpenSearch description pointing e-reader apps at the Atom search feed.
+pub fn opensearch(public_url: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?><OpenSearchDescription xmlns="http://a9.com/-/spec/opensearch/1.1/"><ShortName>{title}</ShortName><Description>Search the {title} catalog</Description><InputEncoding>UTF-8</InputEncoding><OutputEncoding>UTF-8</OutputEncoding><Url type="{kind}" template="{root}/search?q={{searchTerms}}"/></OpenSearchDescription>"#,
+        title = CATALOG_TITLE,
+        kind = escape_xml(ACQUISITION_TYPE),
+        root = escape_xml(&feed_root(public_url, FeedVersion::Atom)),
+    )
+}
+
+/// Percent-encodes a query parameter value.
+pub fn encode_query_value(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+fn feed_root(public_url: &str, version: FeedVersion) -> String {
+    format!("{}/opds/{}", public_url, version.path())
+}
+
+fn page_href(root: &str, feed: &Feed, page: i64) -> String {
+    let mut params = Vec::new();
+    if let Some(query) = &feed.query {
+        params.push(query.clone());
+    }
+    if page > 1 {
+        params.push(format!("page={}", page));
+    }
+
+    if params.is_empty() {
+        format!("{}{}", root, feed.path)
+    } else {
+        format!("{}{}?{}", root, feed.path, params.join("&"))
+    }
+}
+
+// Downloads go through the reader, which accepts Basic credentials from e-reader apps
+fn acquisition_url(publication: &Publication, public_url: &str) -> String {
+    format!("{}/reader/content/{}", public_url, publication.id)
+}
+
+fn cover_url(publication: &Publication, public_url: &str) -> Option<String> {
+    let cover = publication.cover_image.trim();
+    if cover.is_empty() {
+        None
+    } else if cover.starts_with("http://") || cover.starts_with("https://") {
+        Some(cover.to_string())
+    } else {
+        Some(format!("{}/{}", public_url, cover.trim_start_matches('/')))
+    }
+}
+
+fn link(rel: &str, href: &str, kind: &str) -> String {
+    format!(
+        "<link rel=\"{}\" href=\"{}\" type=\"{}\"/>",
+        escape_xml(rel),
+        escape_xml(href),
+        escape_xml(kind)
+    )
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
\ No newline at end of file
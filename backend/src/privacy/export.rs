@@ -0,0 +1,273 @@
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use uuid::Uuid;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::auth::models::{User, USER_COLUMNS};
+use super::models::{
+    AccountRecord, AnnotationRecord, DailyReadingRecord, DeviceRecord, IdentityRecord, LoginRecord, ProgressRecord,
+    SessionRecord,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ExportError {
+    #[error("Database error: {0}")]
+    Database(#[from] tokio_postgres::Error),
+    #[error("Failed to write archive: {0}")]
+    Io(#[from] io::Error),
+    #[error("Failed to write archive: {0}")]
+    Zip(#[from] zip::result::ZipError),
+    #[error("Failed to encode CSV: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("Failed to encode JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("User not found")]
+    UserNotFound,
+}
+
+const README: &str = "\
+This archive contains the personal data E-Library stores about you.
+
+account.json          Your profile, sign-in settings and linked accounts
+annotations.json      Bookmarks, highlights and notes
+reading_progress.csv  Your position in each book
+reading_sessions.csv  Reading sessions reported by the reader
+daily_reading.csv     Pages and reading time per book per day, in your timezone
+login_history.csv     Sign-in attempts to your account
+devices.csv           Devices signed in to your account, including signed-out ones
+
+CSV files without rows are left empty.
+";
+
+pub fn path_of(storage_path: &str, name: &str) -> PathBuf {
+    PathBuf::from(storage_path).join(name)
+}
+
+// A missing file is fine; the export is gone either way
+pub async fn remove(storage_path: &str, name: &str) {
+    if let Err(e) = tokio::fs::remove_file(path_of(storage_path, name)).await {
+        if e.kind() != io::ErrorKind::NotFound {
+            eprintln!("Export removal error: {}", e);
+        }
+    }
+}
+
+/// Collects everything stored about the user into a ZIP file at `path` and
+/// returns its size in bytes.
+pub async fn build(client: &deadpool_postgres::Client, user_id: Uuid, path: &Path) -> Result<u64, ExportError> {
+    let files = collect(client, user_id).await?;
+
+    // Compressing is CPU-bound; keep it off the async workers
+    let path = path.to_path_buf();
+    actix_web::rt::task::spawn_blocking(move || write_zip(&path, files))
+        .await
+        .map_err(io::Error::other)?
+}
+
+async fn collect(client: &deadpool_postgres::Client, user_id: Uuid) -> Result<Vec<(&'static str, Vec<u8>)>, ExportError> {
+    let user = client
+        .query_opt(&format!("SELECT {} FROM users WHERE id = $1", USER_COLUMNS), &[&user_id])
+        .await?
+        .map(|row| User::from_row(&row))
+        .ok_or(ExportError::UserNotFound)?;
+
+    let extras = client
+        .query_one(
+            "SELECT (SELECT enabled_at FROM user_mfa WHERE user_id = $1) AS two_factor_enabled_at,
+                    (SELECT username FROM koreader_accounts WHERE user_id = $1) AS koreader_username",
+            &[&user_id],
+        )
+        .await?;
+
+    let linked_identities = client
+        .query(
+            "SELECT issuer, subject, email, created_at, last_login_at
+             FROM user_identities WHERE user_id = $1
+             ORDER BY created_at",
+            &[&user_id],
+        )
+        .await?
+        .iter()
+        .map(|row| IdentityRecord {
+            issuer: row.get("issuer"),
+            subject: row.get("subject"),
+            email: row.get("email"),
+            created_at: row.get("created_at"),
+            last_login_at: row.get("last_login_at"),
+        })
+        .collect();
+
+    let account = AccountRecord {
+        user,
+        two_factor_enabled_at: extras.get("two_factor_enabled_at"),
+        koreader_username: extras.get("koreader_username"),
+        linked_identities,
+    };
+
+    let annotations: Vec<AnnotationRecord> = client
+        .query(
+            "SELECT a.id, a.book_id, b.title AS book_title, a.kind, a.page, a.location,
+                    a.selected_text, a.note, a.color, a.created_at
+             FROM user_annotations a
+             JOIN books b ON b.id = a.book_id
+             WHERE a.user_id = $1
+             ORDER BY a.created_at",
+            &[&user_id],
+        )
+        .await?
+        .iter()
+        .map(|row| AnnotationRecord {
+            id: row.get("id"),
+            book_id: row.get("book_id"),
+            book_title: row.get("book_title"),
+            kind: row.get("kind"),
+            page: row.get("page"),
+            location: row.get("location"),
+            selected_text: row.get("selected_text"),
+            note: row.get("note"),
+            color: row.get("color"),
+            created_at: row.get("created_at"),
+        })
+        .collect();
+
+    let progress: Vec<ProgressRecord> = client
+        .query(
+            "SELECT p.book_id, b.title AS book_title, p.current_page, p.total_pages, p.completed, p.last_read_at
+             FROM user_reading_progress p
+             JOIN books b ON b.id = p.book_id
+             WHERE p.user_id = $1
+             ORDER BY p.last_read_at DESC",
+            &[&user_id],
+        )
+        .await?
+        .iter()
+        .map(|row| ProgressRecord {
+            book_id: row.get("book_id"),
+            book_title: row.get("book_title"),
+            current_page: row.get("current_page"),
+            total_pages: row.get("total_pages"),
+            completed: row.get("completed"),
+            last_read_at: row.get("last_read_at"),
+        })
+        .collect();
+
+    let sessions: Vec<SessionRecord> = client
+        .query(
+            "SELECT s.book_id, b.title AS book_title, s.started_at, s.ended_at, s.end_reason,
+                    s.start_page, s.current_page, s.pages_turned, s.active_seconds
+             FROM reading_sessions s
+             JOIN books b ON b.id = s.book_id
+             WHERE s.user_id = $1
+             ORDER BY s.started_at",
+            &[&user_id],
+        )
+        .await?
+        .iter()
+        .map(|row| SessionRecord {
+            book_id: row.get("book_id"),
+            book_title: row.get("book_title"),
+            started_at: row.get("started_at"),
+            ended_at: row.get("ended_at"),
+            end_reason: row.get("end_reason"),
+            start_page: row.get("start_page"),
+            current_page: row.get("current_page"),
+            pages_turned: row.get("pages_turned"),
+            active_seconds: row.get("active_seconds"),
+        })
+        .collect();
+
+    let daily_reading: Vec<DailyReadingRecord> = client
+        .query(
+            "SELECT a.session_date, a.book_id, b.title AS book_title, a.pages_read, a.reading_time_seconds
+             FROM user_analytics a
+             JOIN books b ON b.id = a.book_id
+             WHERE a.user_id = $1
+             ORDER BY a.session_date, b.title",
+            &[&user_id],
+        )
+        .await?
+        .iter()
+        .map(|row| DailyReadingRecord {
+            date: row.get("session_date"),
+            book_id: row.get("book_id"),
+            book_title: row.get("book_title"),
+            pages_read: row.get("pages_read"),
+            reading_time_seconds: row.get("reading_time_seconds"),
+        })
+        .collect();
+
+    let logins: Vec<LoginRecord> = client
+        .query(
+            "SELECT created_at, method, outcome, ip_address, user_agent
+             FROM login_attempts
+             WHERE user_id = $1
+             ORDER BY created_at",
+            &[&user_id],
+        )
+        .await?
+        .iter()
+        .map(|row| LoginRecord {
+            attempted_at: row.get("created_at"),
+            method: row.get("method"),
+            outcome: row.get("outcome"),
+            ip_address: row.get("ip_address"),
+            user_agent: row.get("user_agent"),
+        })
+        .collect();
+
+    let devices: Vec<DeviceRecord> = client
+        .query(
+            "SELECT device_name, user_agent, ip_address, created_at, last_active_at, revoked_at
+             FROM user_sessions
+             WHERE user_id = $1
+             ORDER BY created_at",
+            &[&user_id],
+        )
+        .await?
+        .iter()
+        .map(|row| DeviceRecord {
+            device_name: row.get("device_name"),
+            user_agent: row.get("user_agent"),
+            ip_address: row.get("ip_address"),
+            signed_in_at: row.get("created_at"),
+            last_active_at: row.get("last_active_at"),
+            signed_out_at: row.get("revoked_at"),
+        })
+        .collect();
+
+    Ok(vec![
+        ("README.txt", README.as_bytes().to_vec()),
+        ("account.json", serde_json::to_vec_pretty(&account)?),
+        ("annotations.json", serde_json::to_vec_pretty(&annotations)?),
+        ("reading_progress.csv", to_csv(&progress)?),
+        ("reading_sessions.csv", to_csv(&sessions)?),
+        ("daily_reading.csv", to_csv(&daily_reading)?),
+        ("login_history.csv", to_csv(&logins)?),
+        ("devices.csv", to_csv(&devices)?),
+    ])
+}
+
+fn to_csv<T: Serialize>(rows: &[T]) -> Result<Vec<u8>, ExportError> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for row in rows {
+        writer.serialize(row)?;
+    }
+
+    writer.into_inner().map_err(|e| ExportError::Io(e.into_error()))
+}
+
+fn write_zip(path: &Path, files: Vec<(&'static str, Vec<u8>)>) -> Result<u64, ExportError> {
+    let mut zip = ZipWriter::new(std::fs::File::create(path)?);
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    for (name, contents) in files {
+        zip.start_file(name, options)?;
+        zip.write_all(&contents)?;
+    }
+
+    let file = zip.finish()?;
+    Ok(file.metadata()?.len())
+}
\ No newline at end of file
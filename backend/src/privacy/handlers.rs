@@ -0,0 +1,308 @@
+use actix_files::NamedFile;
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::{web, HttpResponse, Responder, get, post, HttpRequest};
+use uuid::Uuid;
+
+use crate::auth::verify_token;
+use crate::config::Config;
+use crate::db::DbPool;
+use super::export;
+use super::models::{DataExport, EXPORT_COLUMNS};
+
+// Exports a user may request per day; each one reads everything stored about them
+const MAX_EXPORTS_PER_DAY: i64 = 3;
+
+// Exports listed, newest first
+const MAX_LISTED_EXPORTS: i64 = 20;
+
+#[post("/exports")]
+pub async fn request_export(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let recent: i64 = match client
+        .query_one(
+            "SELECT COUNT(*) FROM data_exports
+             WHERE user_id = $1 AND requested_at > CURRENT_TIMESTAMP - INTERVAL '1 day'",
+            &[&user_id],
+        )
+        .await
+    {
+        Ok(row) => row.get(0),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Database error");
+        }
+    };
+
+    if recent >= MAX_EXPORTS_PER_DAY {
+        return HttpResponse::TooManyRequests().json("Too many exports requested today; try again tomorrow");
+    }
+
+    // One export at a time; the worker picks it up within a minute
+    let created = client
+        .query_opt(
+            &format!(
+                "INSERT INTO data_exports (id, user_id)
+                 SELECT $1, $2
+                 WHERE NOT EXISTS (
+                     SELECT 1 FROM data_exports WHERE user_id = $2 AND status IN ('pending', 'processing')
+                 )
+                 RETURNING {}",
+                EXPORT_COLUMNS
+            ),
+            &[&Uuid::new_v4(), &user_id],
+        )
+        .await;
+
+    match created {
+        Ok(Some(row)) => HttpResponse::Accepted().json(DataExport::from_row(&row)),
+        Ok(None) => HttpResponse::Conflict().json("An export is already being prepared"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error requesting export")
+        }
+    }
+}
+
+#[get("/exports")]
+pub async fn list_exports(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let rows = client
+        .query(
+            &format!(
+                "SELECT {} FROM data_exports WHERE user_id = $1 ORDER BY requested_at DESC LIMIT $2",
+                EXPORT_COLUMNS
+            ),
+            &[&user_id, &MAX_LISTED_EXPORTS],
+        )
+        .await;
+
+    match rows {
+        Ok(rows) => {
+            let exports: Vec<DataExport> = rows.iter().map(DataExport::from_row).collect();
+            HttpResponse::Ok().json(exports)
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error fetching exports")
+        }
+    }
+}
+
+#[get("/exports/{id}")]
+pub async fn get_export(
+    req: HttpRequest,
+    path: web::Path<(String,)>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let export_id = match Uuid::parse_str(&path.0) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().json("Invalid export ID"),
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let row = client
+        .query_opt(
+            &format!("SELECT {} FROM data_exports WHERE id = $1 AND user_id = $2", EXPORT_COLUMNS),
+            &[&export_id, &user_id],
+        )
+        .await;
+
+    match row {
+        Ok(Some(row)) => HttpResponse::Ok().json(DataExport::from_row(&row)),
+        Ok(None) => HttpResponse::NotFound().json("Export not found"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error fetching export")
+        }
+    }
+}
+
+#[get("/exports/{id}/download")]
+pub async fn download_export(
+    req: HttpRequest,
+    path: web::Path<(String,)>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let export_id = match Uuid::parse_str(&path.0) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().json("Invalid export ID"),
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    // Files past their expiry may not have been deleted yet, but are no longer handed out
+    let row = client
+        .query_opt(
+            "SELECT file_path, requested_at FROM data_exports
+             WHERE id = $1 AND user_id = $2 AND status = 'ready' AND expires_at > CURRENT_TIMESTAMP",
+            &[&export_id, &user_id],
+        )
+        .await;
+
+    let (file_path, requested_at): (String, chrono::DateTime<chrono::Utc>) = match row {
+        Ok(Some(row)) => (row.get("file_path"), row.get("requested_at")),
+        Ok(None) => return HttpResponse::NotFound().json("Export not available"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Database error");
+        }
+    };
+
+    match NamedFile::open(export::path_of(&config.data_export_storage_path, &file_path)) {
+        Ok(file) => file
+            .set_content_disposition(ContentDisposition {
+                disposition: DispositionType::Attachment,
+                parameters: vec![DispositionParam::Filename(format!(
+                    "e-library-data-{}.zip",
+                    requested_at.format("%Y-%m-%d")
+                ))],
+            })
+            .into_response(&req),
+        Err(_) => HttpResponse::NotFound().json("Export not available"),
+    }
+}
\ No newline at end of file
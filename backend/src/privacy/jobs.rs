@@ -0,0 +1,144 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use log::{error, info};
+use uuid::Uuid;
+
+use crate::db::DbPool;
+use super::export;
+
+// How often new export requests and expired downloads are looked for
+const EXPORT_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+// Exports left processing this long, e.g. by a restart mid-build, are started again
+const STALE_PROCESSING_SECONDS: f64 = 3600.0;
+
+// Shown to the user; the cause is only logged
+const EXPORT_FAILED_MESSAGE: &str = "The export could not be created; please request a new one";
+
+/// Builds requested data exports in the background and deletes their files once
+/// the download window has passed.
+pub fn spawn_export_worker(pool: DbPool, storage_path: String, expires_in: Duration) {
+    actix_web::rt::spawn(async move {
+        let mut interval = tokio::time::interval(EXPORT_POLL_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            let client = match pool.get().await {
+                Ok(client) => client,
+                Err(e) => {
+                    error!("Data export worker could not get a connection: {}", e);
+                    continue;
+                }
+            };
+
+            match expire_downloads(&client, &storage_path).await {
+                Ok(0) => {}
+                Ok(expired) => info!("Deleted {} expired data exports", expired),
+                Err(e) => error!("Could not expire data exports: {}", e),
+            }
+
+            // Work through everything that is waiting before sleeping again
+            loop {
+                match build_next(&client, &storage_path, expires_in).await {
+                    Ok(true) => continue,
+                    Ok(false) => break,
+                    Err(e) => {
+                        error!("Data export worker failed: {}", e);
+                        break;
+                    }
+                }
+            }
+        }
+    });
+}
+
+// Builds the oldest waiting export; false when there was none
+async fn build_next(
+    client: &deadpool_postgres::Client,
+    storage_path: &str,
+    expires_in: Duration,
+) -> Result<bool, tokio_postgres::Error> {
+    // SKIP LOCKED lets several instances share the queue
+    let claimed = client
+        .query_opt(
+            "UPDATE data_exports SET status = 'processing', started_at = CURRENT_TIMESTAMP
+             WHERE id = (
+                 SELECT id FROM data_exports
+                 WHERE status = 'pending'
+                    OR (status = 'processing' AND started_at < CURRENT_TIMESTAMP - make_interval(secs => $1))
+                 ORDER BY requested_at
+                 LIMIT 1
+                 FOR UPDATE SKIP LOCKED
+             )
+             RETURNING id, user_id",
+            &[&STALE_PROCESSING_SECONDS],
+        )
+        .await?;
+
+    let (export_id, user_id): (Uuid, Uuid) = match claimed {
+        Some(row) => (row.get("id"), row.get("user_id")),
+        None => return Ok(false),
+    };
+
+    let name = format!("{}.zip", export_id);
+    let path = PathBuf::from(storage_path).join(&name);
+    let built = match tokio::fs::create_dir_all(storage_path).await {
+        Ok(_) => export::build(client, user_id, &path).await,
+        Err(e) => Err(e.into()),
+    };
+
+    match built {
+        Ok(size_bytes) => {
+            let expires_at = chrono::Utc::now() + chrono::Duration::seconds(expires_in.as_secs() as i64);
+            client
+                .execute(
+                    "UPDATE data_exports
+                     SET status = 'ready', file_path = $2, size_bytes = $3,
+                         completed_at = CURRENT_TIMESTAMP, expires_at = $4
+                     WHERE id = $1",
+                    &[&export_id, &name, &(size_bytes as i64), &expires_at],
+                )
+                .await?;
+            info!("Built data export {} for user {}", export_id, user_id);
+        }
+        Err(e) => {
+            error!("Could not build data export {}: {}", export_id, e);
+            export::remove(storage_path, &name).await;
+            client
+                .execute(
+                    "UPDATE data_exports SET status = 'failed', error = $2, completed_at = CURRENT_TIMESTAMP
+                     WHERE id = $1",
+                    &[&export_id, &EXPORT_FAILED_MESSAGE],
+                )
+                .await?;
+        }
+    }
+
+    Ok(true)
+}
+
+async fn expire_downloads(client: &deadpool_postgres::Client, storage_path: &str) -> Result<usize, tokio_postgres::Error> {
+    let rows = client
+        .query(
+            "UPDATE data_exports d SET status = 'expired', file_path = NULL
+             FROM (
+                 SELECT id, file_path FROM data_exports
+                 WHERE status = 'ready' AND expires_at <= CURRENT_TIMESTAMP
+                 FOR UPDATE
+             ) old
+             WHERE d.id = old.id
+             RETURNING old.file_path",
+            &[],
+        )
+        .await?;
+
+    for row in &rows {
+        if let Some(name) = row.get::<_, Option<String>>("file_path") {
+            export::remove(storage_path, &name).await;
+        }
+    }
+
+    Ok(rows.len())
+}
\ No newline at end of file
@@ -0,0 +1,16 @@
+pub mod export;
+pub mod handlers;
+pub mod jobs;
+pub mod models;
+
+use actix_web::web;
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/privacy")
+            .service(handlers::request_export)
+            .service(handlers::list_exports)
+            .service(handlers::get_export)
+            .service(handlers::download_export)
+    );
+}
\ No newline at end of file
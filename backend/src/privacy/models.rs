@@ -0,0 +1,166 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::auth::models::User;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportStatus {
+    Pending,
+    Processing,
+    Ready,
+    Failed,
+    Expired,
+}
+
+impl std::fmt::Display for ExportStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ExportStatus::Pending => "pending",
+            ExportStatus::Processing => "processing",
+            ExportStatus::Ready => "ready",
+            ExportStatus::Failed => "failed",
+            ExportStatus::Expired => "expired",
+        };
+        f.write_str(name)
+    }
+}
+
+impl std::str::FromStr for ExportStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(ExportStatus::Pending),
+            "processing" => Ok(ExportStatus::Processing),
+            "ready" => Ok(ExportStatus::Ready),
+            "failed" => Ok(ExportStatus::Failed),
+            "expired" => Ok(ExportStatus::Expired),
+            other => Err(format!("Unknown export status: {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct DataExport {
+    pub id: Uuid,
+    pub status: ExportStatus,
+    pub size_bytes: Option<i64>,
+    pub error: Option<String>,
+    pub requested_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+    // Only while the file can be downloaded
+    pub download_url: Option<String>,
+}
+
+// Columns read by `DataExport::from_row`
+pub const EXPORT_COLUMNS: &str = "id, status, size_bytes, error, requested_at, completed_at, expires_at";
+
+impl DataExport {
+    pub fn from_row(row: &tokio_postgres::Row) -> Self {
+        let id: Uuid = row.get("id");
+        let status = row.get::<_, String>("status").parse().unwrap_or(ExportStatus::Failed);
+
+        DataExport {
+            id,
+            status,
+            size_bytes: row.get("size_bytes"),
+            error: row.get("error"),
+            requested_at: row.get("requested_at"),
+            completed_at: row.get("completed_at"),
+            expires_at: row.get("expires_at"),
+            download_url: (status == ExportStatus::Ready).then(|| format!("/privacy/exports/{}/download", id)),
+        }
+    }
+}
+
+// account.json
+#[derive(Debug, Serialize)]
+pub struct AccountRecord {
+    pub user: User,
+    pub two_factor_enabled_at: Option<DateTime<Utc>>,
+    pub koreader_username: Option<String>,
+    pub linked_identities: Vec<IdentityRecord>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IdentityRecord {
+    pub issuer: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_login_at: DateTime<Utc>,
+}
+
+// annotations.json; notes and highlighted passages read better as JSON than CSV
+#[derive(Debug, Serialize)]
+pub struct AnnotationRecord {
+    pub id: Uuid,
+    pub book_id: Uuid,
+    pub book_title: String,
+    pub kind: String,
+    pub page: i32,
+    pub location: Option<String>,
+    pub selected_text: Option<String>,
+    pub note: Option<String>,
+    pub color: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+// reading_progress.csv
+#[derive(Debug, Serialize)]
+pub struct ProgressRecord {
+    pub book_id: Uuid,
+    pub book_title: String,
+    pub current_page: i32,
+    pub total_pages: i32,
+    pub completed: bool,
+    pub last_read_at: DateTime<Utc>,
+}
+
+// reading_sessions.csv
+#[derive(Debug, Serialize)]
+pub struct SessionRecord {
+    pub book_id: Uuid,
+    pub book_title: String,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: Option<DateTime<Utc>>,
+    pub end_reason: Option<String>,
+    pub start_page: i32,
+    pub current_page: i32,
+    pub pages_turned: i32,
+    pub active_seconds: i32,
+}
+
+// daily_reading.csv
+#[derive(Debug, Serialize)]
+pub struct DailyReadingRecord {
+    pub date: NaiveDate,
+    pub book_id: Uuid,
+    pub book_title: String,
+    pub pages_read: i32,
+    pub reading_time_seconds: i32,
+}
+
+// login_history.csv
+#[derive(Debug, Serialize)]
+pub struct LoginRecord {
+    pub attempted_at: DateTime<Utc>,
+    pub method: String,
+    pub outcome: String,
+    pub ip_address: String,
+    pub user_agent: Option<String>,
+}
+
+// devices.csv
+#[derive(Debug, Serialize)]
+pub struct DeviceRecord {
+    pub device_name: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip_address: String,
+    pub signed_in_at: DateTime<Utc>,
+    pub last_active_at: DateTime<Utc>,
+    pub signed_out_at: Option<DateTime<Utc>>,
+}
\ No newline at end of file
@@ -1,30 +1,155 @@
-use actix_web::{web, HttpResponse, Responder, get, post, HttpRequest};
+use actix_web::{web, HttpResponse, Responder, get, post, delete, HttpRequest};
+use actix_ws::{CloseCode, CloseReason, Message};
+use futures::StreamExt;
 use uuid::Uuid;
 use std::path::Path;
+use std::time::Instant;
 use actix_files::NamedFile;
 use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
+use validator::{Validate, ValidationError};
 
-use crate::auth::verify_token;
+use crate::auth::{self, verify_token};
 use crate::config::Config;
 use crate::db::DbPool;
+use crate::validation::{field_error, ValidJson, ValidQuery};
 use crate::catalog::models::BookFormat;
 use super::formats;
+use super::progress::{self, ReadingProgress};
+use super::sync::{self, Replay, SyncAction, SyncConnection, SyncEvent, SyncEventKind, SyncHub};
 
-#[derive(Debug, Deserialize)]
+// Longest annotation text accepted, in characters
+const MAX_ANNOTATION_TEXT_LENGTH: u64 = 10000;
+
+#[derive(Debug, Deserialize, Validate)]
+#[validate(schema(function = "page_within_book"))]
 pub struct ReadingProgressRequest {
+    #[validate(range(min = 0))]
     pub current_page: i32,
+    #[validate(range(min = 1))]
     pub total_pages: i32,
 }
 
+fn page_within_book(progress: &ReadingProgressRequest) -> Result<(), ValidationError> {
+    if progress.current_page > progress.total_pages {
+        return Err(field_error("current_page", "Must not be after `total_pages`"));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AnnotationKind {
+    Bookmark,
+    Highlight,
+}
+
+impl std::fmt::Display for AnnotationKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AnnotationKind::Bookmark => write!(f, "bookmark"),
+            AnnotationKind::Highlight => write!(f, "highlight"),
+        }
+    }
+}
+
+impl std::str::FromStr for AnnotationKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bookmark" => Ok(AnnotationKind::Bookmark),
+            "highlight" => Ok(AnnotationKind::Highlight),
+            _ => Err(format!("Unknown annotation kind: {}", s)),
+        }
+    }
+}
+
+impl From<AnnotationKind> for SyncEventKind {
+    fn from(kind: AnnotationKind) -> Self {
+        match kind {
+            AnnotationKind::Bookmark => SyncEventKind::Bookmark,
+            AnnotationKind::Highlight => SyncEventKind::Highlight,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct AnnotationRequest {
+    pub kind: AnnotationKind,
+    #[validate(range(min = 0))]
+    pub page: i32,
+    #[validate(length(max = 1000))]
+    pub location: Option<String>,
+    #[validate(length(max = MAX_ANNOTATION_TEXT_LENGTH))]
+    pub selected_text: Option<String>,
+    #[validate(length(max = MAX_ANNOTATION_TEXT_LENGTH))]
+    pub note: Option<String>,
+    // As in `user_annotations.color`
+    #[validate(length(max = 20))]
+    pub color: Option<String>,
+}
+
 #[derive(Debug, Serialize)]
-pub struct ReadingProgress {
+pub struct Annotation {
+    pub id: Uuid,
     pub book_id: Uuid,
-    pub user_id: Uuid,
-    pub current_page: i32,
-    pub total_pages: i32,
-    pub last_read_at: DateTime<Utc>,
-    pub completed: bool,
+    pub kind: AnnotationKind,
+    pub page: i32,
+    pub location: Option<String>,
+    pub selected_text: Option<String>,
+    pub note: Option<String>,
+    pub color: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Annotation {
+    fn from_row(row: &tokio_postgres::Row) -> Self {
+        let kind: String = row.get("kind");
+        Annotation {
+            id: row.get("id"),
+            book_id: row.get("book_id"),
+            kind: kind.parse().unwrap_or(AnnotationKind::Bookmark),
+            page: row.get("page"),
+            location: row.get("location"),
+            selected_text: row.get("selected_text"),
+            note: row.get("note"),
+            color: row.get("color"),
+            created_at: row.get("created_at"),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct SyncQuery {
+    // Browsers cannot set headers on a WebSocket handshake, so the token may come in the query
+    pub token: Option<String>,
+    pub last_seq: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum SyncServerMessage<'a> {
+    Hello { session_id: Uuid, seq: u64 },
+    Resync { seq: u64 },
+    Event(&'a SyncEvent),
+    Pong,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum SyncClientMessage {
+    Ping,
+}
+
+async fn send_sync_message(
+    session: &mut actix_ws::Session,
+    message: &SyncServerMessage<'_>,
+) -> Result<(), actix_ws::Closed> {
+    match serde_json::to_string(message) {
+        Ok(text) => session.text(text).await,
+        Err(_) => Ok(()),
+    }
 }
 
 #[get("/content/{id}")]
@@ -34,48 +159,73 @@ pub async fn get_book_content(
     pool: web::Data<DbPool>,
     config: web::Data<Config>,
 ) -> impl Responder {
-    // Extract token from authorization header
+    // Parse book ID
+    let book_id = match Uuid::parse_str(&path.0) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().json("Invalid book ID"),
+    };
+
+    // Basic credentials are checked against the database
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    // Extract credentials from authorization header
     let auth_header = match req.headers().get("Authorization") {
         Some(header) => header,
-        None => return HttpResponse::Unauthorized().json("No authorization header"),
+        None => return challenge("No authorization header"),
     };
 
     let auth_str = match auth_header.to_str() {
         Ok(str) => str,
-        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+        Err(_) => return challenge("Invalid authorization header"),
     };
 
-    // Check if it's a Bearer token
-    if !auth_str.starts_with("Bearer ") {
-        return HttpResponse::Unauthorized().json("Invalid token format");
-    }
-
-    let token = &auth_str[7..]; // Remove "Bearer " prefix
+    // Reading time is reported through analytics sessions, so only the credentials are checked here
+    let user_id = if let Some(token) = auth_str.strip_prefix("Bearer ") {
+        let claims = match verify_token(token, &config) {
+            Ok(claims) => claims,
+            Err(_) => return challenge("Invalid token"),
+        };
 
-    // Verify the token
-    let claims = match verify_token(token, &config) {
-        Ok(claims) => claims,
-        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
-    };
-
-    // Get user ID from claims
-    let user_id = match Uuid::parse_str(&claims.sub) {
-        Ok(id) => id,
-        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+        match Uuid::parse_str(&claims.sub) {
+            Ok(id) => id,
+            Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+        }
+    } else if let Some(credentials) = auth_str.strip_prefix("Basic ") {
+        // OPDS clients follow acquisition links with the account's email and password
+        match auth::verify_basic_credentials(&client, credentials, &req, &config).await {
+            Ok(auth::BasicAuth::Valid(id)) => id,
+            Ok(auth::BasicAuth::Invalid) => return challenge("Invalid credentials"),
+            Ok(auth::BasicAuth::Throttled(retry_after)) => {
+                return HttpResponse::TooManyRequests()
+                    .insert_header(("Retry-After", retry_after.to_string()))
+                    .json("Too many failed sign-in attempts; try again later")
+            }
+            Ok(auth::BasicAuth::MfaRequired) => {
+                return HttpResponse::Forbidden()
+                    .json("Accounts with two-factor authentication must sign in with a token")
+            }
+            Err(e) => {
+                eprintln!("Database error: {}", e);
+                return HttpResponse::InternalServerError().json("Database error");
+            }
+        }
+    } else {
+        return challenge("Invalid token format");
     };
 
-    // Parse book ID
-    let book_id = match Uuid::parse_str(&path.0) {
-        Ok(id) => id,
-        Err(_) => return HttpResponse::BadRequest().json("Invalid book ID"),
-    };
+    match auth::verification::may_read(&client, &config, user_id).await {
+        Ok(true) => {}
+        Ok(false) => return HttpResponse::Forbidden().json("Verify your email address to read books"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Database error");
+        }
+    }
 
     // Check if the book exists and get its format and file path
-    let client = match pool.get().await {
-        Ok(client) => client,
-        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
-    };
-
     let book_result = client
         .query_one(
             "SELECT format, file_path FROM books WHERE id = $1",
@@ -94,17 +244,6 @@ pub async fn get_book_content(
     // Construct the full file path
     let full_path = Path::new(&config.book_storage_path).join(&file_path);
 
-    // Log reading analytics
-    let _ = client
-        .execute(
-            "INSERT INTO user_analytics (user_id, book_id, pages_read, reading_time_seconds, session_date)
-             VALUES ($1, $2, $3, $4, CURRENT_DATE)
-             ON CONFLICT (user_id, book_id, session_date)
-             DO UPDATE SET pages_read = user_analytics.pages_read + 1",
-            &[&user_id, &book_id, &1, &0],
-        )
-        .await;
-
     // Return the file based on format
     match format {
         BookFormat::PDF => {
@@ -130,13 +269,21 @@ pub async fn get_book_content(
     }
 }
 
+// A 401 that tells e-reader apps to prompt for a username and password
+fn challenge(message: &str) -> HttpResponse {
+    HttpResponse::Unauthorized()
+        .insert_header(("WWW-Authenticate", "Basic realm=\"E-Library\", charset=\"UTF-8\""))
+        .json(message)
+}
+
 #[post("/progress/{book_id}")]
 pub async fn save_reading_progress(
     req: HttpRequest,
     path: web::Path<(String,)>,
-    body: web::Json<ReadingProgressRequest>,
+    body: ValidJson<ReadingProgressRequest>,
     pool: web::Data<DbPool>,
     config: web::Data<Config>,
+    hub: web::Data<SyncHub>,
 ) -> impl Responder {
     // Extract token from authorization header
     let auth_header = match req.headers().get("Authorization") {
@@ -188,37 +335,18 @@ pub async fn save_reading_progress(
         return HttpResponse::NotFound().json("Book not found");
     }
 
-    // Check if completing the book
-    let completed = body.current_page >= body.total_pages;
-
     // Save or update reading progress
-    let result = client
-        .query_one(
-            "INSERT INTO user_reading_progress (user_id, book_id, current_page, total_pages, completed)
-             VALUES ($1, $2, $3, $4, $5)
-             ON CONFLICT (user_id, book_id)
-             DO UPDATE SET current_page = $3, total_pages = $4, completed = $5, last_read_at = CURRENT_TIMESTAMP
-             RETURNING id, user_id, book_id, current_page, total_pages, last_read_at, completed",
-            &[
-                &user_id,
-                &book_id,
-                &body.current_page,
-                &body.total_pages,
-                &completed,
-            ],
-        )
-        .await;
-
-    match result {
-        Ok(row) => {
-            let progress = ReadingProgress {
-                book_id: row.get("book_id"),
-                user_id: row.get("user_id"),
-                current_page: row.get("current_page"),
-                total_pages: row.get("total_pages"),
-                last_read_at: row.get("last_read_at"),
-                completed: row.get("completed"),
-            };
+    match progress::save(&client, user_id, book_id, body.current_page, body.total_pages).await {
+        Ok(progress) => {
+            // Push the new position to the user's other devices
+            hub.publish(
+                user_id,
+                sync::origin_session(&req),
+                SyncEventKind::Progress,
+                SyncAction::Upsert,
+                book_id,
+                serde_json::to_value(&progress).unwrap_or_default(),
+            );
 
             HttpResponse::Ok().json(progress)
         }
@@ -318,4 +446,390 @@ pub async fn get_reading_progress(
             HttpResponse::InternalServerError().json("Error fetching reading progress")
         }
     }
-}
\ No newline at end of file
+}
+
+#[get("/annotations/{book_id}")]
+pub async fn get_annotations(
+    req: HttpRequest,
+    path: web::Path<(String,)>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    // Parse book ID
+    let book_id = match Uuid::parse_str(&path.0) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().json("Invalid book ID"),
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let result = client
+        .query(
+            "SELECT id, book_id, kind, page, location, selected_text, note, color, created_at
+             FROM user_annotations
+             WHERE user_id = $1 AND book_id = $2
+             ORDER BY page, created_at",
+            &[&user_id, &book_id],
+        )
+        .await;
+
+    match result {
+        Ok(rows) => {
+            let annotations: Vec<Annotation> = rows.iter().map(Annotation::from_row).collect();
+            HttpResponse::Ok().json(annotations)
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error fetching annotations")
+        }
+    }
+}
+
+#[post("/annotations/{book_id}")]
+pub async fn create_annotation(
+    req: HttpRequest,
+    path: web::Path<(String,)>,
+    body: ValidJson<AnnotationRequest>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    hub: web::Data<SyncHub>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    // Parse book ID
+    let book_id = match Uuid::parse_str(&path.0) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().json("Invalid book ID"),
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let book_exists = client
+        .query_one("SELECT 1 FROM books WHERE id = $1", &[&book_id])
+        .await;
+
+    if book_exists.is_err() {
+        return HttpResponse::NotFound().json("Book not found");
+    }
+
+    let result = client
+        .query_one(
+            "INSERT INTO user_annotations (id, user_id, book_id, kind, page, location, selected_text, note, color)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+             RETURNING id, book_id, kind, page, location, selected_text, note, color, created_at",
+            &[
+                &Uuid::new_v4(),
+                &user_id,
+                &book_id,
+                &body.kind.to_string(),
+                &body.page,
+                &body.location,
+                &body.selected_text,
+                &body.note,
+                &body.color,
+            ],
+        )
+        .await;
+
+    match result {
+        Ok(row) => {
+            let annotation = Annotation::from_row(&row);
+
+            hub.publish(
+                user_id,
+                sync::origin_session(&req),
+                annotation.kind.into(),
+                SyncAction::Upsert,
+                book_id,
+                serde_json::to_value(&annotation).unwrap_or_default(),
+            );
+
+            HttpResponse::Created().json(annotation)
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error saving annotation")
+        }
+    }
+}
+
+#[delete("/annotations/{book_id}/{annotation_id}")]
+pub async fn delete_annotation(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    hub: web::Data<SyncHub>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    // Parse book and annotation IDs
+    let book_id = match Uuid::parse_str(&path.0) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().json("Invalid book ID"),
+    };
+
+    let annotation_id = match Uuid::parse_str(&path.1) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().json("Invalid annotation ID"),
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let result = client
+        .query_opt(
+            "DELETE FROM user_annotations
+             WHERE id = $1 AND user_id = $2 AND book_id = $3
+             RETURNING kind",
+            &[&annotation_id, &user_id, &book_id],
+        )
+        .await;
+
+    match result {
+        Ok(Some(row)) => {
+            let kind: String = row.get("kind");
+            let kind: AnnotationKind = kind.parse().unwrap_or(AnnotationKind::Bookmark);
+
+            hub.publish(
+                user_id,
+                sync::origin_session(&req),
+                kind.into(),
+                SyncAction::Delete,
+                book_id,
+                serde_json::json!({ "id": annotation_id }),
+            );
+
+            HttpResponse::Ok().json("Annotation deleted")
+        }
+        Ok(None) => HttpResponse::NotFound().json("Annotation not found"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error deleting annotation")
+        }
+    }
+}
+
+#[get("/sync")]
+pub async fn sync_socket(
+    req: HttpRequest,
+    body: web::Payload,
+    query: ValidQuery<SyncQuery>,
+    config: web::Data<Config>,
+    hub: web::Data<SyncHub>,
+) -> actix_web::Result<HttpResponse> {
+    // Accept the token from the Authorization header or, for browsers, the query string
+    let header_token = req
+        .headers()
+        .get("Authorization")
+        .and_then(|header| header.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    let token = match header_token.or(query.token.as_deref()) {
+        Some(token) => token,
+        None => return Ok(HttpResponse::Unauthorized().json("No authorization token")),
+    };
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return Ok(HttpResponse::Unauthorized().json("Invalid token")),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return Ok(HttpResponse::InternalServerError().json("Invalid user ID in token")),
+    };
+
+    let connection = match hub.connect(user_id, query.last_seq) {
+        Ok(connection) => connection,
+        Err(e) => return Ok(HttpResponse::TooManyRequests().json(e.to_string())),
+    };
+
+    let (response, mut session, stream) = match actix_ws::handle(&req, body) {
+        Ok(handshake) => handshake,
+        Err(e) => {
+            hub.disconnect(user_id, connection.session_id);
+            return Err(e);
+        }
+    };
+
+    let mut stream = stream.max_frame_size(sync::MAX_FRAME_SIZE);
+    let SyncConnection { session_id, current_seq, replay, mut receiver } = connection;
+
+    actix_web::rt::spawn(async move {
+        let mut heartbeat = tokio::time::interval(sync::HEARTBEAT_INTERVAL);
+        let mut last_seen = Instant::now();
+
+        let hello = SyncServerMessage::Hello { session_id, seq: current_seq };
+        let mut ready = send_sync_message(&mut session, &hello).await.is_ok();
+
+        // Catch the device up on whatever it missed while disconnected
+        match replay {
+            Replay::Events(events) => {
+                for event in events {
+                    if !ready {
+                        break;
+                    }
+                    ready = send_sync_message(&mut session, &SyncServerMessage::Event(&event)).await.is_ok();
+                }
+            }
+            Replay::Resync => {
+                let resync = SyncServerMessage::Resync { seq: current_seq };
+                ready = ready && send_sync_message(&mut session, &resync).await.is_ok();
+            }
+        }
+
+        let close_reason = if !ready {
+            None
+        } else {
+            loop {
+                tokio::select! {
+                    message = stream.next() => match message {
+                        Some(Ok(Message::Ping(bytes))) => {
+                            last_seen = Instant::now();
+                            if session.pong(&bytes).await.is_err() {
+                                break None;
+                            }
+                        }
+                        Some(Ok(Message::Pong(_))) => last_seen = Instant::now(),
+                        Some(Ok(Message::Text(text))) => {
+                            last_seen = Instant::now();
+                            if let Ok(SyncClientMessage::Ping) = serde_json::from_str(&text) {
+                                if send_sync_message(&mut session, &SyncServerMessage::Pong).await.is_err() {
+                                    break None;
+                                }
+                            }
+                        }
+                        Some(Ok(Message::Close(reason))) => break reason,
+                        Some(Ok(_)) => {}
+                        Some(Err(_)) | None => break None,
+                    },
+                    event = receiver.recv() => match event {
+                        Some(event) => {
+                            if send_sync_message(&mut session, &SyncServerMessage::Event(&event)).await.is_err() {
+                                break None;
+                            }
+                        }
+                        // The hub dropped us because our queue filled up
+                        None => break Some(CloseReason {
+                            code: CloseCode::Again,
+                            description: Some("Too far behind, reconnect with last_seq".to_string()),
+                        }),
+                    },
+                    _ = heartbeat.tick() => {
+                        if last_seen.elapsed() > sync::CLIENT_TIMEOUT {
+                            break Some(CloseReason {
+                                code: CloseCode::Policy,
+                                description: Some("Heartbeat timeout".to_string()),
+                            });
+                        }
+                        if session.ping(b"").await.is_err() {
+                            break None;
+                        }
+                    }
+                }
+            }
+        };
+
+        hub.disconnect(user_id, session_id);
+        let _ = session.close(close_reason).await;
+    });
+
+    Ok(response)
+}
@@ -1,5 +1,7 @@
 pub mod handlers;
 pub mod formats;
+pub mod progress;
+pub mod sync;
 
 use actix_web::web;
 
@@ -9,5 +11,9 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
             .service(handlers::get_book_content)
             .service(handlers::save_reading_progress)
             .service(handlers::get_reading_progress)
+            .service(handlers::get_annotations)
+            .service(handlers::create_annotation)
+            .service(handlers::delete_annotation)
+            .service(handlers::sync_socket)
     );
 }
\ No newline at end of file
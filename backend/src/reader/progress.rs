@@ -0,0 +1,50 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+
+#[derive(Debug, Serialize)]
+pub struct ReadingProgress {
+    pub book_id: Uuid,
+    pub user_id: Uuid,
+    pub current_page: i32,
+    pub total_pages: i32,
+    pub last_read_at: DateTime<Utc>,
+    pub completed: bool,
+}
+
+/// Saves a reader's position in a book. Reaching the last page marks the book as
+/// completed; moving back clears it.
+pub async fn save(
+    client: &deadpool_postgres::Client,
+    user_id: Uuid,
+    book_id: Uuid,
+    current_page: i32,
+    total_pages: i32,
+) -> Result<ReadingProgress, tokio_postgres::Error> {
+    // Check if completing the book
+    let completed = current_page >= total_pages;
+
+    let row = client
+        .query_one(
+            "INSERT INTO user_reading_progress (user_id, book_id, current_page, total_pages, completed, completed_at)
+             VALUES ($1, $2, $3, $4, $5, CASE WHEN $5 THEN CURRENT_TIMESTAMP END)
+             ON CONFLICT (user_id, book_id)
+             DO UPDATE SET current_page = $3, total_pages = $4, completed = $5, last_read_at = CURRENT_TIMESTAMP,
+                 completed_at = CASE
+                     WHEN NOT $5 THEN NULL
+                     ELSE COALESCE(user_reading_progress.completed_at, CURRENT_TIMESTAMP)
+                 END
+             RETURNING id, user_id, book_id, current_page, total_pages, last_read_at, completed",
+            &[&user_id, &book_id, &current_page, &total_pages, &completed],
+        )
+        .await?;
+
+    Ok(ReadingProgress {
+        book_id: row.get("book_id"),
+        user_id: row.get("user_id"),
+        current_page: row.get("current_page"),
+        total_pages: row.get("total_pages"),
+        last_read_at: row.get("last_read_at"),
+        completed: row.get("completed"),
+    })
+}
\ No newline at end of file
@@ -0,0 +1,221 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+// How often the server pings each socket, and how long it waits for a pong
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+pub const CLIENT_TIMEOUT: Duration = Duration::from_secs(45);
+
+// Largest frame we accept from a client; clients only send small control messages
+pub const MAX_FRAME_SIZE: usize = 16 * 1024;
+
+// Events kept per user so a reconnecting device can catch up
+const REPLAY_BUFFER_SIZE: usize = 256;
+
+// How long a user's backlog outlives their last socket, so a dropped connection can still replay
+const IDLE_CHANNEL_TTL: Duration = Duration::from_secs(10 * 60);
+
+// Events queued per socket before the socket is considered too slow and dropped
+const SESSION_QUEUE_SIZE: usize = 64;
+
+// Concurrent sockets allowed per user
+const MAX_SESSIONS_PER_USER: usize = 10;
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncEventKind {
+    Progress,
+    Bookmark,
+    Highlight,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncAction {
+    Upsert,
+    Delete,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SyncEvent {
+    pub seq: u64,
+    pub kind: SyncEventKind,
+    pub action: SyncAction,
+    pub book_id: Uuid,
+    pub data: serde_json::Value,
+}
+
+#[derive(Debug)]
+pub enum Replay {
+    // Every event after the client's last-seen sequence number, oldest first
+    Events(Vec<Arc<SyncEvent>>),
+    // The client is too far behind (or from before a restart) and must refetch state over REST
+    Resync,
+}
+
+pub struct SyncConnection {
+    pub session_id: Uuid,
+    pub current_seq: u64,
+    pub replay: Replay,
+    pub receiver: mpsc::Receiver<Arc<SyncEvent>>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SyncError {
+    #[error("Too many open sync sessions")]
+    TooManySessions,
+}
+
+struct UserChannel {
+    last_seq: u64,
+    backlog: VecDeque<Arc<SyncEvent>>,
+    sessions: HashMap<Uuid, mpsc::Sender<Arc<SyncEvent>>>,
+    // Set while no socket is open; the channel is dropped once this is older than IDLE_CHANNEL_TTL
+    idle_since: Option<Instant>,
+}
+
+impl UserChannel {
+    fn new() -> Self {
+        // Sequence numbers start at the creation time in microseconds, so they keep growing
+        // across evicted channels and restarts and a stale `last_seq` always asks for a resync
+        let start = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_micros() as u64)
+            .unwrap_or_default();
+
+        UserChannel {
+            last_seq: start,
+            backlog: VecDeque::new(),
+            sessions: HashMap::new(),
+            idle_since: None,
+        }
+    }
+
+    fn is_expired(&self, now: Instant) -> bool {
+        self.idle_since.is_some_and(|since| now.duration_since(since) >= IDLE_CHANNEL_TTL)
+    }
+}
+
+/// In-memory fan-out of reading state changes to each user's connected devices.
+#[derive(Default)]
+pub struct SyncHub {
+    users: Mutex<HashMap<Uuid, UserChannel>>,
+}
+
+impl SyncHub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn connect(&self, user_id: Uuid, last_seen: Option<u64>) -> Result<SyncConnection, SyncError> {
+        let mut users = self.users.lock().unwrap();
+        let now = Instant::now();
+        users.retain(|id, channel| *id == user_id || !channel.is_expired(now));
+
+        let channel = users.entry(user_id).or_insert_with(UserChannel::new);
+        if channel.is_expired(now) {
+            *channel = UserChannel::new();
+        }
+
+        if channel.sessions.len() >= MAX_SESSIONS_PER_USER {
+            return Err(SyncError::TooManySessions);
+        }
+
+        let replay = match last_seen {
+            None => Replay::Events(Vec::new()),
+            Some(seq) if seq > channel.last_seq => Replay::Resync,
+            Some(seq) => {
+                let oldest = channel.backlog.front().map(|e| e.seq).unwrap_or(channel.last_seq + 1);
+                if seq + 1 < oldest {
+                    Replay::Resync
+                } else {
+                    Replay::Events(
+                        channel.backlog.iter().filter(|e| e.seq > seq).cloned().collect(),
+                    )
+                }
+            }
+        };
+
+        let (sender, receiver) = mpsc::channel(SESSION_QUEUE_SIZE);
+        let session_id = Uuid::new_v4();
+        channel.sessions.insert(session_id, sender);
+        channel.idle_since = None;
+
+        Ok(SyncConnection {
+            session_id,
+            current_seq: channel.last_seq,
+            replay,
+            receiver,
+        })
+    }
+
+    pub fn disconnect(&self, user_id: Uuid, session_id: Uuid) {
+        let mut users = self.users.lock().unwrap();
+        if let Some(channel) = users.get_mut(&user_id) {
+            channel.sessions.remove(&session_id);
+            if channel.sessions.is_empty() && channel.idle_since.is_none() {
+                channel.idle_since = Some(Instant::now());
+            }
+        }
+    }
+
+    /// Records a change and pushes it to every session of the user except `origin`.
+    /// Sessions whose queue is full are dropped; they reconnect and replay from the backlog.
+    /// Nothing is kept for users without a recent socket, who resync on their next connect.
+    pub fn publish(
+        &self,
+        user_id: Uuid,
+        origin: Option<Uuid>,
+        kind: SyncEventKind,
+        action: SyncAction,
+        book_id: Uuid,
+        data: serde_json::Value,
+    ) -> Option<u64> {
+        let mut users = self.users.lock().unwrap();
+        let channel = users.get_mut(&user_id)?;
+
+        if channel.is_expired(Instant::now()) {
+            users.remove(&user_id);
+            return None;
+        }
+
+        channel.last_seq += 1;
+        let event = Arc::new(SyncEvent {
+            seq: channel.last_seq,
+            kind,
+            action,
+            book_id,
+            data,
+        });
+
+        channel.backlog.push_back(event.clone());
+        if channel.backlog.len() > REPLAY_BUFFER_SIZE {
+            channel.backlog.pop_front();
+        }
+
+        channel.sessions.retain(|session_id, sender| {
+            if Some(*session_id) == origin {
+                return true;
+            }
+            sender.try_send(event.clone()).is_ok()
+        });
+        if channel.sessions.is_empty() && channel.idle_since.is_none() {
+            channel.idle_since = Some(Instant::now());
+        }
+
+        Some(event.seq)
+    }
+}
+
+/// Session id of the socket that caused a change, sent by clients as `X-Sync-Session`
+/// so the change is not echoed back to the device that made it.
+pub fn origin_session(req: &actix_web::HttpRequest) -> Option<Uuid> {
+    req.headers()
+        .get("X-Sync-Session")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| Uuid::parse_str(value).ok())
+}
@@ -0,0 +1,277 @@
+use actix_web::{web, HttpResponse, Responder, get, HttpRequest};
+use uuid::Uuid;
+
+use crate::auth::verify_token;
+use crate::config::Config;
+use crate::db::DbPool;
+use crate::validation::ValidQuery;
+use super::models::{RecommendationQuery, RecommendedBook, BookRecommendations};
+
+const DEFAULT_LIMIT: i64 = 10;
+
+// Window used for the popular-books fallback
+const POPULAR_WINDOW_DAYS: i32 = 30;
+
+#[get("")]
+pub async fn get_user_recommendations(
+    req: HttpRequest,
+    query: ValidQuery<RecommendationQuery>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let result = client
+        .query(
+            "SELECT r.book_id, b.title, b.author, b.cover_image, c.name AS category_name,
+                    r.score, r.because_book_id, sb.title AS because_title,
+                    COALESCE(s.co_readers, 0) AS co_readers,
+                    COALESCE(s.same_author, FALSE) AS same_author,
+                    COALESCE(s.same_category, FALSE) AS same_category,
+                    COALESCE(s.shared_tags, '{}') AS shared_tags
+             FROM user_recommendations r
+             JOIN books b ON b.id = r.book_id
+             JOIN categories c ON c.id = b.category_id
+             LEFT JOIN books sb ON sb.id = r.because_book_id
+             LEFT JOIN book_similarities s
+               ON s.book_id = r.because_book_id AND s.similar_book_id = r.book_id
+             WHERE r.user_id = $1
+             ORDER BY r.rank
+             LIMIT $2",
+            &[&user_id, &limit],
+        )
+        .await;
+
+    let rows = match result {
+        Ok(rows) => rows,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Error fetching recommendations");
+        }
+    };
+
+    if !rows.is_empty() {
+        let books: Vec<RecommendedBook> = rows
+            .iter()
+            .map(|row| {
+                let because_title: Option<String> = row.get("because_title");
+                let reasons = reasons(row, because_title.as_deref().unwrap_or("this book"));
+                RecommendedBook {
+                    book_id: row.get("book_id"),
+                    title: row.get("title"),
+                    author: row.get("author"),
+                    cover_image: row.get("cover_image"),
+                    score: row.get("score"),
+                    because_book_id: row.get("because_book_id"),
+                    explanation: match because_title {
+                        Some(title) => format!("Because you read {}", title),
+                        None => "Recommended for you".to_string(),
+                    },
+                    reasons,
+                }
+            })
+            .collect();
+
+        return HttpResponse::Ok().json(books);
+    }
+
+    // New readers, or readers whose books have no neighbours yet, get what's popular
+    let result = client
+        .query(
+            "SELECT b.id, b.title, b.author, b.cover_image, COUNT(*) AS readers
+             FROM user_reading_progress p
+             JOIN books b ON b.id = p.book_id
+             WHERE p.last_read_at >= CURRENT_TIMESTAMP - make_interval(days => $2)
+               AND NOT EXISTS (
+                   SELECT 1 FROM user_reading_progress r
+                   WHERE r.user_id = $1 AND r.book_id = b.id
+               )
+             GROUP BY b.id, b.title, b.author, b.cover_image
+             ORDER BY readers DESC, b.title
+             LIMIT $3",
+            &[&user_id, &POPULAR_WINDOW_DAYS, &limit],
+        )
+        .await;
+
+    match result {
+        Ok(rows) => {
+            let books: Vec<RecommendedBook> = rows
+                .iter()
+                .map(|row| {
+                    let readers: i64 = row.get("readers");
+                    RecommendedBook {
+                        book_id: row.get("id"),
+                        title: row.get("title"),
+                        author: row.get("author"),
+                        cover_image: row.get("cover_image"),
+                        score: readers as f64,
+                        because_book_id: None,
+                        explanation: "Popular with readers this month".to_string(),
+                        reasons: vec![format!("{} readers opened it in the last {} days", readers, POPULAR_WINDOW_DAYS)],
+                    }
+                })
+                .collect();
+
+            HttpResponse::Ok().json(books)
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error fetching recommendations")
+        }
+    }
+}
+
+#[get("/books/{id}")]
+pub async fn get_book_recommendations(
+    path: web::Path<(String,)>,
+    query: ValidQuery<RecommendationQuery>,
+    pool: web::Data<DbPool>,
+) -> impl Responder {
+    let book_id = match Uuid::parse_str(&path.0) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().json("Invalid book ID"),
+    };
+
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT) as usize;
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let title: String = match client
+        .query_opt("SELECT title FROM books WHERE id = $1", &[&book_id])
+        .await
+    {
+        Ok(Some(row)) => row.get("title"),
+        Ok(None) => return HttpResponse::NotFound().json("Book not found"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Database error");
+        }
+    };
+
+    let result = client
+        .query(
+            "SELECT s.similar_book_id, b.title, b.author, b.cover_image, c.name AS category_name,
+                    s.score, s.co_reading_score, s.co_readers, s.same_author, s.same_category, s.shared_tags
+             FROM book_similarities s
+             JOIN books b ON b.id = s.similar_book_id
+             JOIN categories c ON c.id = b.category_id
+             WHERE s.book_id = $1
+             ORDER BY s.score DESC",
+            &[&book_id],
+        )
+        .await;
+
+    let rows = match result {
+        Ok(rows) => rows,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Error fetching recommendations");
+        }
+    };
+
+    let to_book = |row: &tokio_postgres::Row, score: f64, explanation: String| RecommendedBook {
+        book_id: row.get("similar_book_id"),
+        title: row.get("title"),
+        author: row.get("author"),
+        cover_image: row.get("cover_image"),
+        score,
+        because_book_id: Some(book_id),
+        explanation,
+        reasons: reasons(row, &title),
+    };
+
+    // Only books that share readers, strongest overlap first
+    let mut co_read: Vec<&tokio_postgres::Row> = rows
+        .iter()
+        .filter(|row| row.get::<_, i32>("co_readers") > 0)
+        .collect();
+    co_read.sort_by(|a, b| {
+        b.get::<_, f64>("co_reading_score")
+            .total_cmp(&a.get::<_, f64>("co_reading_score"))
+    });
+
+    let readers_also_read = co_read
+        .into_iter()
+        .take(limit)
+        .map(|row| {
+            to_book(
+                row,
+                row.get("co_reading_score"),
+                format!("Readers of {} also read this", title),
+            )
+        })
+        .collect();
+
+    let similar_books = rows
+        .iter()
+        .take(limit)
+        .map(|row| to_book(row, row.get("score"), format!("Similar to {}", title)))
+        .collect();
+
+    HttpResponse::Ok().json(BookRecommendations {
+        book_id,
+        readers_also_read,
+        similar_books,
+    })
+}
+
+// Human-readable reasons behind a similarity, strongest signal first
+fn reasons(row: &tokio_postgres::Row, seed_title: &str) -> Vec<String> {
+    let mut reasons = Vec::new();
+
+    let co_readers: i32 = row.get("co_readers");
+    if co_readers > 0 {
+        reasons.push(format!("{} readers of {} also read this", co_readers, seed_title));
+    }
+    if row.get::<_, bool>("same_author") {
+        reasons.push(format!("Also by {}", row.get::<_, String>("author")));
+    }
+    if row.get::<_, bool>("same_category") {
+        reasons.push(format!("Also in {}", row.get::<_, String>("category_name")));
+    }
+
+    let shared_tags: Vec<String> = row.get("shared_tags");
+    if !shared_tags.is_empty() {
+        reasons.push(format!("Shares themes: {}", shared_tags.join(", ")));
+    }
+
+    reasons
+}
\ No newline at end of file
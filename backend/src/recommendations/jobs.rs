@@ -0,0 +1,47 @@
+use std::time::Duration;
+
+use log::{error, info};
+
+use crate::db::DbPool;
+use super::scoring;
+
+/// Periodically recomputes book similarities and every user's recommendations.
+pub fn spawn_recommendation_builder(pool: DbPool, every: Duration) {
+    actix_web::rt::spawn(async move {
+        let mut interval = tokio::time::interval(every);
+
+        loop {
+            interval.tick().await;
+
+            let mut client = match pool.get().await {
+                Ok(client) => client,
+                Err(e) => {
+                    error!("Recommendation builder could not get a connection: {}", e);
+                    continue;
+                }
+            };
+
+            let tx = match client.transaction().await {
+                Ok(tx) => tx,
+                Err(e) => {
+                    error!("Recommendation builder could not start a transaction: {}", e);
+                    continue;
+                }
+            };
+
+            let result = match scoring::rebuild(&tx).await {
+                Ok(counts) => tx.commit().await.map(|_| counts),
+                Err(e) => Err(e),
+            };
+
+            match result {
+                Ok(None) => {}
+                Ok(Some((pairs, recommendations))) => info!(
+                    "Rebuilt {} book similarities and {} user recommendations",
+                    pairs, recommendations
+                ),
+                Err(e) => error!("Recommendation builder failed: {}", e),
+            }
+        }
+    });
+}
\ No newline at end of file
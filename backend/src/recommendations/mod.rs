@@ -0,0 +1,14 @@
+pub mod handlers;
+pub mod jobs;
+pub mod models;
+pub mod scoring;
+
+use actix_web::web;
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/recommendations")
+            .service(handlers::get_user_recommendations)
+            .service(handlers::get_book_recommendations)
+    );
+}
\ No newline at end of file
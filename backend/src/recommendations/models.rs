@@ -0,0 +1,32 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::Validate;
+
+// Most recommendations returned at once
+pub const MAX_LIMIT: i64 = 50;
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct RecommendationQuery {
+    #[validate(range(min = 1, max = MAX_LIMIT))]
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecommendedBook {
+    pub book_id: Uuid,
+    pub title: String,
+    pub author: String,
+    pub cover_image: String,
+    pub score: f64,
+    // The book this one is recommended because of, when there is one
+    pub because_book_id: Option<Uuid>,
+    pub explanation: String,
+    pub reasons: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BookRecommendations {
+    pub book_id: Uuid,
+    pub readers_also_read: Vec<RecommendedBook>,
+    pub similar_books: Vec<RecommendedBook>,
+}
\ No newline at end of file
@@ -0,0 +1,140 @@
+use deadpool_postgres::Transaction;
+
+// Pairs of books read by fewer users than this are treated as coincidence
+const MIN_CO_READERS: i64 = 2;
+
+// Neighbours kept per book
+const SIMILAR_BOOKS_PER_BOOK: i64 = 20;
+
+// Recommendations kept per user
+const RECOMMENDATIONS_PER_USER: i64 = 30;
+
+// How co-reading and catalog similarity are blended into one score
+const CO_READING_WEIGHT: f64 = 0.6;
+const CONTENT_WEIGHT: f64 = 0.4;
+
+// Books read this many days ago count for about a third as much as today's
+const RECENCY_DAYS: f64 = 180.0;
+
+/// Recomputes both tables. Returns (similar pairs, user recommendations) written.
+/// Runs are skipped while another instance holds the lock.
+pub async fn rebuild(tx: &Transaction<'_>) -> Result<Option<(u64, u64)>, tokio_postgres::Error> {
+    let locked: bool = tx
+        .query_one("SELECT pg_try_advisory_xact_lock(hashtext('recommendations'))", &[])
+        .await?
+        .get(0);
+
+    if !locked {
+        return Ok(None);
+    }
+
+    let pairs = rebuild_similarities(tx).await?;
+    let recommendations = rebuild_user_recommendations(tx).await?;
+
+    Ok(Some((pairs, recommendations)))
+}
+
+// Co-reading is the cosine similarity of two books' reader sets. Content similarity
+// credits a shared author, a shared category and the overlap (Jaccard) of their tags.
+async fn rebuild_similarities(tx: &Transaction<'_>) -> Result<u64, tokio_postgres::Error> {
+    tx.execute("DELETE FROM book_similarities", &[]).await?;
+
+    tx.execute(
+        "INSERT INTO book_similarities
+            (book_id, similar_book_id, score, co_reading_score, content_score,
+             co_readers, same_author, same_category, shared_tags)
+         WITH readers AS (
+             SELECT book_id, COUNT(*)::float8 AS readers
+             FROM user_reading_progress
+             GROUP BY book_id
+         ),
+         co_reading AS (
+             SELECT a.book_id, b.book_id AS similar_book_id, COUNT(*) AS co_readers
+             FROM user_reading_progress a
+             JOIN user_reading_progress b ON b.user_id = a.user_id AND b.book_id <> a.book_id
+             GROUP BY a.book_id, b.book_id
+             HAVING COUNT(*) >= $1
+         ),
+         content AS (
+             SELECT x.id AS book_id, y.id AS similar_book_id,
+                    lower(x.author) = lower(y.author) AS same_author,
+                    x.category_id = y.category_id AS same_category,
+                    ARRAY(SELECT unnest(x.tags) INTERSECT SELECT unnest(y.tags)) AS shared_tags,
+                    cardinality(ARRAY(SELECT unnest(x.tags) UNION SELECT unnest(y.tags))) AS all_tags
+             FROM books x
+             JOIN books y
+               ON y.id <> x.id
+              AND (lower(x.author) = lower(y.author) OR x.category_id = y.category_id OR x.tags && y.tags)
+         ),
+         combined AS (
+             SELECT COALESCE(c.book_id, t.book_id) AS book_id,
+                    COALESCE(c.similar_book_id, t.similar_book_id) AS similar_book_id,
+                    COALESCE(c.co_readers / sqrt(ra.readers * rb.readers), 0) AS co_reading_score,
+                    COALESCE(
+                        CASE WHEN t.same_author THEN 0.5 ELSE 0 END
+                        + CASE WHEN t.same_category THEN 0.3 ELSE 0 END
+                        + 0.2 * cardinality(t.shared_tags) / NULLIF(t.all_tags, 0),
+                        0
+                    )::float8 AS content_score,
+                    COALESCE(c.co_readers, 0)::int AS co_readers,
+                    COALESCE(t.same_author, FALSE) AS same_author,
+                    COALESCE(t.same_category, FALSE) AS same_category,
+                    COALESCE(t.shared_tags, '{}') AS shared_tags
+             FROM co_reading c
+             FULL JOIN content t ON t.book_id = c.book_id AND t.similar_book_id = c.similar_book_id
+             LEFT JOIN readers ra ON ra.book_id = c.book_id
+             LEFT JOIN readers rb ON rb.book_id = c.similar_book_id
+         ),
+         ranked AS (
+             SELECT *,
+                    $2::float8 * co_reading_score + $3::float8 * content_score AS score,
+                    ROW_NUMBER() OVER (
+                        PARTITION BY book_id
+                        ORDER BY $2::float8 * co_reading_score + $3::float8 * content_score DESC, similar_book_id
+                    ) AS position
+             FROM combined
+         )
+         SELECT book_id, similar_book_id, score, co_reading_score, content_score,
+                co_readers, same_author, same_category, shared_tags
+         FROM ranked
+         WHERE position <= $4 AND score > 0",
+        &[&MIN_CO_READERS, &CO_READING_WEIGHT, &CONTENT_WEIGHT, &SIMILAR_BOOKS_PER_BOOK],
+    )
+    .await
+}
+
+// A user's candidates are the neighbours of everything they have read, weighted by
+// how recently they read it and whether they finished it. Books they already
+// started are never recommended.
+async fn rebuild_user_recommendations(tx: &Transaction<'_>) -> Result<u64, tokio_postgres::Error> {
+    tx.execute("DELETE FROM user_recommendations", &[]).await?;
+
+    tx.execute(
+        "INSERT INTO user_recommendations (user_id, book_id, rank, score, because_book_id)
+         WITH contributions AS (
+             SELECT p.user_id, s.book_id AS seed_id, s.similar_book_id,
+                    s.score
+                    * CASE WHEN p.completed THEN 1.0 ELSE 0.5 END
+                    * exp(-EXTRACT(EPOCH FROM CURRENT_TIMESTAMP - p.last_read_at) / (86400.0 * $1::float8)) AS contribution
+             FROM user_reading_progress p
+             JOIN book_similarities s ON s.book_id = p.book_id
+             WHERE NOT EXISTS (
+                 SELECT 1 FROM user_reading_progress r
+                 WHERE r.user_id = p.user_id AND r.book_id = s.similar_book_id
+             )
+         ),
+         ranked AS (
+             SELECT user_id, similar_book_id,
+                    SUM(contribution) AS score,
+                    (array_agg(seed_id ORDER BY contribution DESC))[1] AS because_book_id,
+                    ROW_NUMBER() OVER (PARTITION BY user_id ORDER BY SUM(contribution) DESC, similar_book_id) AS rank
+             FROM contributions
+             GROUP BY user_id, similar_book_id
+         )
+         SELECT user_id, similar_book_id, rank::int, score, because_book_id
+         FROM ranked
+         WHERE rank <= $2",
+        &[&RECENCY_DAYS, &RECOMMENDATIONS_PER_USER],
+    )
+    .await
+}
\ No newline at end of file
@@ -3,12 +3,22 @@ use crate::auth;
 use crate::catalog;
 use crate::reader;
 use crate::analytics;
+use crate::admin;
+use crate::shelves;
+use crate::recommendations;
+use crate::opds;
+use crate::koreader;
+use crate::oidc;
+use crate::privacy;
 
 pub fn configure(cfg: &mut web::ServiceConfig) {
     // Configure all routes for our API
     
     // Auth routes
     auth::configure(cfg);
+
+    // Single sign-on routes
+    oidc::configure(cfg);
     
     // Catalog routes
     catalog::configure(cfg);
@@ -18,6 +28,24 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
     
     // Analytics routes
     analytics::configure(cfg);
+
+    // Shelf routes
+    shelves::configure(cfg);
+
+    // Recommendation routes
+    recommendations::configure(cfg);
+
+    // OPDS feeds for e-reader apps
+    opds::configure(cfg);
+
+    // KOReader progress sync
+    koreader::configure(cfg);
+
+    // Personal data exports
+    privacy::configure(cfg);
+
+    // Staff dashboard routes
+    admin::configure(cfg);
     
     // Health check endpoint
     cfg.route("/health", web::get().to(health_check));
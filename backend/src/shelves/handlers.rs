@@ -0,0 +1,895 @@
+use actix_web::{web, HttpResponse, Responder, get, post, put, delete, HttpRequest};
+use std::collections::HashSet;
+use uuid::Uuid;
+
+use crate::auth::verify_token;
+use crate::config::Config;
+use crate::db::DbPool;
+use crate::validation::ValidJson;
+use super::models::{
+    Shelf, ShelfBook, ShelfDetail, ShelfKind, SharedShelf,
+    CreateShelfRequest, UpdateShelfRequest, AddBookRequest, ReorderRequest,
+};
+
+// Shelves a single user may have, default ones included
+const MAX_SHELVES_PER_USER: i64 = 100;
+
+const SHELF_SELECT: &str = "SELECT s.id, s.name, s.kind, s.is_public, s.share_token, s.created_at, s.updated_at,
+        (SELECT COUNT(*) FROM shelf_books sb WHERE sb.shelf_id = s.id) AS book_count
+    FROM shelves s";
+
+#[get("")]
+pub async fn get_shelves(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    // Default shelves first, then custom lists in the order they were created
+    let sql = format!(
+        "{} WHERE s.user_id = $1
+         ORDER BY CASE s.kind WHEN 'want_to_read' THEN 0 WHEN 'favorites' THEN 1 ELSE 2 END, s.created_at",
+        SHELF_SELECT
+    );
+
+    match client.query(&sql, &[&user_id]).await {
+        Ok(rows) => {
+            let shelves: Vec<Shelf> = rows.iter().map(Shelf::from_row).collect();
+            HttpResponse::Ok().json(shelves)
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error fetching shelves")
+        }
+    }
+}
+
+#[post("")]
+pub async fn create_shelf(
+    req: HttpRequest,
+    body: ValidJson<CreateShelfRequest>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let name = body.name.trim().to_string();
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let existing = client
+        .query_one(
+            "SELECT COUNT(*) AS shelves, COUNT(*) FILTER (WHERE name = $2) AS same_name
+             FROM shelves WHERE user_id = $1",
+            &[&user_id, &name],
+        )
+        .await;
+
+    match existing {
+        Ok(row) => {
+            if row.get::<_, i64>("same_name") > 0 {
+                return HttpResponse::Conflict().json("A shelf with this name already exists");
+            }
+            if row.get::<_, i64>("shelves") >= MAX_SHELVES_PER_USER {
+                return HttpResponse::BadRequest().json("Shelf limit reached");
+            }
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Database error");
+        }
+    }
+
+    let is_public = body.is_public.unwrap_or(false);
+    let share_token = is_public.then(new_share_token);
+
+    let shelf_id = Uuid::new_v4();
+    let result = client
+        .execute(
+            "INSERT INTO shelves (id, user_id, name, kind, is_public, share_token)
+             VALUES ($1, $2, $3, $4, $5, $6)",
+            &[&shelf_id, &user_id, &name, &ShelfKind::Custom.to_string(), &is_public, &share_token],
+        )
+        .await;
+
+    if let Err(e) = result {
+        eprintln!("Database error: {}", e);
+        return HttpResponse::InternalServerError().json("Error creating shelf");
+    }
+
+    match client
+        .query_one(&format!("{} WHERE s.id = $1", SHELF_SELECT), &[&shelf_id])
+        .await
+    {
+        Ok(row) => HttpResponse::Created().json(Shelf::from_row(&row)),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error fetching shelf")
+        }
+    }
+}
+
+#[get("/shared/{token}")]
+pub async fn get_shared_shelf(
+    path: web::Path<(String,)>,
+    pool: web::Data<DbPool>,
+) -> impl Responder {
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let shelf = client
+        .query_opt(
+            "SELECT s.id, s.name, u.username
+             FROM shelves s
+             JOIN users u ON u.id = s.user_id
+             WHERE s.share_token = $1 AND s.is_public",
+            &[&path.0],
+        )
+        .await;
+
+    let (shelf_id, name, owner): (Uuid, String, String) = match shelf {
+        Ok(Some(row)) => (row.get("id"), row.get("name"), row.get("username")),
+        Ok(None) => return HttpResponse::NotFound().json("Shelf not found"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Database error");
+        }
+    };
+
+    match load_books(&client, shelf_id).await {
+        Ok(books) => HttpResponse::Ok().json(SharedShelf { name, owner, books }),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error fetching shelf")
+        }
+    }
+}
+
+#[get("/{id}")]
+pub async fn get_shelf(
+    req: HttpRequest,
+    path: web::Path<(String,)>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let shelf_id = match Uuid::parse_str(&path.0) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().json("Invalid shelf ID"),
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let shelf = match client
+        .query_opt(
+            &format!("{} WHERE s.id = $1 AND s.user_id = $2", SHELF_SELECT),
+            &[&shelf_id, &user_id],
+        )
+        .await
+    {
+        Ok(Some(row)) => Shelf::from_row(&row),
+        Ok(None) => return HttpResponse::NotFound().json("Shelf not found"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Database error");
+        }
+    };
+
+    match load_books(&client, shelf_id).await {
+        Ok(books) => HttpResponse::Ok().json(ShelfDetail { shelf, books }),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error fetching shelf")
+        }
+    }
+}
+
+#[put("/{id}")]
+pub async fn update_shelf(
+    req: HttpRequest,
+    path: web::Path<(String,)>,
+    body: ValidJson<UpdateShelfRequest>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let shelf_id = match Uuid::parse_str(&path.0) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().json("Invalid shelf ID"),
+    };
+
+    let new_name = body.name.as_deref().map(|name| name.trim().to_string());
+
+    let mut client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let tx = match client.transaction().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let current = tx
+        .query_opt(
+            "SELECT name, kind, is_public, share_token FROM shelves WHERE id = $1 AND user_id = $2 FOR UPDATE",
+            &[&shelf_id, &user_id],
+        )
+        .await;
+
+    let (name, kind, is_public, share_token): (String, String, bool, Option<String>) = match current {
+        Ok(Some(row)) => (row.get("name"), row.get("kind"), row.get("is_public"), row.get("share_token")),
+        Ok(None) => return HttpResponse::NotFound().json("Shelf not found"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Database error");
+        }
+    };
+
+    let name = match new_name {
+        Some(new_name) if new_name != name => {
+            if kind != ShelfKind::Custom.to_string() {
+                return HttpResponse::BadRequest().json("Default shelves cannot be renamed");
+            }
+
+            match tx
+                .query_opt(
+                    "SELECT 1 FROM shelves WHERE user_id = $1 AND name = $2",
+                    &[&user_id, &new_name],
+                )
+                .await
+            {
+                Ok(Some(_)) => return HttpResponse::Conflict().json("A shelf with this name already exists"),
+                Ok(None) => new_name,
+                Err(e) => {
+                    eprintln!("Database error: {}", e);
+                    return HttpResponse::InternalServerError().json("Database error");
+                }
+            }
+        }
+        _ => name,
+    };
+
+    // Making a shelf private drops its link, so a later share hands out a fresh one
+    let is_public = body.is_public.unwrap_or(is_public);
+    let share_token = match (is_public, share_token) {
+        (false, _) => None,
+        (true, Some(token)) if !body.regenerate_link.unwrap_or(false) => Some(token),
+        (true, _) => Some(new_share_token()),
+    };
+
+    let result = async move {
+        tx.execute(
+            "UPDATE shelves
+             SET name = $3, is_public = $4, share_token = $5, updated_at = CURRENT_TIMESTAMP
+             WHERE id = $1 AND user_id = $2",
+            &[&shelf_id, &user_id, &name, &is_public, &share_token],
+        )
+        .await?;
+        tx.commit().await
+    }
+    .await;
+
+    if let Err(e) = result {
+        eprintln!("Database error: {}", e);
+        return HttpResponse::InternalServerError().json("Error updating shelf");
+    }
+
+    match client
+        .query_one(&format!("{} WHERE s.id = $1", SHELF_SELECT), &[&shelf_id])
+        .await
+    {
+        Ok(row) => HttpResponse::Ok().json(Shelf::from_row(&row)),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error fetching shelf")
+        }
+    }
+}
+
+#[delete("/{id}")]
+pub async fn delete_shelf(
+    req: HttpRequest,
+    path: web::Path<(String,)>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let shelf_id = match Uuid::parse_str(&path.0) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().json("Invalid shelf ID"),
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let kind: String = match client
+        .query_opt(
+            "SELECT kind FROM shelves WHERE id = $1 AND user_id = $2",
+            &[&shelf_id, &user_id],
+        )
+        .await
+    {
+        Ok(Some(row)) => row.get("kind"),
+        Ok(None) => return HttpResponse::NotFound().json("Shelf not found"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Database error");
+        }
+    };
+
+    if kind != ShelfKind::Custom.to_string() {
+        return HttpResponse::BadRequest().json("Default shelves cannot be deleted");
+    }
+
+    match client
+        .execute(
+            "DELETE FROM shelves WHERE id = $1 AND user_id = $2",
+            &[&shelf_id, &user_id],
+        )
+        .await
+    {
+        Ok(_) => HttpResponse::Ok().json("Shelf deleted successfully"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error deleting shelf")
+        }
+    }
+}
+
+#[post("/{id}/books")]
+pub async fn add_book(
+    req: HttpRequest,
+    path: web::Path<(String,)>,
+    body: ValidJson<AddBookRequest>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let shelf_id = match Uuid::parse_str(&path.0) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().json("Invalid shelf ID"),
+    };
+
+    let book_id = match Uuid::parse_str(&body.book_id) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().json("Invalid book ID"),
+    };
+
+    let mut client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let tx = match client.transaction().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    // Locking the shelf serializes position changes
+    match tx
+        .query_opt(
+            "SELECT 1 FROM shelves WHERE id = $1 AND user_id = $2 FOR UPDATE",
+            &[&shelf_id, &user_id],
+        )
+        .await
+    {
+        Ok(Some(_)) => {}
+        Ok(None) => return HttpResponse::NotFound().json("Shelf not found"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Database error");
+        }
+    }
+
+    let state = tx
+        .query_one(
+            "SELECT EXISTS (SELECT 1 FROM books WHERE id = $2) AS book_exists,
+                    EXISTS (SELECT 1 FROM shelf_books WHERE shelf_id = $1 AND book_id = $2) AS on_shelf,
+                    (SELECT COUNT(*) FROM shelf_books WHERE shelf_id = $1)::int AS book_count",
+            &[&shelf_id, &book_id],
+        )
+        .await;
+
+    let book_count: i32 = match state {
+        Ok(row) => {
+            if !row.get::<_, bool>("book_exists") {
+                return HttpResponse::NotFound().json("Book not found");
+            }
+            if row.get::<_, bool>("on_shelf") {
+                return HttpResponse::Conflict().json("Book is already on this shelf");
+            }
+            row.get("book_count")
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Database error");
+        }
+    };
+
+    let position = body.position.unwrap_or(book_count + 1).clamp(1, book_count + 1);
+
+    let result = async move {
+        tx.execute(
+            "UPDATE shelf_books SET position = position + 1 WHERE shelf_id = $1 AND position >= $2",
+            &[&shelf_id, &position],
+        )
+        .await?;
+        tx.execute(
+            "INSERT INTO shelf_books (shelf_id, book_id, position) VALUES ($1, $2, $3)",
+            &[&shelf_id, &book_id, &position],
+        )
+        .await?;
+        tx.execute(
+            "UPDATE shelves SET updated_at = CURRENT_TIMESTAMP WHERE id = $1",
+            &[&shelf_id],
+        )
+        .await?;
+        tx.commit().await
+    }
+    .await;
+
+    if let Err(e) = result {
+        eprintln!("Database error: {}", e);
+        return HttpResponse::InternalServerError().json("Error adding book to shelf");
+    }
+
+    match load_books(&client, shelf_id).await {
+        Ok(books) => HttpResponse::Ok().json(books),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error fetching shelf")
+        }
+    }
+}
+
+#[delete("/{id}/books/{book_id}")]
+pub async fn remove_book(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let shelf_id = match Uuid::parse_str(&path.0) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().json("Invalid shelf ID"),
+    };
+
+    let book_id = match Uuid::parse_str(&path.1) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().json("Invalid book ID"),
+    };
+
+    let mut client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let tx = match client.transaction().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    match tx
+        .query_opt(
+            "SELECT 1 FROM shelves WHERE id = $1 AND user_id = $2 FOR UPDATE",
+            &[&shelf_id, &user_id],
+        )
+        .await
+    {
+        Ok(Some(_)) => {}
+        Ok(None) => return HttpResponse::NotFound().json("Shelf not found"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Database error");
+        }
+    }
+
+    let removed = tx
+        .query_opt(
+            "DELETE FROM shelf_books WHERE shelf_id = $1 AND book_id = $2 RETURNING position",
+            &[&shelf_id, &book_id],
+        )
+        .await;
+
+    let position: i32 = match removed {
+        Ok(Some(row)) => row.get("position"),
+        Ok(None) => return HttpResponse::NotFound().json("Book is not on this shelf"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Error removing book from shelf");
+        }
+    };
+
+    // Close the gap so positions stay 1..n
+    let result = async move {
+        tx.execute(
+            "UPDATE shelf_books SET position = position - 1 WHERE shelf_id = $1 AND position > $2",
+            &[&shelf_id, &position],
+        )
+        .await?;
+        tx.execute(
+            "UPDATE shelves SET updated_at = CURRENT_TIMESTAMP WHERE id = $1",
+            &[&shelf_id],
+        )
+        .await?;
+        tx.commit().await
+    }
+    .await;
+
+    match result {
+        Ok(_) => HttpResponse::Ok().json("Book removed from shelf"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error removing book from shelf")
+        }
+    }
+}
+
+#[put("/{id}/books/order")]
+pub async fn reorder_books(
+    req: HttpRequest,
+    path: web::Path<(String,)>,
+    body: ValidJson<ReorderRequest>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    // Extract token from authorization header
+    let auth_header = match req.headers().get("Authorization") {
+        Some(header) => header,
+        None => return HttpResponse::Unauthorized().json("No authorization header"),
+    };
+
+    let auth_str = match auth_header.to_str() {
+        Ok(str) => str,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid authorization header"),
+    };
+
+    // Check if it's a Bearer token
+    if !auth_str.starts_with("Bearer ") {
+        return HttpResponse::Unauthorized().json("Invalid token format");
+    }
+
+    let token = &auth_str[7..]; // Remove "Bearer " prefix
+
+    // Verify the token
+    let claims = match verify_token(token, &config) {
+        Ok(claims) => claims,
+        Err(_) => return HttpResponse::Unauthorized().json("Invalid token"),
+    };
+
+    // Get user ID from claims
+    let user_id = match Uuid::parse_str(&claims.sub) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().json("Invalid user ID in token"),
+    };
+
+    let shelf_id = match Uuid::parse_str(&path.0) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().json("Invalid shelf ID"),
+    };
+
+    let mut client = match pool.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    let tx = match client.transaction().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json("Database error"),
+    };
+
+    match tx
+        .query_opt(
+            "SELECT 1 FROM shelves WHERE id = $1 AND user_id = $2 FOR UPDATE",
+            &[&shelf_id, &user_id],
+        )
+        .await
+    {
+        Ok(Some(_)) => {}
+        Ok(None) => return HttpResponse::NotFound().json("Shelf not found"),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Database error");
+        }
+    }
+
+    let current: HashSet<Uuid> = match tx
+        .query("SELECT book_id FROM shelf_books WHERE shelf_id = $1", &[&shelf_id])
+        .await
+    {
+        Ok(rows) => rows.iter().map(|row| row.get("book_id")).collect(),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Database error");
+        }
+    };
+
+    // The new order must list every book on the shelf exactly once
+    let requested: HashSet<Uuid> = body.book_ids.iter().copied().collect();
+    if requested.len() != body.book_ids.len() || requested != current {
+        return HttpResponse::BadRequest().json("book_ids must list every book on the shelf exactly once");
+    }
+
+    let result = async move {
+        tx.execute(
+            "UPDATE shelf_books sb
+             SET position = o.position::int
+             FROM unnest($2::uuid[]) WITH ORDINALITY AS o(book_id, position)
+             WHERE sb.shelf_id = $1 AND sb.book_id = o.book_id",
+            &[&shelf_id, &body.book_ids],
+        )
+        .await?;
+        tx.execute(
+            "UPDATE shelves SET updated_at = CURRENT_TIMESTAMP WHERE id = $1",
+            &[&shelf_id],
+        )
+        .await?;
+        tx.commit().await
+    }
+    .await;
+
+    if let Err(e) = result {
+        eprintln!("Database error: {}", e);
+        return HttpResponse::InternalServerError().json("Error reordering shelf");
+    }
+
+    match load_books(&client, shelf_id).await {
+        Ok(books) => HttpResponse::Ok().json(books),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Error fetching shelf")
+        }
+    }
+}
+
+async fn load_books(
+    client: &deadpool_postgres::Client,
+    shelf_id: Uuid,
+) -> Result<Vec<ShelfBook>, tokio_postgres::Error> {
+    let rows = client
+        .query(
+            "SELECT sb.book_id, b.title, b.author, b.cover_image, sb.position, sb.added_at
+             FROM shelf_books sb
+             JOIN books b ON b.id = sb.book_id
+             WHERE sb.shelf_id = $1
+             ORDER BY sb.position",
+            &[&shelf_id],
+        )
+        .await?;
+
+    Ok(rows
+        .iter()
+        .map(|row| ShelfBook {
+            book_id: row.get("book_id"),
+            title: row.get("title"),
+            author: row.get("author"),
+            cover_image: row.get("cover_image"),
+            position: row.get("position"),
+            added_at: row.get("added_at"),
+        })
+        .collect())
+}
+
+// Unguessable token for a public shelf's share link
+fn new_share_token() -> String {
+    Uuid::new_v4().simple().to_string()
+}
\ No newline at end of file
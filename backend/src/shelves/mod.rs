@@ -0,0 +1,35 @@
+pub mod handlers;
+pub mod models;
+
+use actix_web::web;
+use deadpool_postgres::Transaction;
+use uuid::Uuid;
+use models::ShelfKind;
+
+/// Creates the built-in shelves every user starts with.
+pub async fn create_default_shelves(tx: &Transaction<'_>, user_id: Uuid) -> Result<(), tokio_postgres::Error> {
+    for (name, kind) in [("Want to Read", ShelfKind::WantToRead), ("Favorites", ShelfKind::Favorites)] {
+        tx.execute(
+            "INSERT INTO shelves (id, user_id, name, kind) VALUES ($1, $2, $3, $4)",
+            &[&Uuid::new_v4(), &user_id, &name, &kind.to_string()],
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/shelves")
+            .service(handlers::get_shelves)
+            .service(handlers::create_shelf)
+            .service(handlers::get_shared_shelf)
+            .service(handlers::get_shelf)
+            .service(handlers::update_shelf)
+            .service(handlers::delete_shelf)
+            .service(handlers::add_book)
+            .service(handlers::remove_book)
+            .service(handlers::reorder_books)
+    );
+}
\ No newline at end of file
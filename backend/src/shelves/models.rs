@@ -0,0 +1,124 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::validation::not_blank;
+
+// Longest shelf name, in characters
+pub const MAX_NAME_LENGTH: u64 = 100;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ShelfKind {
+    WantToRead,
+    Favorites,
+    Custom,
+}
+
+impl std::fmt::Display for ShelfKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ShelfKind::WantToRead => write!(f, "want_to_read"),
+            ShelfKind::Favorites => write!(f, "favorites"),
+            ShelfKind::Custom => write!(f, "custom"),
+        }
+    }
+}
+
+impl std::str::FromStr for ShelfKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "want_to_read" => Ok(ShelfKind::WantToRead),
+            "favorites" => Ok(ShelfKind::Favorites),
+            "custom" => Ok(ShelfKind::Custom),
+            _ => Err(format!("Unknown shelf kind: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Shelf {
+    pub id: Uuid,
+    pub name: String,
+    pub kind: ShelfKind,
+    pub is_public: bool,
+    pub share_token: Option<String>,
+    pub book_count: i64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Shelf {
+    pub fn from_row(row: &tokio_postgres::Row) -> Self {
+        Shelf {
+            id: row.get("id"),
+            name: row.get("name"),
+            kind: row
+                .get::<_, String>("kind")
+                .parse()
+                .unwrap_or(ShelfKind::Custom),
+            is_public: row.get("is_public"),
+            share_token: row.get("share_token"),
+            book_count: row.get("book_count"),
+            created_at: row.get("created_at"),
+            updated_at: row.get("updated_at"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ShelfBook {
+    pub book_id: Uuid,
+    pub title: String,
+    pub author: String,
+    pub cover_image: String,
+    pub position: i32,
+    pub added_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ShelfDetail {
+    #[serde(flatten)]
+    pub shelf: Shelf,
+    pub books: Vec<ShelfBook>,
+}
+
+// What a visitor following a share link sees
+#[derive(Debug, Serialize)]
+pub struct SharedShelf {
+    pub name: String,
+    pub owner: String,
+    pub books: Vec<ShelfBook>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateShelfRequest {
+    #[validate(custom(function = "not_blank"), length(max = MAX_NAME_LENGTH))]
+    pub name: String,
+    pub is_public: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct UpdateShelfRequest {
+    #[validate(custom(function = "not_blank"), length(max = MAX_NAME_LENGTH))]
+    pub name: Option<String>,
+    pub is_public: Option<bool>,
+    // Replaces the share link, e.g. after it was passed around too widely
+    pub regenerate_link: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct AddBookRequest {
+    pub book_id: String,
+    // 1-based; the book is appended when omitted
+    #[validate(range(min = 1))]
+    pub position: Option<i32>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ReorderRequest {
+    pub book_ids: Vec<Uuid>,
+}
\ No newline at end of file
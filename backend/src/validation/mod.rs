@@ -0,0 +1,270 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::ops::{Deref, DerefMut};
+
+use actix_web::{
+    dev::Payload,
+    error::{JsonPayloadError, QueryPayloadError},
+    http::StatusCode,
+    web, FromRequest, HttpRequest, HttpResponse, ResponseError,
+};
+use futures::future::{ready, LocalBoxFuture, Ready};
+use serde::{de::DeserializeOwned, Serialize};
+use validator::{Validate, ValidationError, ValidationErrors, ValidationErrorsKind};
+
+// Deepest page a paged listing serves, which keeps `(page - 1) * limit` and the
+// OFFSET built from it from overflowing
+pub const MAX_PAGE: usize = 10_000;
+
+/// Problems with a request, collected per field and returned together as 422 so
+/// clients can show each one next to its input:
+///
+/// `{"message": "Validation failed", "errors": {"password": ["Must be at least 8 characters"]}}`
+#[derive(Debug, Serialize)]
+pub struct FieldErrors {
+    message: &'static str,
+    errors: BTreeMap<String, Vec<String>>,
+}
+
+impl FieldErrors {
+    pub fn new() -> Self {
+        FieldErrors {
+            message: "Validation failed",
+            errors: BTreeMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, field: &str, problem: impl Into<String>) {
+        self.errors.entry(field.to_string()).or_default().push(problem.into());
+    }
+
+    pub fn extend(&mut self, field: &str, problems: Vec<String>) {
+        for problem in problems {
+            self.add(field, problem);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// The 422 response, or `None` when nothing was wrong.
+    pub fn response(&self) -> Option<HttpResponse> {
+        if self.is_empty() {
+            return None;
+        }
+
+        Some(HttpResponse::UnprocessableEntity().json(self))
+    }
+
+    // Nested structs and lists are reported as `field.inner` and `field[index]`
+    fn collect(&mut self, prefix: &str, errors: &ValidationErrors) {
+        for (field, kind) in errors.errors() {
+            match kind {
+                ValidationErrorsKind::Field(problems) => {
+                    for problem in problems {
+                        // Checks across fields report against the field named by `field_error`
+                        let field = match field.as_ref() {
+                            "__all__" => problem.params.get("field").and_then(|field| field.as_str()).unwrap_or(""),
+                            field => field,
+                        };
+                        self.add(&join(prefix, field), describe(problem));
+                    }
+                }
+                ValidationErrorsKind::Struct(nested) => self.collect(&join(prefix, field), nested),
+                ValidationErrorsKind::List(items) => {
+                    for (index, nested) in items {
+                        self.collect(&format!("{}[{}]", join(prefix, field), index), nested);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Default for FieldErrors {
+    fn default() -> Self {
+        FieldErrors::new()
+    }
+}
+
+impl From<ValidationErrors> for FieldErrors {
+    fn from(errors: ValidationErrors) -> Self {
+        let mut field_errors = FieldErrors::new();
+        field_errors.collect("", &errors);
+        field_errors
+    }
+}
+
+// One line, e.g. `pages_read: Must be at least 0; timestamp: Must be an RFC 3339 timestamp`
+impl std::fmt::Display for FieldErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let problems: Vec<String> = self
+            .errors
+            .iter()
+            .flat_map(|(field, problems)| problems.iter().map(move |problem| format!("{}: {}", field, problem)))
+            .collect();
+        f.write_str(&problems.join("; "))
+    }
+}
+
+impl ResponseError for FieldErrors {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::UNPROCESSABLE_ENTITY
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::UnprocessableEntity().json(self)
+    }
+}
+
+fn join(prefix: &str, field: &str) -> String {
+    match (prefix.is_empty(), field.is_empty()) {
+        (true, _) => field.to_string(),
+        (false, true) => prefix.to_string(),
+        (false, false) => format!("{}.{}", prefix, field),
+    }
+}
+
+// Message for a failed rule that didn't bring its own
+fn describe(error: &ValidationError) -> String {
+    if let Some(message) = &error.message {
+        return message.to_string();
+    }
+
+    let param = |name: &str| error.params.get(name).map(|value| value.to_string());
+
+    match error.code.as_ref() {
+        "length" => {
+            // Lists are checked for their number of items, text for its characters
+            let unit = match error.params.get("value") {
+                Some(serde_json::Value::Array(_)) => "items",
+                _ => "characters",
+            };
+            match (param("min"), param("max"), param("equal")) {
+                (_, _, Some(equal)) => format!("Must be exactly {} {}", equal, unit),
+                (Some(min), Some(max), _) => format!("Must be between {} and {} {}", min, max, unit),
+                (Some(min), None, _) => format!("Must be at least {} {}", min, unit),
+                (None, Some(max), _) => format!("Must be at most {} {}", max, unit),
+                (None, None, _) => "Has an invalid length".to_string(),
+            }
+        }
+        "range" => match (param("min"), param("max")) {
+            (Some(min), Some(max)) => format!("Must be between {} and {}", min, max),
+            (Some(min), None) => format!("Must be at least {}", min),
+            (None, Some(max)) => format!("Must be at most {}", max),
+            (None, None) => "Is out of range".to_string(),
+        },
+        "email" => "Invalid email address".to_string(),
+        "required" => "Is required".to_string(),
+        _ => "Is invalid".to_string(),
+    }
+}
+
+/// Error of a `schema` check that compares fields, reported against `field`.
+pub fn field_error(field: &'static str, message: &'static str) -> ValidationError {
+    let mut error = ValidationError::new("invalid").with_message(Cow::Borrowed(message));
+    error.add_param(Cow::Borrowed("field"), &field);
+    error
+}
+
+/// Rejects text that is empty or only whitespace.
+pub fn not_blank(value: &str) -> Result<(), ValidationError> {
+    if value.trim().is_empty() {
+        return Err(ValidationError::new("blank").with_message(Cow::Borrowed("Must not be blank")));
+    }
+    Ok(())
+}
+
+/// Accepts IANA time zone names such as `Asia/Jakarta`.
+pub fn known_timezone(value: &str) -> Result<(), ValidationError> {
+    match value.parse::<chrono_tz::Tz>() {
+        Ok(_) => Ok(()),
+        Err(_) => Err(ValidationError::new("timezone").with_message(Cow::Borrowed("Unknown timezone"))),
+    }
+}
+
+/// JSON body that is checked with its `Validate` rules before the handler runs.
+/// Bodies that fail them, or don't fit the type at all, get a 422 with the problems.
+pub struct ValidJson<T>(pub T);
+
+impl<T> Deref for ValidJson<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for ValidJson<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T: DeserializeOwned + Validate + 'static> FromRequest for ValidJson<T> {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let json = web::Json::<T>::from_request(req, payload);
+
+        Box::pin(async move {
+            let value = match json.await {
+                Ok(json) => json.into_inner(),
+                // Wrong content type or an oversized body keep their own status
+                Err(e) => match e.as_error::<JsonPayloadError>() {
+                    Some(JsonPayloadError::Deserialize(problem)) => {
+                        let mut errors = FieldErrors::new();
+                        errors.add("body", problem.to_string());
+                        return Err(errors.into());
+                    }
+                    _ => return Err(e),
+                },
+            };
+
+            value.validate().map_err(FieldErrors::from)?;
+            Ok(ValidJson(value))
+        })
+    }
+}
+
+/// Query string that is checked with its `Validate` rules before the handler runs,
+/// answering like `ValidJson` when it doesn't pass.
+pub struct ValidQuery<T>(pub T);
+
+impl<T> Deref for ValidQuery<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for ValidQuery<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T: DeserializeOwned + Validate> FromRequest for ValidQuery<T> {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let value = match web::Query::<T>::from_query(req.query_string()) {
+            Ok(query) => query.into_inner(),
+            Err(QueryPayloadError::Deserialize(problem)) => {
+                let mut errors = FieldErrors::new();
+                errors.add("query", problem.to_string());
+                return ready(Err(errors.into()));
+            }
+            Err(e) => return ready(Err(e.into())),
+        };
+
+        ready(match value.validate() {
+            Ok(()) => Ok(ValidQuery(value)),
+            Err(errors) => Err(FieldErrors::from(errors).into()),
+        })
+    }
+}
\ No newline at end of file
@@ -0,0 +1,17 @@
+-- Bookmarks and highlights created from the reader.
+-- Changes are pushed to a user's other devices over /reader/sync.
+CREATE TABLE IF NOT EXISTS user_annotations (
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    book_id UUID NOT NULL REFERENCES books(id) ON DELETE CASCADE,
+    kind VARCHAR(20) NOT NULL CHECK (kind IN ('bookmark', 'highlight')),
+    page INTEGER NOT NULL,
+    location TEXT,
+    selected_text TEXT,
+    note TEXT,
+    color VARCHAR(20),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_user_annotations_user_book
+    ON user_annotations (user_id, book_id);
@@ -0,0 +1,22 @@
+-- Explicit reading sessions reported by the reader (start / heartbeat / end).
+-- Sessions without a heartbeat for a while are closed as idle by the backend.
+CREATE TABLE IF NOT EXISTS reading_sessions (
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    book_id UUID NOT NULL REFERENCES books(id) ON DELETE CASCADE,
+    started_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_heartbeat_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    ended_at TIMESTAMPTZ,
+    end_reason VARCHAR(20) CHECK (end_reason IN ('ended', 'idle')),
+    start_page INTEGER NOT NULL,
+    current_page INTEGER NOT NULL,
+    pages_turned INTEGER NOT NULL DEFAULT 0,
+    active_seconds INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE INDEX IF NOT EXISTS idx_reading_sessions_user_started
+    ON reading_sessions (user_id, started_at);
+
+CREATE INDEX IF NOT EXISTS idx_reading_sessions_open
+    ON reading_sessions (last_heartbeat_at)
+    WHERE ended_at IS NULL;
@@ -0,0 +1,30 @@
+-- Append-only log of reading activity as reported by clients and reading sessions.
+-- Events are keyed by a client-supplied id so retried uploads are ignored.
+CREATE TABLE IF NOT EXISTS reading_events (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    event_id UUID NOT NULL,
+    book_id UUID NOT NULL REFERENCES books(id) ON DELETE CASCADE,
+    source VARCHAR(20) NOT NULL CHECK (source IN ('client', 'offline', 'session')),
+    pages_read INTEGER NOT NULL CHECK (pages_read >= 0),
+    reading_time_seconds INTEGER NOT NULL CHECK (reading_time_seconds >= 0),
+    occurred_at TIMESTAMPTZ NOT NULL,
+    received_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (user_id, event_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_reading_events_user_occurred
+    ON reading_events (user_id, occurred_at);
+
+CREATE INDEX IF NOT EXISTS idx_reading_events_received
+    ON reading_events (received_at);
+
+CREATE OR REPLACE FUNCTION reading_events_append_only() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'reading_events is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS reading_events_no_update ON reading_events;
+CREATE TRIGGER reading_events_no_update
+    BEFORE UPDATE ON reading_events
+    FOR EACH ROW EXECUTE FUNCTION reading_events_append_only();
@@ -0,0 +1,46 @@
+-- Daily analytics are bucketed in each user's own timezone and rebuilt from
+-- reading_events by the aggregation job, which also keeps weekly/monthly rollups.
+ALTER TABLE users ADD COLUMN IF NOT EXISTS timezone VARCHAR(64) NOT NULL DEFAULT 'UTC';
+
+CREATE TABLE IF NOT EXISTS user_analytics_summaries (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    period VARCHAR(10) NOT NULL CHECK (period IN ('week', 'month')),
+    period_start DATE NOT NULL,
+    pages_read BIGINT NOT NULL,
+    reading_time_seconds BIGINT NOT NULL,
+    books_count INTEGER NOT NULL,
+    active_days INTEGER NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (user_id, period, period_start)
+);
+
+CREATE TABLE IF NOT EXISTS analytics_aggregation_state (
+    job VARCHAR(50) PRIMARY KEY,
+    processed_until TIMESTAMPTZ NOT NULL
+);
+
+-- Daily rows written before the event log existed become 'legacy' events so the
+-- job can rebuild every day from events alone. Amounts already covered by events
+-- on the same UTC day are subtracted to avoid counting them twice.
+ALTER TABLE reading_events DROP CONSTRAINT IF EXISTS reading_events_source_check;
+ALTER TABLE reading_events ADD CONSTRAINT reading_events_source_check
+    CHECK (source IN ('client', 'offline', 'session', 'legacy'));
+
+INSERT INTO reading_events (user_id, event_id, book_id, source, pages_read, reading_time_seconds, occurred_at)
+SELECT a.user_id,
+       md5(a.user_id::text || a.book_id::text || a.session_date::text)::uuid,
+       a.book_id,
+       'legacy',
+       GREATEST(a.pages_read - COALESCE(e.pages_read, 0), 0),
+       GREATEST(a.reading_time_seconds - COALESCE(e.reading_time_seconds, 0), 0),
+       (a.session_date + TIME '12:00') AT TIME ZONE 'UTC'
+FROM user_analytics a
+LEFT JOIN (
+    SELECT user_id, book_id, (occurred_at AT TIME ZONE 'UTC')::date AS day,
+           SUM(pages_read) AS pages_read, SUM(reading_time_seconds) AS reading_time_seconds
+    FROM reading_events
+    GROUP BY user_id, book_id, (occurred_at AT TIME ZONE 'UTC')::date
+) e ON e.user_id = a.user_id AND e.book_id = a.book_id AND e.day = a.session_date
+WHERE a.pages_read > COALESCE(e.pages_read, 0)
+   OR a.reading_time_seconds > COALESCE(e.reading_time_seconds, 0)
+ON CONFLICT (user_id, event_id) DO NOTHING;
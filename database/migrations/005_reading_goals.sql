@@ -0,0 +1,16 @@
+-- Yearly book goals and daily reading-time goals.
+CREATE TABLE IF NOT EXISTS reading_goals (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    year INTEGER NOT NULL,
+    books_target INTEGER CHECK (books_target > 0),
+    daily_minutes_target INTEGER CHECK (daily_minutes_target > 0),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (user_id, year)
+);
+
+-- When a book was finished, so finished books can be counted per year.
+ALTER TABLE user_reading_progress ADD COLUMN IF NOT EXISTS completed_at TIMESTAMPTZ;
+
+UPDATE user_reading_progress
+SET completed_at = last_read_at
+WHERE completed AND completed_at IS NULL;
@@ -0,0 +1,7 @@
+-- Staff roles. Librarians and admins can see library-wide usage.
+ALTER TABLE users ADD COLUMN IF NOT EXISTS role VARCHAR(20) NOT NULL DEFAULT 'reader'
+    CHECK (role IN ('reader', 'librarian', 'admin'));
+
+-- Dashboard queries scan reading activity by day and progress by book
+CREATE INDEX IF NOT EXISTS idx_user_analytics_session_date ON user_analytics (session_date);
+CREATE INDEX IF NOT EXISTS idx_user_reading_progress_book ON user_reading_progress (book_id);
@@ -0,0 +1,8 @@
+-- Yearly recaps, stored once the year can no longer receive late events.
+CREATE TABLE IF NOT EXISTS yearly_recaps (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    year INTEGER NOT NULL,
+    recap JSONB NOT NULL,
+    generated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (user_id, year)
+);
@@ -0,0 +1,38 @@
+-- Star ratings and text reviews, one per user per book.
+CREATE TABLE IF NOT EXISTS book_reviews (
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    book_id UUID NOT NULL REFERENCES books(id) ON DELETE CASCADE,
+    rating SMALLINT NOT NULL CHECK (rating BETWEEN 1 AND 5),
+    body TEXT,
+    -- Reviews with text wait for a librarian; bare ratings are approved straight away
+    status VARCHAR(20) NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'approved', 'hidden')),
+    helpful_count INTEGER NOT NULL DEFAULT 0,
+    vote_count INTEGER NOT NULL DEFAULT 0,
+    moderated_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    moderated_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (user_id, book_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_book_reviews_book_status ON book_reviews (book_id, status);
+CREATE INDEX IF NOT EXISTS idx_book_reviews_status_created ON book_reviews (status, created_at);
+
+-- "Was this helpful" votes
+CREATE TABLE IF NOT EXISTS review_votes (
+    review_id UUID NOT NULL REFERENCES book_reviews(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    helpful BOOLEAN NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (review_id, user_id)
+);
+
+-- Hidden reviews don't count towards a book's rating
+CREATE OR REPLACE VIEW book_rating_stats AS
+SELECT book_id,
+       AVG(rating)::float8 AS average_rating,
+       COUNT(*) AS rating_count
+FROM book_reviews
+WHERE status <> 'hidden'
+GROUP BY book_id;
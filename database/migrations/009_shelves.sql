@@ -0,0 +1,34 @@
+-- User-defined shelves. Every user has a want-to-read and a favorites shelf;
+-- any number of custom lists can be added.
+CREATE TABLE IF NOT EXISTS shelves (
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name VARCHAR(100) NOT NULL,
+    kind VARCHAR(20) NOT NULL CHECK (kind IN ('want_to_read', 'favorites', 'custom')),
+    is_public BOOLEAN NOT NULL DEFAULT FALSE,
+    -- Set while the shelf is public; clearing it invalidates old links
+    share_token VARCHAR(64) UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (user_id, name)
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_shelves_default_kind
+    ON shelves (user_id, kind) WHERE kind <> 'custom';
+
+CREATE TABLE IF NOT EXISTS shelf_books (
+    shelf_id UUID NOT NULL REFERENCES shelves(id) ON DELETE CASCADE,
+    book_id UUID NOT NULL REFERENCES books(id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    added_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (shelf_id, book_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_shelf_books_position ON shelf_books (shelf_id, position);
+
+-- Default shelves for users who registered before shelves existed
+INSERT INTO shelves (id, user_id, name, kind)
+SELECT gen_random_uuid(), u.id, d.name, d.kind
+FROM users u
+CROSS JOIN (VALUES ('Want to Read', 'want_to_read'), ('Favorites', 'favorites')) AS d(name, kind)
+ON CONFLICT DO NOTHING;
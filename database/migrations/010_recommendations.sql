@@ -0,0 +1,34 @@
+-- Free-form tags used for content similarity
+ALTER TABLE books ADD COLUMN IF NOT EXISTS tags TEXT[] NOT NULL DEFAULT '{}';
+CREATE INDEX IF NOT EXISTS idx_books_tags ON books USING GIN (tags);
+
+-- Precomputed book-to-book similarity, the strongest neighbours per book.
+-- The flags are kept so each recommendation can be explained.
+CREATE TABLE IF NOT EXISTS book_similarities (
+    book_id UUID NOT NULL REFERENCES books(id) ON DELETE CASCADE,
+    similar_book_id UUID NOT NULL REFERENCES books(id) ON DELETE CASCADE,
+    score DOUBLE PRECISION NOT NULL,
+    co_reading_score DOUBLE PRECISION NOT NULL DEFAULT 0,
+    content_score DOUBLE PRECISION NOT NULL DEFAULT 0,
+    co_readers INTEGER NOT NULL DEFAULT 0,
+    same_author BOOLEAN NOT NULL DEFAULT FALSE,
+    same_category BOOLEAN NOT NULL DEFAULT FALSE,
+    shared_tags TEXT[] NOT NULL DEFAULT '{}',
+    computed_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (book_id, similar_book_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_book_similarities_score ON book_similarities (book_id, score DESC);
+
+-- Per-user recommendations, each attributed to the book that contributed most
+CREATE TABLE IF NOT EXISTS user_recommendations (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    book_id UUID NOT NULL REFERENCES books(id) ON DELETE CASCADE,
+    rank INTEGER NOT NULL,
+    score DOUBLE PRECISION NOT NULL,
+    because_book_id UUID REFERENCES books(id) ON DELETE SET NULL,
+    computed_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (user_id, book_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_user_recommendations_rank ON user_recommendations (user_id, rank);
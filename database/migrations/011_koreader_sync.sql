@@ -0,0 +1,29 @@
+-- KOReader identifies documents by a partial MD5 of the file, or by the MD5 of
+-- its file name. The partial hash is filled in by a background job.
+ALTER TABLE books ADD COLUMN IF NOT EXISTS document_hash VARCHAR(32);
+CREATE INDEX IF NOT EXISTS idx_books_document_hash ON books (document_hash);
+CREATE INDEX IF NOT EXISTS idx_books_file_name_hash ON books (md5(regexp_replace(file_path, '^.*/', '')));
+
+-- KOReader sync credentials. KOReader sends md5(password) as its key; only a
+-- bcrypt hash of that key is stored.
+CREATE TABLE IF NOT EXISTS koreader_accounts (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    username VARCHAR(100) NOT NULL UNIQUE,
+    key_hash VARCHAR(255) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Positions exactly as KOReader reported them, including documents that
+-- don't match a book in the library
+CREATE TABLE IF NOT EXISTS koreader_progress (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    document VARCHAR(64) NOT NULL,
+    book_id UUID REFERENCES books(id) ON DELETE SET NULL,
+    progress TEXT NOT NULL,
+    percentage DOUBLE PRECISION NOT NULL,
+    device VARCHAR(100) NOT NULL,
+    device_id VARCHAR(100) NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (user_id, document)
+);
@@ -0,0 +1,10 @@
+-- Profile fields users can edit themselves. A new email address waits in
+-- pending_email until it is verified; the old one stays in use until then.
+ALTER TABLE users ADD COLUMN IF NOT EXISTS pending_email VARCHAR(255);
+ALTER TABLE users ADD COLUMN IF NOT EXISTS avatar_path VARCHAR(255);
+ALTER TABLE users ADD COLUMN IF NOT EXISTS language VARCHAR(10) NOT NULL DEFAULT 'en';
+ALTER TABLE users ADD COLUMN IF NOT EXISTS reader_theme VARCHAR(10) NOT NULL DEFAULT 'light'
+    CHECK (reader_theme IN ('light', 'dark', 'sepia'));
+
+-- Tokens issued before this moment are rejected, e.g. after a password change
+ALTER TABLE users ADD COLUMN IF NOT EXISTS tokens_invalid_before TIMESTAMPTZ;
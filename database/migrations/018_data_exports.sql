@@ -0,0 +1,18 @@
+-- Self-service exports of everything stored about a user. A background job
+-- builds the ZIP file; it is deleted again once the download window closes.
+CREATE TABLE IF NOT EXISTS data_exports (
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    status VARCHAR(20) NOT NULL DEFAULT 'pending'
+        CHECK (status IN ('pending', 'processing', 'ready', 'failed', 'expired')),
+    file_path VARCHAR(255),
+    size_bytes BIGINT,
+    error TEXT,
+    requested_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    started_at TIMESTAMPTZ,
+    completed_at TIMESTAMPTZ,
+    expires_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS idx_data_exports_user ON data_exports (user_id, requested_at DESC);
+CREATE INDEX IF NOT EXISTS idx_data_exports_status ON data_exports (status, requested_at);
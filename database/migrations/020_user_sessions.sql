@@ -0,0 +1,18 @@
+-- One row per sign-in. Access tokens carry the session id in their `sid` claim
+-- and stop working once the session is revoked.
+CREATE TABLE IF NOT EXISTS user_sessions (
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- Given by the client, or derived from the user agent
+    device_name VARCHAR(100),
+    user_agent TEXT,
+    -- Where the session was last used from
+    ip_address VARCHAR(45) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_active_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    -- When the last token issued for the session expires
+    expires_at TIMESTAMPTZ NOT NULL,
+    revoked_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS idx_user_sessions_user ON user_sessions (user_id, last_active_at DESC);
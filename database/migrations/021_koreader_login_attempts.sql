@@ -0,0 +1,4 @@
+-- KOReader sync credentials are throttled and audited like other sign-ins
+ALTER TABLE login_attempts DROP CONSTRAINT IF EXISTS login_attempts_method_check;
+ALTER TABLE login_attempts ADD CONSTRAINT login_attempts_method_check
+    CHECK (method IN ('password', 'mfa', 'basic', 'oidc', 'koreader'));
@@ -0,0 +1,2 @@
+-- Wrong second factors lock 2FA for a while instead of until the next password login
+ALTER TABLE user_mfa ADD COLUMN IF NOT EXISTS last_failed_at TIMESTAMPTZ;
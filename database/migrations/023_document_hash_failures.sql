@@ -0,0 +1,4 @@
+-- Books whose file can't be hashed are retried with a growing delay instead of
+-- being picked again at the head of every batch
+ALTER TABLE books ADD COLUMN IF NOT EXISTS document_hash_failures INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE books ADD COLUMN IF NOT EXISTS document_hash_retry_at TIMESTAMPTZ;